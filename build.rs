@@ -1,18 +1,100 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    // The kernel unpacks the initramfs archive into its root filesystem.
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=initramfs");
+    let ramdisk_path = out_dir.join("initramfs.cpio");
+    fs::write(&ramdisk_path, cpio::archive(Path::new("initramfs"))).unwrap();
+
     let uefi_path = out_dir.join("uefi.img");
     bootloader::UefiBoot::new(&kernel)
+        .set_ramdisk(&ramdisk_path)
         .create_disk_image(&uefi_path)
         .unwrap();
 
     let bios_path = out_dir.join("bios.img");
     bootloader::BiosBoot::new(&kernel)
+        .set_ramdisk(&ramdisk_path)
         .create_disk_image(&bios_path)
         .unwrap();
 
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
-}
\ No newline at end of file
+    println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
+}
+
+/// Writes cpio archives in the `newc` format.
+mod cpio {
+    use std::fs;
+    use std::path::Path;
+
+    const MODE_DIR: u32 = 0o040755;
+    const MODE_FILE: u32 = 0o100644;
+    const MODE_SYMLINK: u32 = 0o120777;
+
+    /// Packs everything below `dir`, with paths relative to it.
+    pub fn archive(dir: &Path) -> Vec<u8> {
+        let mut archive = Archive { data: Vec::new(), next_ino: 1 };
+        if dir.is_dir() {
+            archive.add_dir(dir, "");
+        }
+        archive.add("TRAILER!!!", 0, &[]);
+        archive.data
+    }
+
+    struct Archive {
+        data: Vec<u8>,
+        next_ino: u32,
+    }
+
+    impl Archive {
+        fn add_dir(&mut self, dir: &Path, prefix: &str) {
+            let mut entries: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap()).collect();
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                let name = format!("{}{}", prefix, entry.file_name().to_str().expect("initramfs paths must be UTF-8"));
+                let path = entry.path();
+                let kind = fs::symlink_metadata(&path).unwrap().file_type();
+                if kind.is_symlink() {
+                    let target = fs::read_link(&path).unwrap();
+                    self.add(&name, MODE_SYMLINK, target.to_str().unwrap().as_bytes());
+                } else if kind.is_dir() {
+                    self.add(&name, MODE_DIR, &[]);
+                    self.add_dir(&path, &format!("{}/", name));
+                } else {
+                    self.add(&name, MODE_FILE, &fs::read(&path).unwrap());
+                }
+            }
+        }
+
+        fn add(&mut self, name: &str, mode: u32, contents: &[u8]) {
+            let ino = self.next_ino;
+            self.next_ino += 1;
+            let nlink = if mode == MODE_DIR { 2 } else { 1 };
+            // Magic, then thirteen hex fields: inode, mode, uid, gid, links,
+            // mtime, file size, device major and minor, special device
+            // major and minor, name size and checksum.
+            let fields = [ino, mode, 0, 0, nlink, 0, contents.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+            self.data.extend_from_slice(b"070701");
+            for field in fields {
+                self.data.extend_from_slice(format!("{:08x}", field).as_bytes());
+            }
+            self.data.extend_from_slice(name.as_bytes());
+            self.data.push(0);
+            self.pad();
+            self.data.extend_from_slice(contents);
+            self.pad();
+        }
+
+        /// Names and contents start on four-byte boundaries.
+        fn pad(&mut self) {
+            while self.data.len() % 4 != 0 {
+                self.data.push(0);
+            }
+        }
+    }
+}
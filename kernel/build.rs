@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+fn main() {
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+
+    // Optional boot splash logo (BMP or QOI); an empty file disables it.
+    println!("cargo:rerun-if-env-changed=KERNEL_LOGO");
+    let logo = match std::env::var_os("KERNEL_LOGO") {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", PathBuf::from(&path).display());
+            std::fs::read(path).expect("failed to read KERNEL_LOGO")
+        }
+        None => Vec::new(),
+    };
+    std::fs::write(out_dir.join("logo"), logo).unwrap();
+}
@@ -9,9 +9,15 @@ use x86_64::{
 pub const HEAP_START: *mut u8 = 0x_4444_4444_0000 as *mut u8;
 pub const HEAP_SIZE: u64 = 100 * 1024;
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+/// Returns `(used, size)` of the kernel heap in bytes.
+pub fn heap_stats() -> (usize, usize) {
+    let heap = ALLOCATOR.lock();
+    (heap.used(), heap.size())
+}
+
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
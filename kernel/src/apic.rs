@@ -2,12 +2,16 @@ pub mod ioapic;
 pub mod lapic;
 mod rsdp;
 
-use self::rsdp::Handler;
-use acpi::{AcpiTables, InterruptModel};
+pub use self::rsdp::Handler;
+use acpi::{AcpiError, AcpiTables, InterruptModel};
+use core::sync::atomic::{AtomicU64, Ordering};
 
-pub fn init(rsdp_addr: &u64) {
-    let tables = unsafe { AcpiTables::from_rsdp(Handler, *rsdp_addr as usize).unwrap() };
-    let platform_info = tables.platform_info().unwrap();
+static RSDP_ADDR: AtomicU64 = AtomicU64::new(0);
+
+pub fn init(rsdp_addr: &u64) -> Result<(), AcpiError> {
+    RSDP_ADDR.store(*rsdp_addr, Ordering::Relaxed);
+    let tables = acpi_tables()?;
+    let platform_info = tables.platform_info()?;
     let interrupt_model = platform_info.interrupt_model;
 
     if let InterruptModel::Apic(apic) = interrupt_model {
@@ -15,4 +19,14 @@ pub fn init(rsdp_addr: &u64) {
         self::ioapic::init(apic);
         self::lapic::LAPIC.lock().enable();
     }
+
+    Ok(())
+}
+
+/// Parses the ACPI tables again from the RSDP found at boot.
+pub fn acpi_tables() -> Result<AcpiTables<Handler>, AcpiError> {
+    match RSDP_ADDR.load(Ordering::Relaxed) {
+        0 => Err(AcpiError::NoValidRsdp),
+        rsdp_addr => unsafe { AcpiTables::from_rsdp(Handler, rsdp_addr as usize) },
+    }
 }
@@ -1,32 +1,111 @@
+use crate::interrupts::InterruptIndex;
 use crate::memory;
-use acpi::platform::interrupt::Apic;
+use crate::sync::SpinLock;
+use acpi::platform::interrupt::{Apic, Polarity, TriggerMode};
 use alloc::alloc::Global;
 use alloc::vec::Vec;
-use x2apic::ioapic::{IoApic, IrqMode, RedirectionTableEntry};
+use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
 use x86_64::PhysAddr;
 
 use super::lapic::LAPIC;
 
+/// Legacy ISA IRQs routed at boot.
+const ROUTES: &[(u8, InterruptIndex)] = &[
+    (1, InterruptIndex::Keyboard),
+    (12, InterruptIndex::Mouse),
+];
+
+struct IoApicEntry {
+    gsi_base: u32,
+    /// Number of redirection table entries.
+    entries: u32,
+    ioapic: IoApic,
+}
+
+static IOAPICS: SpinLock<Vec<IoApicEntry>> = SpinLock::named("IOAPICS", Vec::new());
+
+/// The GSI and pin settings of each ISA IRQ. ISA IRQs are edge triggered
+/// and active high on the GSI of the same number, unless the MADT
+/// overrides them, as it usually does for the PIT on IRQ 0.
+static ISA_ROUTES: SpinLock<[(u32, IrqFlags); 16]> = SpinLock::named("ISA_ROUTES", {
+    let mut routes = [(0, IrqFlags::empty()); 16];
+    let mut irq = 0;
+    while irq < 16 {
+        routes[irq].0 = irq as u32;
+        irq += 1;
+    }
+    routes
+});
+
 pub fn init(apic: Apic<Global>) {
-    let mut ioapic_vec: Vec<IoApic> = Vec::new();
+    let mut ioapics = IOAPICS.lock();
 
     for ioapic in apic.io_apics.iter() {
         let virt = memory::phys_to_virt(PhysAddr::new(ioapic.address as u64)).as_u64();
-        ioapic_vec.push(unsafe { IoApic::new(virt) })
+        let mut ioapic_regs = unsafe { IoApic::new(virt) };
+        unsafe { ioapic_regs.init(32) };
+        ioapics.push(IoApicEntry {
+            gsi_base: ioapic.global_system_interrupt_base,
+            entries: unsafe { ioapic_regs.max_table_entry() } as u32 + 1,
+            ioapic: ioapic_regs,
+        });
     }
+    drop(ioapics);
 
-    unsafe {
-        for mut ioapic in ioapic_vec.into_iter() {
-            ioapic.init(32);
+    let mut isa_routes = ISA_ROUTES.lock();
+    for source in apic.interrupt_source_overrides.iter() {
+        let Some(route) = isa_routes.get_mut(source.isa_source as usize) else {
+            continue;
+        };
+        let mut flags = IrqFlags::empty();
+        if matches!(source.polarity, Polarity::ActiveLow) {
+            flags |= IrqFlags::LOW_ACTIVE;
+        }
+        if matches!(source.trigger_mode, TriggerMode::Level) {
+            flags |= IrqFlags::LEVEL_TRIGGERED;
+        }
+        *route = (source.global_system_interrupt, flags);
+    }
+    drop(isa_routes);
 
-            let mut e = RedirectionTableEntry::default();
-            e.set_mode(IrqMode::Fixed);
-            e.set_vector(33);
-            e.set_dest(LAPIC.lock().id() as u8);
+    for &(irq, index) in ROUTES {
+        route_isa(irq, index.as_u8());
+    }
+}
 
-            ioapic.set_table_entry(1, e);
+/// Delivers ISA IRQ `irq` to `vector` on this CPU's LAPIC and unmasks it,
+/// following the MADT's interrupt source overrides.
+pub fn route_isa(irq: u8, vector: u8) {
+    let Some(&(gsi, flags)) = ISA_ROUTES.lock().get(irq as usize) else {
+        return;
+    };
+    set_route(gsi, vector, flags);
+}
 
-            ioapic.enable_irq(1);
-        }
+/// Delivers global system interrupt `gsi` to `vector` on this CPU's LAPIC
+/// and unmasks it, edge triggered and active high.
+pub fn route(gsi: u32, vector: u8) {
+    set_route(gsi, vector, IrqFlags::empty());
+}
+
+fn set_route(gsi: u32, vector: u8, flags: IrqFlags) {
+    let mut ioapics = IOAPICS.lock();
+    let Some(entry) = ioapics
+        .iter_mut()
+        .find(|entry| (entry.gsi_base..entry.gsi_base + entry.entries).contains(&gsi))
+    else {
+        return;
+    };
+    let irq = (gsi - entry.gsi_base) as u8;
+
+    let mut e = RedirectionTableEntry::default();
+    e.set_mode(IrqMode::Fixed);
+    e.set_flags(flags);
+    e.set_vector(vector);
+    e.set_dest(LAPIC.lock().apic_id() as u8);
+
+    unsafe {
+        entry.ioapic.set_table_entry(irq, e);
+        entry.ioapic.enable_irq(irq);
     }
 }
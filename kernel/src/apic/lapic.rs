@@ -1,12 +1,22 @@
 use x2apic::lapic::{LocalApic, LocalApicBuilder};
 use x86_64::instructions::port::Port;
 use x86_64::PhysAddr;
-use spin::Mutex;
+use crate::sync::IrqMutex;
 
-pub static LAPIC: Mutex<LApic> = Mutex::new(LApic { lapic: None });
+/// Shared with interrupt handlers, which signal EOI through it.
+pub static LAPIC: IrqMutex<LApic> = IrqMutex::named(
+    "LAPIC",
+    LApic {
+        lapic: None,
+        x2apic: false,
+    },
+);
 
 pub struct LApic {
     lapic: Option<LocalApic>,
+    /// The x2apic crate switches to x2APIC mode when the CPU has it; xAPIC
+    /// keeps IDs in the top byte of the ID and ICR destination registers.
+    x2apic: bool,
 }
 
 impl LApic {
@@ -45,6 +55,7 @@ impl LApic {
         let apic_virtual_address: u64 =
             crate::memory::phys_to_virt(PhysAddr::new(local_apic_address)).as_u64();
 
+        self.x2apic = core::arch::x86_64::__cpuid(1).ecx & (1 << 21) != 0;
         self.lapic = LocalApicBuilder::default()
             .timer_vector(32)
             .error_vector(51)
@@ -75,4 +86,29 @@ impl LApic {
     pub fn id(&self) -> u32 {
         unsafe { self.lapic.as_ref().unwrap().id() }
     }
+
+    /// This CPU's APIC ID as listed in the MADT.
+    pub fn apic_id(&self) -> u32 {
+        if self.x2apic { self.id() } else { self.id() >> 24 }
+    }
+
+    fn destination(&self, apic_id: u32) -> u32 {
+        if self.x2apic { apic_id } else { apic_id << 24 }
+    }
+
+    pub fn send_ipi(&mut self, vector: u8, apic_id: u32) {
+        let dest = self.destination(apic_id);
+        unsafe { self.lapic.as_mut().unwrap().send_ipi(vector, dest) }
+    }
+
+    pub fn send_init_ipi(&mut self, apic_id: u32) {
+        let dest = self.destination(apic_id);
+        unsafe { self.lapic.as_mut().unwrap().send_init_ipi(dest) }
+    }
+
+    /// Starts the CPU at real-mode address `page << 12`.
+    pub fn send_sipi(&mut self, page: u8, apic_id: u32) {
+        let dest = self.destination(apic_id);
+        unsafe { self.lapic.as_mut().unwrap().send_sipi(page, dest) }
+    }
 }
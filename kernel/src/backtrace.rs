@@ -0,0 +1,56 @@
+//! Return addresses collected by walking frame pointers.
+
+use crate::memory;
+use core::fmt;
+use x86_64::VirtAddr;
+
+const MAX_FRAMES: usize = 16;
+
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Walks the `rbp` chain of the caller. Stops at the first frame
+    /// pointer that is not mapped or does not lead up the stack.
+    #[inline(never)]
+    pub fn capture() -> Backtrace {
+        let mut backtrace = Backtrace {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+        let mut rbp: u64;
+        unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+        while backtrace.len < MAX_FRAMES && rbp != 0 && rbp % 8 == 0 {
+            if !memory::is_mapped(VirtAddr::new_truncate(rbp)) || !memory::is_mapped(VirtAddr::new_truncate(rbp + 8)) {
+                break;
+            }
+            let (next, return_address) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+            if return_address == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = return_address;
+            backtrace.len += 1;
+            if next <= rbp {
+                break;
+            }
+            rbp = next;
+        }
+        backtrace
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, address) in self.frames().iter().enumerate() {
+            writeln!(f, "  #{:<2} {:#018x}", i, address)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Block devices.
+//!
+//! Storage drivers implement [`BlockDevice`] and [`register`] each disk
+//! they find. Registration wraps the device in a [`Disk`], whose request
+//! queue merges adjacent reads and writes from concurrent callers into
+//! single device transfers. Everything above the drivers goes through the
+//! disks in the registry. Each disk's partition table is scanned in the
+//! background, and its partitions are registered as disks too.
+
+pub mod commands;
+mod disk;
+mod partition;
+
+pub use self::disk::{Disk, DiskStats};
+pub use self::partition::{Guid, Partition, PartitionInfo, PartitionType};
+
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The range extends past the end of the device.
+    OutOfRange,
+    /// The buffer is not a whole number of sectors.
+    Unaligned,
+    ReadOnly,
+    Unsupported,
+    NoMemory,
+    /// The device reported an error.
+    Io,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            BlockError::OutOfRange => "sector out of range",
+            BlockError::Unaligned => "buffer is not a multiple of the sector size",
+            BlockError::ReadOnly => "device is read-only",
+            BlockError::Unsupported => "operation not supported",
+            BlockError::NoMemory => "out of DMA memory",
+            BlockError::Io => "I/O error",
+        };
+        f.write_str(message)
+    }
+}
+
+pub type BlockFuture<'a, T = ()> = Pin<Box<dyn Future<Output = Result<T, BlockError>> + Send + 'a>>;
+
+/// A device addressed in fixed-size sectors.
+///
+/// Reads and writes cover `buf.len() / sector_size()` sectors starting at
+/// `sector`; callers check the range with [`check_range`].
+pub trait BlockDevice: Send + Sync {
+    fn sector_size(&self) -> usize;
+
+    /// Size of the device in sectors.
+    fn capacity(&self) -> u64;
+
+    /// Most sectors a single read or write may cover.
+    fn max_sectors(&self) -> usize;
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Where the device sits on its disk, if it is a partition.
+    fn partition(&self) -> Option<&PartitionInfo> {
+        None
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a>;
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a>;
+
+    /// Makes completed writes durable.
+    fn flush(&self) -> BlockFuture<'_>;
+}
+
+/// Checks that `len` bytes at `sector` are whole sectors within `device`
+/// and returns the sector count.
+pub fn check_range(device: &dyn BlockDevice, sector: u64, len: usize) -> Result<u64, BlockError> {
+    let sector_size = device.sector_size();
+    if !len.is_multiple_of(sector_size) {
+        return Err(BlockError::Unaligned);
+    }
+    let count = (len / sector_size) as u64;
+    match sector.checked_add(count) {
+        Some(end) if end <= device.capacity() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+static DISKS: SpinLock<Vec<Arc<Disk>>> = SpinLock::named("disks", Vec::new());
+
+/// Adds a disk under `prefix` followed by the first free letter, e.g.
+/// `vda`, and returns it.
+pub fn register(prefix: &str, device: Arc<dyn BlockDevice>) -> Arc<Disk> {
+    let mut disks = DISKS.lock();
+    let name = (b'a'..=b'z')
+        .map(|letter| alloc::format!("{}{}", prefix, letter as char))
+        .find(|name| disks.iter().all(|disk| disk.name() != name))
+        .unwrap_or_else(|| alloc::format!("{}{}", prefix, disks.len()));
+    let disk = Arc::new(Disk::new(name, device));
+    disks.push(disk.clone());
+    drop(disks);
+    scan(&disk);
+    disk
+}
+
+/// Adds a disk under a name the driver chose, such as `nvme0n1`.
+pub fn register_named(name: String, device: Arc<dyn BlockDevice>) -> Arc<Disk> {
+    let disk = insert(name, device);
+    scan(&disk);
+    disk
+}
+
+/// Adds a disk without looking for partitions on it.
+fn insert(name: String, device: Arc<dyn BlockDevice>) -> Arc<Disk> {
+    let disk = Arc::new(Disk::new(name, device));
+    DISKS.lock().push(disk.clone());
+    disk
+}
+
+fn scan(disk: &Arc<Disk>) {
+    crate::task::spawn("partitions", partition::scan(disk.clone()));
+}
+
+pub fn disks() -> Vec<Arc<Disk>> {
+    DISKS.lock().clone()
+}
+
+pub fn get(name: &str) -> Option<Arc<Disk>> {
+    DISKS.lock().iter().find(|disk| disk.name() == name).cloned()
+}
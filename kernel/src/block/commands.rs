@@ -0,0 +1,86 @@
+//! Shell commands for block devices.
+
+use super::BlockDevice;
+use crate::task::shell::command::{self, Command, CommandError, CommandResult};
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+const COMMANDS: &[Command] = &[
+    Command { name: "lsblk", help: "list block devices", run: lsblk },
+    Command { name: "blkdump", help: "hex dump sectors of a block device", run: blkdump },
+];
+
+pub fn register() {
+    for command in COMMANDS {
+        command::register(*command);
+    }
+}
+
+fn lsblk(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    writeln!(out, "NAME      SIZE  SECTOR  RO  READS  WRITES  TRANSFERS  MERGED  TYPE")?;
+    for disk in super::disks() {
+        let stats = disk.stats();
+        write!(
+            out,
+            "{:6} {:5} MiB  {:6}  {:2}  {:5}  {:6}  {:9}  {:6}",
+            disk.name(),
+            disk.size() >> 20,
+            disk.sector_size(),
+            if disk.read_only() { "y" } else { "n" },
+            stats.reads,
+            stats.writes,
+            stats.transfers,
+            stats.merged
+        )?;
+        match disk.partition() {
+            Some(info) => writeln!(out, "  {}", info.kind)?,
+            None => writeln!(out)?,
+        }
+    }
+    Ok(())
+}
+
+/// Sectors `blkdump` dumps at most; the dump is kept in memory as text.
+const BLKDUMP_MAX_SECTORS: usize = 4;
+
+fn blkdump(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "blkdump <device> [sector] [count]";
+    let (name, sector, count) = match args {
+        [name] => (name, 0, 1),
+        [name, sector] => (name, sector.parse().map_err(|_| CommandError::Usage(USAGE))?, 1),
+        [name, sector, count] => (
+            name,
+            sector.parse().map_err(|_| CommandError::Usage(USAGE))?,
+            count.parse().map_err(|_| CommandError::Usage(USAGE))?,
+        ),
+        _ => return Err(CommandError::Usage(USAGE)),
+    };
+    if count > BLKDUMP_MAX_SECTORS {
+        return Err(CommandError::Failed(format!("at most {} sectors at a time", BLKDUMP_MAX_SECTORS)));
+    }
+    let disk = super::get(name).ok_or_else(|| CommandError::Failed(format!("{}: no such device", name)))?;
+    let sector_size = disk.sector_size();
+    let chunk_sectors = disk.max_sectors().max(1);
+    let mut buf = alloc::vec![0; chunk_sectors.min(count) * sector_size];
+
+    let mut done = 0;
+    while done < count {
+        command::check_interrupt()?;
+        let sectors = chunk_sectors.min(count - done);
+        let chunk = &mut buf[..sectors * sector_size];
+        let start = sector + done as u64;
+        command::block_on(disk.read(start, chunk))?.map_err(|err| CommandError::Failed(format!("{}: {}", name, err)))?;
+        for (i, line) in chunk.chunks(16).enumerate() {
+            let offset = start * sector_size as u64 + i as u64 * 16;
+            write!(out, "{:08x} ", offset)?;
+            for byte in line {
+                write!(out, " {:02x}", byte)?;
+            }
+            let text: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            writeln!(out, "  |{}|", text)?;
+        }
+        done += sectors;
+    }
+    Ok(())
+}
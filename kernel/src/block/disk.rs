@@ -0,0 +1,349 @@
+use super::{check_range, BlockDevice, BlockError, BlockFuture, PartitionInfo};
+use crate::sync::{Semaphore, SpinLock, WaitQueue};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Transfers a disk keeps in flight at once.
+const QUEUE_DEPTH: usize = 4;
+
+/// Bytes a request or merged transfer is staged in on the heap; larger
+/// reads and writes are split.
+const MAX_STAGING: usize = 16 * 1024;
+
+/// Heap bytes all disks together may hold in request and merged transfer
+/// buffers, counted in permits. Requests wait for room; batches that find
+/// none for a merged buffer transfer each request from its own.
+static STAGING: Semaphore = Semaphore::new(2 * MAX_STAGING);
+
+/// A registered block device behind a request queue.
+///
+/// Every read or write is queued. A caller whose request is at the front of
+/// the line dispatches it together with any queued requests in the same
+/// direction that extend it to a contiguous range, and hands each of them
+/// its part of the result.
+pub struct Disk {
+    name: String,
+    device: Arc<dyn BlockDevice>,
+    queue: SpinLock<Queue>,
+    changed: WaitQueue,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskStats {
+    pub reads: u64,
+    pub writes: u64,
+    /// Transfers issued to the device.
+    pub transfers: u64,
+    /// Requests that shared a transfer with others.
+    pub merged: u64,
+}
+
+struct Queue {
+    next_id: u64,
+    pending: Vec<Request>,
+    /// Results waiting to be collected by their submitters.
+    done: BTreeMap<u64, Result<Vec<u8>, BlockError>>,
+    /// Requests in a transfer whose submitter went away.
+    abandoned: BTreeSet<u64>,
+    in_flight: usize,
+    stats: DiskStats,
+}
+
+struct Request {
+    id: u64,
+    write: bool,
+    sector: u64,
+    count: u64,
+    /// Data to write, or the buffer to read into.
+    data: Vec<u8>,
+}
+
+enum Step {
+    Done(Result<Vec<u8>, BlockError>),
+    Dispatch(VecDeque<Request>),
+}
+
+impl Disk {
+    pub(super) fn new(name: String, device: Arc<dyn BlockDevice>) -> Disk {
+        Disk {
+            name,
+            device,
+            queue: SpinLock::new(Queue {
+                next_id: 0,
+                pending: Vec::new(),
+                done: BTreeMap::new(),
+                abandoned: BTreeSet::new(),
+                in_flight: 0,
+                stats: DiskStats::default(),
+            }),
+            changed: WaitQueue::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stats(&self) -> DiskStats {
+        self.queue.lock().stats
+    }
+
+    /// Size of the disk in bytes.
+    pub fn size(&self) -> u64 {
+        self.device.capacity() * self.device.sector_size() as u64
+    }
+
+    /// Queues a transfer and waits for its result, dispatching batches
+    /// while our request is next in line.
+    async fn submit(&self, write: bool, sector: u64, data: Vec<u8>) -> Result<Vec<u8>, BlockError> {
+        let id = {
+            let mut queue = self.queue.lock();
+            let id = queue.next_id;
+            queue.next_id += 1;
+            if write {
+                queue.stats.writes += 1;
+            } else {
+                queue.stats.reads += 1;
+            }
+            let count = (data.len() / self.device.sector_size()) as u64;
+            queue.pending.push(Request { id, write, sector, count, data });
+            id
+        };
+        let mut waiter = Waiter { disk: self, id, finished: false };
+        let max_sectors = self.chunk_sectors() as u64;
+        loop {
+            let step = self.changed.wait_until(|| self.queue.lock().step(id, max_sectors)).await;
+            match step {
+                Step::Done(result) => {
+                    waiter.finished = true;
+                    return result;
+                }
+                Step::Dispatch(batch) => self.dispatch(batch).await,
+            }
+        }
+    }
+
+    async fn dispatch(&self, batch: VecDeque<Request>) {
+        let write = batch[0].write;
+        let sector = batch[0].sector;
+        let mut guard = Batch { disk: self, requests: Some(batch) };
+        let requests = guard.requests.as_mut().unwrap();
+        let len = requests.iter().map(|request| request.data.len()).sum();
+        let staging = match requests.len() {
+            1 => None,
+            _ => STAGING.try_acquire_many(len),
+        };
+        let result = match staging {
+            // A lone request, or one of a batch without room to merge,
+            // transfers straight from or into its own buffer.
+            None => {
+                let mut result = Ok(());
+                for request in requests.iter_mut() {
+                    result = self.transfer(write, request.sector, &mut request.data).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                result
+            }
+            Some(_permit) => {
+                let mut buffer: Vec<u8> = match write {
+                    true => requests.iter().flat_map(|request| request.data.iter().copied()).collect(),
+                    false => vec![0; len],
+                };
+                let result = self.transfer(write, sector, &mut buffer).await;
+                if !write && result.is_ok() {
+                    let mut offset = 0;
+                    for request in requests.iter_mut() {
+                        let len = request.data.len();
+                        request.data.copy_from_slice(&buffer[offset..offset + len]);
+                        offset += len;
+                    }
+                }
+                result
+            }
+        };
+        let batch = guard.requests.take().unwrap();
+
+        let mut queue = self.queue.lock();
+        queue.in_flight -= 1;
+        queue.stats.transfers += 1;
+        if batch.len() > 1 {
+            queue.stats.merged += batch.len() as u64;
+        }
+        for request in batch {
+            if !queue.abandoned.remove(&request.id) {
+                queue.done.insert(request.id, result.map(|()| request.data));
+            }
+        }
+        drop(queue);
+        self.changed.wake_all();
+    }
+
+    async fn transfer(&self, write: bool, sector: u64, data: &mut [u8]) -> Result<(), BlockError> {
+        match write {
+            true => self.device.write(sector, data).await,
+            false => self.device.read(sector, data).await,
+        }
+    }
+
+    /// Most sectors a single request or merged transfer covers: what the
+    /// device takes at once, but no more than [`MAX_STAGING`] bytes.
+    fn chunk_sectors(&self) -> usize {
+        self.device.max_sectors().min(MAX_STAGING / self.device.sector_size()).max(1)
+    }
+
+    /// The device behind the queue.
+    pub(super) fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+}
+
+impl Queue {
+    fn step(&mut self, id: u64, max_sectors: u64) -> Option<Step> {
+        if let Some(result) = self.done.remove(&id) {
+            return Some(Step::Done(result));
+        }
+        if self.in_flight >= QUEUE_DEPTH {
+            return None;
+        }
+        // Not pending and not done: another caller is transferring it.
+        let index = self.pending.iter().position(|request| request.id == id)?;
+        self.in_flight += 1;
+        Some(Step::Dispatch(self.take_batch(index, max_sectors)))
+    }
+
+    /// Removes the request at `index` from the queue, together with queued
+    /// requests that extend it on either side.
+    fn take_batch(&mut self, index: usize, max_sectors: u64) -> VecDeque<Request> {
+        let first = self.pending.remove(index);
+        let write = first.write;
+        let mut start = first.sector;
+        let mut end = first.sector + first.count;
+        let mut batch = VecDeque::from([first]);
+        while let Some(index) = self.pending.iter().position(|request| {
+            request.write == write
+                && (request.sector == end || request.sector + request.count == start)
+                && end - start + request.count <= max_sectors
+        }) {
+            let request = self.pending.remove(index);
+            if request.sector == end {
+                end += request.count;
+                batch.push_back(request);
+            } else {
+                start = request.sector;
+                batch.push_front(request);
+            }
+        }
+        batch
+    }
+}
+
+/// Cleans up after a submitter that stops waiting early.
+struct Waiter<'a> {
+    disk: &'a Disk,
+    id: u64,
+    finished: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let mut queue = self.disk.queue.lock();
+        if queue.done.remove(&self.id).is_some() {
+            return;
+        }
+        match queue.pending.iter().position(|request| request.id == self.id) {
+            Some(index) => {
+                queue.pending.remove(index);
+            }
+            None => {
+                queue.abandoned.insert(self.id);
+            }
+        }
+    }
+}
+
+/// Requeues a batch whose dispatcher went away before the transfer ended,
+/// so the other submitters in it are not stranded.
+struct Batch<'a> {
+    disk: &'a Disk,
+    requests: Option<VecDeque<Request>>,
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        let Some(requests) = self.requests.take() else {
+            return;
+        };
+        let mut queue = self.disk.queue.lock();
+        queue.in_flight -= 1;
+        for request in requests {
+            if !queue.abandoned.remove(&request.id) {
+                queue.pending.push(request);
+            }
+        }
+        drop(queue);
+        self.disk.changed.wake_all();
+    }
+}
+
+impl BlockDevice for Disk {
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.device.capacity()
+    }
+
+    fn max_sectors(&self) -> usize {
+        self.device.max_sectors()
+    }
+
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    fn partition(&self) -> Option<&PartitionInfo> {
+        self.device.partition()
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            let sectors_per_chunk = self.chunk_sectors();
+            for (i, chunk) in buf.chunks_mut(sectors_per_chunk * self.sector_size()).enumerate() {
+                let _staging = STAGING.acquire_many(chunk.len()).await;
+                let data = self.submit(false, sector + (i * sectors_per_chunk) as u64, vec![0; chunk.len()]).await?;
+                chunk.copy_from_slice(&data);
+            }
+            Ok(())
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            if self.read_only() {
+                return Err(BlockError::ReadOnly);
+            }
+            check_range(self, sector, buf.len())?;
+            let sectors_per_chunk = self.chunk_sectors();
+            for (i, chunk) in buf.chunks(sectors_per_chunk * self.sector_size()).enumerate() {
+                let _staging = STAGING.acquire_many(chunk.len()).await;
+                self.submit(true, sector + (i * sectors_per_chunk) as u64, chunk.to_vec()).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        self.device.flush()
+    }
+}
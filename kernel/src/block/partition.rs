@@ -0,0 +1,161 @@
+//! Partition tables.
+//!
+//! Every disk is scanned once it is registered. A protective MBR entry
+//! means GPT, read from the primary header or, if that fails its checks,
+//! the backup at the end of the disk; otherwise the MBR partitions are
+//! used, including the logical ones in extended partitions. Each partition
+//! is registered as a disk of its own, named after its disk plus its
+//! number, such as `vda1` or `nvme0n1p1`.
+
+mod gpt;
+mod mbr;
+
+use super::{check_range, BlockDevice, BlockError, BlockFuture, Disk};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A GPT GUID, stored in its on-disk byte order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    /// The first three fields are little-endian on disk.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PartitionType {
+    Gpt { type_guid: Guid, guid: Guid, label: String, attributes: u64 },
+    Mbr { system_id: u8, bootable: bool },
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionType::Gpt { type_guid, label, .. } => write!(f, "{} \"{}\"", type_guid, label),
+            PartitionType::Mbr { system_id, bootable } => {
+                write!(f, "type {:#04x}{}", system_id, if *bootable { " boot" } else { "" })
+            }
+        }
+    }
+}
+
+/// Where a partition sits on its disk, in sectors of the disk.
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    pub number: usize,
+    pub start: u64,
+    pub sectors: u64,
+    pub kind: PartitionType,
+}
+
+/// A range of a disk, usable as a block device of its own.
+///
+/// Transfers go straight to the disk's device: the partition is registered
+/// as a disk with a request queue of its own, so a second one would only
+/// stage the data twice.
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    info: PartitionInfo,
+}
+
+impl BlockDevice for Partition {
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.info.sectors
+    }
+
+    fn max_sectors(&self) -> usize {
+        self.device.max_sectors()
+    }
+
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    fn partition(&self) -> Option<&PartitionInfo> {
+        Some(&self.info)
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            self.device.read(self.info.start + sector, buf).await
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            check_range(self, sector, buf.len())?;
+            self.device.write(self.info.start + sector, buf).await
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        self.device.flush()
+    }
+}
+
+/// Reads the partition table of `disk` and registers its partitions.
+pub(super) async fn scan(disk: Arc<Disk>) {
+    let partitions = match read_table(&disk).await {
+        Ok(partitions) => partitions,
+        Err(err) => {
+            crate::println!("{}: cannot read partition table: {}", disk.name(), err);
+            return;
+        }
+    };
+    let separator = if disk.name().ends_with(|c: char| c.is_ascii_digit()) { "p" } else { "" };
+    for info in partitions {
+        if info.sectors == 0 || info.start.checked_add(info.sectors).is_none_or(|end| end > disk.capacity()) {
+            crate::println!("{}: partition {} lies outside the disk", disk.name(), info.number);
+            continue;
+        }
+        let name = format!("{}{}{}", disk.name(), separator, info.number);
+        super::insert(name, Arc::new(Partition { device: disk.device().clone(), info }));
+    }
+}
+
+async fn read_table(disk: &Disk) -> Result<Vec<PartitionInfo>, BlockError> {
+    let mbr = read_sectors(disk, 0, 1).await?;
+    if !mbr::is_valid(&mbr) {
+        return Ok(Vec::new());
+    }
+    if mbr::is_protective(&mbr) {
+        return gpt::read(disk).await;
+    }
+    mbr::read(disk, &mbr).await
+}
+
+/// Reads `count` sectors starting at `sector`.
+async fn read_sectors(disk: &Disk, sector: u64, count: usize) -> Result<Vec<u8>, BlockError> {
+    let mut buf = vec![0; count * disk.sector_size()];
+    disk.read(sector, &mut buf).await?;
+    Ok(buf)
+}
@@ -0,0 +1,155 @@
+use super::{read_sectors, Guid, PartitionInfo, PartitionType};
+use crate::block::{BlockDevice, BlockError, Disk};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const PRIMARY_LBA: u64 = 1;
+const MIN_HEADER_SIZE: usize = 92;
+/// Entries are 128 bytes, or a larger power of two up to this.
+const MIN_ENTRY_SIZE: usize = 128;
+const MAX_ENTRY_SIZE: usize = 512;
+/// Entry arrays larger than this are rejected rather than read; the usual
+/// array of 128 entries is exactly this size.
+const MAX_ENTRIES_LEN: usize = 16 * 1024;
+const NAME_UNITS: usize = 36;
+
+struct Header {
+    alternate_lba: u64,
+    entries_lba: u64,
+    num_entries: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+/// Reads the primary GPT, or the backup if the primary is damaged.
+pub(super) async fn read(disk: &Disk) -> Result<Vec<PartitionInfo>, BlockError> {
+    let sector = read_sectors(disk, PRIMARY_LBA, 1).await?;
+    let primary = parse_header(&sector, PRIMARY_LBA);
+    if let Some(header) = &primary
+        && let Some(partitions) = read_entries(disk, header).await?
+    {
+        return Ok(partitions);
+    }
+    crate::println!("{}: primary GPT is damaged, using the backup", disk.name());
+    let backup_lba = primary
+        .map(|header| header.alternate_lba)
+        .filter(|&lba| lba > PRIMARY_LBA && lba < disk.capacity())
+        .or(disk.capacity().checked_sub(1).filter(|&lba| lba > PRIMARY_LBA));
+    let Some(backup_lba) = backup_lba else {
+        crate::println!("{}: disk is too small for a backup GPT", disk.name());
+        return Err(BlockError::Io);
+    };
+    let sector = read_sectors(disk, backup_lba, 1).await?;
+    if let Some(header) = parse_header(&sector, backup_lba)
+        && let Some(partitions) = read_entries(disk, &header).await?
+    {
+        return Ok(partitions);
+    }
+    crate::println!("{}: backup GPT is damaged too", disk.name());
+    Err(BlockError::Io)
+}
+
+/// Checks the header in `sector`, which was read from `lba`.
+fn parse_header(sector: &[u8], lba: u64) -> Option<Header> {
+    if &sector[0..8] != SIGNATURE {
+        return None;
+    }
+    let header_size = u32_at(sector, 12) as usize;
+    if header_size < MIN_HEADER_SIZE || header_size > sector.len() {
+        return None;
+    }
+    let mut header = sector[..header_size].to_vec();
+    header[16..20].fill(0);
+    if crc32(&header) != u32_at(sector, 16) || u64_at(sector, 24) != lba {
+        return None;
+    }
+    let header = Header {
+        alternate_lba: u64_at(sector, 32),
+        entries_lba: u64_at(sector, 72),
+        num_entries: u32_at(sector, 80) as usize,
+        entry_size: u32_at(sector, 84) as usize,
+        entries_crc: u32_at(sector, 88),
+    };
+    let valid_entries = (MIN_ENTRY_SIZE..=MAX_ENTRY_SIZE).contains(&header.entry_size)
+        && header.entry_size.is_power_of_two()
+        && header.num_entries <= MAX_ENTRIES_LEN / header.entry_size;
+    valid_entries.then_some(header)
+}
+
+/// Reads the entry array `header` points to; `None` if its checksum does
+/// not match.
+async fn read_entries(disk: &Disk, header: &Header) -> Result<Option<Vec<PartitionInfo>>, BlockError> {
+    let len = header.num_entries * header.entry_size;
+    let sectors = len.div_ceil(disk.sector_size());
+    if header.entries_lba.checked_add(sectors as u64).is_none_or(|end| end > disk.capacity()) {
+        return Ok(None);
+    }
+    let array = read_sectors(disk, header.entries_lba, sectors).await?;
+    if crc32(&array[..len]) != header.entries_crc {
+        return Ok(None);
+    }
+    let partitions = array[..len]
+        .chunks(header.entry_size)
+        .enumerate()
+        .filter_map(|(index, entry)| parse_entry(index + 1, entry))
+        .collect();
+    Ok(Some(partitions))
+}
+
+fn parse_entry(number: usize, entry: &[u8]) -> Option<PartitionInfo> {
+    let type_guid = Guid(entry[0..16].try_into().unwrap());
+    if type_guid.is_zero() {
+        return None;
+    }
+    let first = u64_at(entry, 32);
+    let last = u64_at(entry, 40);
+    if last < first {
+        return None;
+    }
+    let sectors = last.checked_add(1)? - first;
+    let units = (0..NAME_UNITS).map(|i| u16::from_le_bytes([entry[56 + 2 * i], entry[57 + 2 * i]]));
+    let label: String = char::decode_utf16(units.take_while(|&unit| unit != 0))
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    Some(PartitionInfo {
+        number,
+        start: first,
+        sectors,
+        kind: PartitionType::Gpt {
+            type_guid,
+            guid: Guid(entry[16..32].try_into().unwrap()),
+            label,
+            attributes: u64_at(entry, 48),
+        },
+    })
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC-32 as used by GPT (IEEE 802.3, reflected).
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+static CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
@@ -0,0 +1,92 @@
+use super::{read_sectors, PartitionInfo, PartitionType};
+use crate::block::{BlockError, Disk};
+use alloc::vec::Vec;
+
+const SIGNATURE_OFFSET: usize = 510;
+const ENTRIES_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+
+const TYPE_EMPTY: u8 = 0x00;
+const TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// Logical partitions are numbered from 5, after the primary slots.
+const FIRST_LOGICAL: usize = 5;
+/// Bounds the extended partition chain, which could loop.
+const MAX_LOGICAL: usize = 128;
+
+struct Entry {
+    bootable: bool,
+    system_id: u8,
+    start: u64,
+    sectors: u64,
+}
+
+fn entries(sector: &[u8]) -> impl Iterator<Item = Entry> + '_ {
+    sector[ENTRIES_OFFSET..SIGNATURE_OFFSET].chunks(ENTRY_SIZE).map(|entry| Entry {
+        bootable: entry[0] & 0x80 != 0,
+        system_id: entry[4],
+        start: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+        sectors: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64,
+    })
+}
+
+pub(super) fn is_valid(sector: &[u8]) -> bool {
+    sector[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 2] == [0x55, 0xaa]
+}
+
+pub(super) fn is_protective(sector: &[u8]) -> bool {
+    entries(sector).any(|entry| entry.system_id == TYPE_GPT_PROTECTIVE)
+}
+
+pub(super) async fn read(disk: &Disk, mbr: &[u8]) -> Result<Vec<PartitionInfo>, BlockError> {
+    let mut partitions = Vec::new();
+    let mut extended = None;
+    for (index, entry) in entries(mbr).enumerate() {
+        if entry.system_id == TYPE_EMPTY {
+            continue;
+        }
+        if EXTENDED_TYPES.contains(&entry.system_id) {
+            extended.get_or_insert(entry.start);
+            continue;
+        }
+        partitions.push(info(index + 1, 0, &entry));
+    }
+
+    // Each extended boot record describes one logical partition, relative
+    // to itself, and links to the next record, relative to the start of
+    // the extended partition.
+    if let Some(base) = extended {
+        let mut record = base;
+        for number in FIRST_LOGICAL..FIRST_LOGICAL + MAX_LOGICAL {
+            let sector = read_sectors(disk, record, 1).await?;
+            if !is_valid(&sector) {
+                break;
+            }
+            let mut entries = entries(&sector);
+            let (Some(logical), Some(next)) = (entries.next(), entries.next()) else {
+                break;
+            };
+            if logical.system_id != TYPE_EMPTY {
+                partitions.push(info(number, record, &logical));
+            }
+            if !EXTENDED_TYPES.contains(&next.system_id) || next.start == 0 {
+                break;
+            }
+            record = base + next.start;
+        }
+    }
+    Ok(partitions)
+}
+
+fn info(number: usize, base: u64, entry: &Entry) -> PartitionInfo {
+    PartitionInfo {
+        number,
+        start: base + entry.start,
+        sectors: entry.sectors,
+        kind: PartitionType::Mbr {
+            system_id: entry.system_id,
+            bootable: entry.bootable,
+        },
+    }
+}
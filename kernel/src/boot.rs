@@ -0,0 +1,75 @@
+//! Boot progress display.
+//!
+//! Every stage of [`crate::init`] is logged with its status and duration,
+//! below an optional splash logo, so a hang shows which stage it is in.
+//! Set `boot.splash=off` on the kernel command line for a plain text log.
+
+use crate::framebuffer::image::Image;
+use crate::framebuffer::writer::{Color, FRAMEBUFFER};
+use crate::{cmdline, print, println, time};
+use core::fmt::Debug;
+
+static LOGO: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/logo"));
+
+const LOGO_MARGIN: usize = 16;
+
+/// Draws the logo centered at the top of the screen and moves the boot log
+/// below it. Falls back to plain text if there is no usable logo.
+pub fn splash() {
+    if LOGO.is_empty() || cmdline::get("boot.splash") == Some("off") {
+        return;
+    }
+
+    let image = match Image::parse(LOGO) {
+        Ok(image) => image,
+        Err(err) => {
+            println!("boot: logo not shown: {:?}", err);
+            return;
+        }
+    };
+
+    let mut framebuffer = FRAMEBUFFER.lock();
+    if image.height() + 2 * LOGO_MARGIN > framebuffer.height() / 2 {
+        return;
+    }
+    if framebuffer.draw_image_centered(&image, LOGO_MARGIN).is_ok() {
+        framebuffer.move_to_row(image.height() + 2 * LOGO_MARGIN);
+    }
+}
+
+/// Runs an infallible init stage.
+pub fn stage<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    try_stage(name, || Ok::<T, core::convert::Infallible>(f()))
+}
+
+/// Runs an init stage, panicking with the stage name if it fails.
+pub fn try_stage<T, E: Debug>(name: &str, f: impl FnOnce() -> Result<T, E>) -> T {
+    status(Color::GRAY, " .. ");
+    print!(" {}", name);
+
+    let start = time::tsc();
+    let result = f();
+    let elapsed = time::cycles_to_duration(time::tsc() - start);
+
+    print!("\r");
+    match result {
+        Ok(value) => {
+            status(Color::GREEN, " OK ");
+            println!(" {} ({}.{:03} ms)", name, elapsed.as_millis(), elapsed.as_micros() % 1000);
+            value
+        }
+        Err(err) => {
+            status(Color::RED, "FAIL");
+            println!(" {}", name);
+            panic!("boot stage `{}` failed: {:?}", name, err);
+        }
+    }
+}
+
+fn status(color: Color, text: &str) {
+    print!("[");
+    FRAMEBUFFER.lock().set_color(color);
+    print!("{}", text);
+    FRAMEBUFFER.lock().set_color(Color::WHITE);
+    print!("]");
+}
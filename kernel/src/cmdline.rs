@@ -0,0 +1,24 @@
+//! Kernel command line.
+//!
+//! The bootloader does not hand us a command line, so it is baked in at build
+//! time from the `KERNEL_CMDLINE` environment variable, e.g.
+//! `KERNEL_CMDLINE="font.size=16" cargo run`.
+
+const CMDLINE: &str = match option_env!("KERNEL_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+pub fn raw() -> &'static str {
+    CMDLINE
+}
+
+/// Looks up `key` among the whitespace separated `key=value` options.
+///
+/// A bare `key` without `=` yields an empty value.
+pub fn get(key: &str) -> Option<&'static str> {
+    CMDLINE.split_whitespace().find_map(|option| {
+        let (k, v) = option.split_once('=').unwrap_or((option, ""));
+        (k == key).then_some(v)
+    })
+}
@@ -0,0 +1,119 @@
+//! CPU identification via CPUID.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+pub struct CpuInfo {
+    pub vendor: String,
+    pub brand: Option<String>,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    pub logical_cpus: u32,
+    pub features: Vec<&'static str>,
+}
+
+/// Leaf 1 EDX and ECX, and leaf 7 EBX feature bits worth reporting.
+const LEAF1_EDX: &[(u32, &str)] = &[
+    (0, "fpu"),
+    (4, "tsc"),
+    (5, "msr"),
+    (6, "pae"),
+    (8, "cx8"),
+    (9, "apic"),
+    (15, "cmov"),
+    (19, "clflush"),
+    (23, "mmx"),
+    (24, "fxsr"),
+    (25, "sse"),
+    (26, "sse2"),
+    (28, "htt"),
+];
+const LEAF1_ECX: &[(u32, &str)] = &[
+    (0, "sse3"),
+    (1, "pclmulqdq"),
+    (5, "vmx"),
+    (9, "ssse3"),
+    (12, "fma"),
+    (13, "cx16"),
+    (19, "sse4.1"),
+    (20, "sse4.2"),
+    (21, "x2apic"),
+    (23, "popcnt"),
+    (24, "tsc-deadline"),
+    (25, "aes"),
+    (26, "xsave"),
+    (28, "avx"),
+    (30, "rdrand"),
+    (31, "hypervisor"),
+];
+const LEAF7_EBX: &[(u32, &str)] = &[
+    (0, "fsgsbase"),
+    (3, "bmi1"),
+    (5, "avx2"),
+    (7, "smep"),
+    (8, "bmi2"),
+    (16, "avx512f"),
+    (18, "rdseed"),
+    (20, "smap"),
+];
+
+pub fn info() -> CpuInfo {
+    let leaf0 = __cpuid(0);
+    let max_leaf = leaf0.eax;
+    let vendor = [leaf0.ebx, leaf0.edx, leaf0.ecx]
+        .iter()
+        .flat_map(|reg| reg.to_le_bytes())
+        .map(char::from)
+        .collect();
+
+    let leaf1 = __cpuid(1);
+    let base_family = (leaf1.eax >> 8) & 0xf;
+    let base_model = (leaf1.eax >> 4) & 0xf;
+    let family = match base_family {
+        0xf => base_family + ((leaf1.eax >> 20) & 0xff),
+        _ => base_family,
+    };
+    let model = match base_family {
+        0x6 | 0xf => base_model | (((leaf1.eax >> 16) & 0xf) << 4),
+        _ => base_model,
+    };
+
+    let mut features = Vec::new();
+    let mut collect = |reg: u32, bits: &[(u32, &'static str)]| {
+        for &(bit, name) in bits {
+            if reg & (1 << bit) != 0 {
+                features.push(name);
+            }
+        }
+    };
+    collect(leaf1.edx, LEAF1_EDX);
+    collect(leaf1.ecx, LEAF1_ECX);
+    if max_leaf >= 7 {
+        collect(__cpuid_count(7, 0).ebx, LEAF7_EBX);
+    }
+
+    CpuInfo {
+        vendor,
+        brand: brand(),
+        family,
+        model,
+        stepping: leaf1.eax & 0xf,
+        logical_cpus: (leaf1.ebx >> 16) & 0xff,
+        features,
+    }
+}
+
+fn brand() -> Option<String> {
+    if __cpuid(0x8000_0000).eax < 0x8000_0004 {
+        return None;
+    }
+    let bytes: Vec<u8> = (0x8000_0002..=0x8000_0004)
+        .map(__cpuid)
+        .flat_map(|r| [r.eax, r.ebx, r.ecx, r.edx])
+        .flat_map(|reg| reg.to_le_bytes())
+        .take_while(|&b| b != 0)
+        .collect();
+    Some(String::from_utf8_lossy(&bytes).trim().into())
+}
@@ -0,0 +1,16 @@
+pub mod ahci;
+pub mod ata;
+pub mod dma;
+pub mod i8042;
+pub mod ide;
+pub mod mouse;
+pub mod nvme;
+pub mod virtio;
+
+/// Registers the PCI drivers, binding them to the functions found so far.
+pub fn register() {
+    crate::pci::driver::register(&virtio::blk::DRIVER);
+    crate::pci::driver::register(&ahci::DRIVER);
+    crate::pci::driver::register(&nvme::DRIVER);
+    crate::pci::driver::register(&ide::DRIVER);
+}
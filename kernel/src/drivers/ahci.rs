@@ -0,0 +1,137 @@
+//! AHCI SATA host bus adapters.
+//!
+//! The probe takes the HBA from the firmware, starts every port that has a
+//! SATA disk attached, identifies the disk with a polled command and then
+//! switches to MSI completion. Each disk is registered as `sda`, `sdb` and
+//! so on.
+
+mod port;
+
+use self::port::Port;
+use crate::block;
+use crate::memory;
+use crate::pci::driver::{Driver, Match, ProbeResult};
+use crate::pci::{Bar, PciFunction, COMMAND_BUS_MASTER, COMMAND_MEMORY};
+use crate::task::{self, Priority};
+use crate::time;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+use x86_64::VirtAddr;
+
+pub static DRIVER: Driver = Driver {
+    name: "ahci",
+    matches: &[Match::class_prog_if(0x01, 0x06, 0x01)],
+    probe,
+};
+
+/// BAR holding the HBA registers.
+const ABAR: usize = 5;
+
+// Generic host control registers.
+const CAP: u64 = 0x00;
+const GHC: u64 = 0x04;
+const IS: u64 = 0x08;
+const PI: u64 = 0x0c;
+const CAP2: u64 = 0x24;
+const BOHC: u64 = 0x28;
+
+const CAP_S64A: u32 = 1 << 31;
+const GHC_AE: u32 = 1 << 31;
+const GHC_IE: u32 = 1 << 1;
+const CAP2_BOH: u32 = 1 << 0;
+const BOHC_BOS: u32 = 1 << 0;
+const BOHC_OOS: u32 = 1 << 1;
+
+const PORT_BASE: u64 = 0x100;
+const PORT_SIZE: u64 = 0x80;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+struct Hba {
+    regs: VirtAddr,
+    ports: Vec<Arc<Port>>,
+}
+
+fn probe(function: &PciFunction) -> ProbeResult {
+    let Some(Bar::Memory { address, .. }) = function.bars[ABAR] else {
+        return Err("ABAR is not a memory BAR");
+    };
+    let regs = memory::phys_to_virt(address);
+    if !memory::is_mapped(regs) {
+        return Err("ABAR is not mapped");
+    }
+    function.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER);
+
+    let cap = read(regs, CAP);
+    if cap & CAP_S64A == 0 {
+        return Err("no 64-bit DMA");
+    }
+    if read(regs, CAP2) & CAP2_BOH != 0 {
+        write(regs, BOHC, read(regs, BOHC) | BOHC_OOS);
+        if !wait_for(|| read(regs, BOHC) & BOHC_BOS == 0) {
+            return Err("firmware did not release the HBA");
+        }
+    }
+    write(regs, GHC, read(regs, GHC) | GHC_AE);
+    write(regs, GHC, read(regs, GHC) & !GHC_IE);
+
+    let slots = ((cap >> 8) & 0x1f) as usize + 1;
+    let implemented = read(regs, PI);
+    let mut ports = Vec::new();
+    for number in (0..32).filter(|number| implemented & (1 << number) != 0) {
+        let port_regs = regs + PORT_BASE + number as u64 * PORT_SIZE;
+        match Port::new(port_regs, number, slots) {
+            Ok(Some(port)) => ports.push(Arc::new(port)),
+            Ok(None) => {}
+            Err(err) => crate::println!("ahci {} port {}: {}", function.address, number, err),
+        }
+    }
+
+    let hba = Arc::new(Hba { regs, ports });
+    // The HBA lives as long as the kernel, so the handler's pointer stays
+    // valid.
+    let data = Arc::into_raw(hba.clone()) as usize;
+    function.msi().and_then(|msi| msi.route("ahci", interrupt, data, 0)).map_err(|_| "no MSI")?;
+    write(regs, IS, u32::MAX);
+    for port in &hba.ports {
+        port.enable_interrupts();
+    }
+    write(regs, GHC, read(regs, GHC) | GHC_IE);
+
+    for port in &hba.ports {
+        let recovering = port.clone();
+        task::spawn_with_priority("ahci-recover", Priority::BottomHalf, async move { recovering.recover().await });
+        block::register("sd", port.clone());
+    }
+    Ok(())
+}
+
+fn interrupt(data: usize) {
+    let hba = unsafe { &*(data as *const Hba) };
+    let pending = read(hba.regs, IS);
+    for port in hba.ports.iter().filter(|port| pending & (1 << port.number()) != 0) {
+        port.interrupt();
+    }
+    write(hba.regs, IS, pending);
+}
+
+fn read(regs: VirtAddr, register: u64) -> u32 {
+    unsafe { (regs + register).as_ptr::<u32>().read_volatile() }
+}
+
+fn write(regs: VirtAddr, register: u64, value: u32) {
+    unsafe { (regs + register).as_mut_ptr::<u32>().write_volatile(value) }
+}
+
+/// Spins until `condition` holds; `false` on timeout.
+fn wait_for(condition: impl Fn() -> bool) -> bool {
+    let start = time::tsc();
+    while !condition() {
+        if time::cycles_to_duration(time::tsc() - start) > TIMEOUT {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
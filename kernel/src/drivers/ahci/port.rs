@@ -0,0 +1,461 @@
+use super::{read, wait_for, write};
+use crate::block::{self, BlockDevice, BlockError, BlockFuture};
+use crate::drivers::ata::{self, Identify};
+use crate::drivers::dma::{DmaBuffer, DmaPool, PAGE_SIZE};
+use crate::memory;
+use crate::sync::{IrqMutex, WaitQueue, WakeList};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::poll_fn;
+use core::sync::atomic::{fence, Ordering};
+use core::task::{Poll, Waker};
+use x86_64::{PhysAddr, VirtAddr};
+
+// Port registers.
+const CLB: u64 = 0x00;
+const CLBU: u64 = 0x04;
+const FB: u64 = 0x08;
+const FBU: u64 = 0x0c;
+const IS: u64 = 0x10;
+const IE: u64 = 0x14;
+const CMD: u64 = 0x18;
+const TFD: u64 = 0x20;
+const SIG: u64 = 0x24;
+const SSTS: u64 = 0x28;
+const SERR: u64 = 0x30;
+const CI: u64 = 0x38;
+
+const CMD_ST: u32 = 1 << 0;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+
+const IS_DHRS: u32 = 1 << 0;
+const IS_PSS: u32 = 1 << 1;
+const IS_DSS: u32 = 1 << 2;
+const IS_SDBS: u32 = 1 << 3;
+const IS_IFS: u32 = 1 << 27;
+const IS_HBDS: u32 = 1 << 28;
+const IS_HBFS: u32 = 1 << 29;
+const IS_TFES: u32 = 1 << 30;
+const IS_ERRORS: u32 = IS_IFS | IS_HBDS | IS_HBFS | IS_TFES;
+
+const SSTS_DET_PRESENT: u32 = 3;
+const SSTS_IPM_ACTIVE: u32 = 1;
+const SIG_ATA: u32 = 0x0000_0101;
+
+const FIS_TYPE_H2D: u8 = 0x27;
+/// Set in a host-to-device FIS that carries a command.
+const FIS_COMMAND: u8 = 0x80;
+
+/// The received FIS area follows the 1 KiB command list in the same frame.
+const FIS_OFFSET: u64 = 0x400;
+const HEADER_SIZE: u64 = 32;
+const PRDT_OFFSET: u64 = 0x80;
+const PRD_SIZE: u64 = 16;
+
+/// Largest transfer; one PRD per bounce page.
+const MAX_TRANSFER: usize = 64 * 1024;
+
+/// One SATA port with a disk behind it.
+pub(super) struct Port {
+    number: usize,
+    regs: VirtAddr,
+    slots: usize,
+    /// Command list and received FIS area.
+    base: PhysAddr,
+    /// A command table per slot, one frame each.
+    tables: Vec<PhysAddr>,
+    identify: Identify,
+    state: IrqMutex<State>,
+    free_slots: WaitQueue,
+    /// Woken when a command fails and the port needs a restart.
+    restart: WaitQueue,
+    dma: DmaPool,
+}
+
+struct State {
+    free: u32,
+    issued: u32,
+    /// A command failed; nothing is issued until the port restarts.
+    failed: bool,
+    slots: [Slot; 32],
+}
+
+#[derive(Default)]
+struct Slot {
+    waker: Option<Waker>,
+    done: Option<Result<(), BlockError>>,
+    /// The submitter went away; free the slot once the command ends.
+    abandoned: bool,
+}
+
+struct Command<'a> {
+    command: u8,
+    lba: u64,
+    count: u16,
+    write: bool,
+    /// Bounce pages and the number of bytes to transfer.
+    data: Option<(&'a DmaBuffer<'a>, usize)>,
+}
+
+impl Port {
+    /// Starts the port and identifies its disk; `None` if there is no ATA
+    /// disk attached.
+    pub(super) fn new(regs: VirtAddr, number: usize, slots: usize) -> Result<Option<Port>, &'static str> {
+        let status = read(regs, SSTS);
+        if status & 0xf != SSTS_DET_PRESENT || (status >> 8) & 0xf != SSTS_IPM_ACTIVE {
+            return Ok(None);
+        }
+        if read(regs, SIG) != SIG_ATA {
+            return Ok(None);
+        }
+
+        let frame = || memory::allocate_dma_frame().map(|frame| frame.start_address()).ok_or("out of memory");
+        let mut port = Port {
+            number,
+            regs,
+            slots,
+            base: frame()?,
+            tables: (0..slots).map(|_| frame()).collect::<Result<_, _>>()?,
+            identify: Identify::default(),
+            state: IrqMutex::new(State {
+                free: (u64::MAX >> (64 - slots)) as u32,
+                issued: 0,
+                failed: false,
+                slots: core::array::from_fn(|_| Slot::default()),
+            }),
+            free_slots: WaitQueue::new(),
+            restart: WaitQueue::new(),
+            dma: DmaPool::new(),
+        };
+        port.stop()?;
+        port.write(CLB, port.base.as_u64() as u32);
+        port.write(CLBU, (port.base.as_u64() >> 32) as u32);
+        port.write(FB, (port.base + FIS_OFFSET).as_u64() as u32);
+        port.write(FBU, ((port.base + FIS_OFFSET).as_u64() >> 32) as u32);
+        port.write(SERR, u32::MAX);
+        port.write(IS, u32::MAX);
+        port.start()?;
+
+        port.identify = Identify::parse(&port.identify_polled()?);
+        if !port.identify.lba48 {
+            return Err("disk does not support LBA48");
+        }
+        Ok(Some(port))
+    }
+
+    pub(super) fn number(&self) -> usize {
+        self.number
+    }
+
+    pub(super) fn enable_interrupts(&self) {
+        self.write(IS, u32::MAX);
+        self.write(IE, IS_DHRS | IS_PSS | IS_DSS | IS_SDBS | IS_ERRORS);
+    }
+
+    fn read(&self, register: u64) -> u32 {
+        read(self.regs, register)
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        write(self.regs, register, value)
+    }
+
+    fn stop(&self) -> Result<(), &'static str> {
+        self.write(CMD, self.read(CMD) & !CMD_ST);
+        if !wait_for(|| self.read(CMD) & CMD_CR == 0) {
+            return Err("command list did not stop");
+        }
+        self.write(CMD, self.read(CMD) & !CMD_FRE);
+        if !wait_for(|| self.read(CMD) & CMD_FR == 0) {
+            return Err("FIS receive did not stop");
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), &'static str> {
+        if !wait_for(|| self.read(TFD) as u8 & (ata::STATUS_BSY | ata::STATUS_DRQ) == 0) {
+            return Err("device stays busy");
+        }
+        self.write(CMD, self.read(CMD) | CMD_FRE);
+        self.write(CMD, self.read(CMD) | CMD_ST);
+        Ok(())
+    }
+
+    /// Issues IDENTIFY DEVICE in slot 0 and polls for it, before
+    /// interrupts are set up.
+    fn identify_polled(&self) -> Result<[u16; 256], &'static str> {
+        let dma = self.dma.alloc(1).ok_or("out of memory")?;
+        self.prepare(0, &Command {
+            command: ata::CMD_IDENTIFY,
+            lba: 0,
+            count: 0,
+            write: false,
+            data: Some((&dma, 512)),
+        });
+        self.write(CI, 1);
+        if !wait_for(|| self.read(CI) & 1 == 0 || self.read(IS) & IS_ERRORS != 0) {
+            return Err("IDENTIFY timed out");
+        }
+        if self.read(IS) & IS_ERRORS != 0 || self.read(TFD) as u8 & ata::STATUS_ERR != 0 {
+            return Err("IDENTIFY failed");
+        }
+        let page = dma.page(0);
+        Ok(core::array::from_fn(|i| u16::from_le_bytes([page[2 * i], page[2 * i + 1]])))
+    }
+
+    /// Fills the command header and table of `slot`.
+    fn prepare(&self, slot: usize, command: &Command) {
+        let table = memory::phys_to_virt(self.tables[slot]);
+        let prds = command.data.map_or(0, |(_, len)| len.div_ceil(PAGE_SIZE));
+        unsafe {
+            table.as_mut_ptr::<u8>().write_bytes(0, PRDT_OFFSET as usize);
+            let fis = table.as_mut_ptr::<u8>();
+            let lba = command.lba.to_le_bytes();
+            let count = command.count.to_le_bytes();
+            let bytes = [
+                FIS_TYPE_H2D,
+                FIS_COMMAND,
+                command.command,
+                0,
+                lba[0],
+                lba[1],
+                lba[2],
+                ata::DEVICE_LBA,
+                lba[3],
+                lba[4],
+                lba[5],
+                0,
+                count[0],
+                count[1],
+            ];
+            fis.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+
+            if let Some((dma, len)) = command.data {
+                for page in 0..prds {
+                    let prd = (table + PRDT_OFFSET + page as u64 * PRD_SIZE).as_mut_ptr::<u32>();
+                    let chunk = (len - page * PAGE_SIZE).min(PAGE_SIZE);
+                    prd.cast::<u64>().write_volatile(dma.phys(page).as_u64());
+                    prd.add(2).write_volatile(0);
+                    prd.add(3).write_volatile(chunk as u32 - 1);
+                }
+            }
+
+            let header = memory::phys_to_virt(self.base + slot as u64 * HEADER_SIZE).as_mut_ptr::<u32>();
+            // Command FIS length in dwords.
+            let flags = 5 | (command.write as u32) << 6 | (prds as u32) << 16;
+            header.write_volatile(flags);
+            header.add(1).write_volatile(0);
+            header.add(2).write_volatile(self.tables[slot].as_u64() as u32);
+            header.add(3).write_volatile((self.tables[slot].as_u64() >> 32) as u32);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    fn take_slot(&self) -> Option<usize> {
+        let mut state = self.state.lock();
+        if state.failed {
+            return None;
+        }
+        let slot = state.free.trailing_zeros() as usize;
+        if slot >= self.slots {
+            return None;
+        }
+        state.free &= !(1 << slot);
+        Some(slot)
+    }
+
+    /// Issues `command` and waits for the interrupt that completes it.
+    async fn issue(&self, command: Command<'_>) -> Result<(), BlockError> {
+        let slot = self.free_slots.wait_until(|| self.take_slot()).await;
+        let mut request = Request { port: self, slot, finished: false };
+        self.prepare(slot, &command);
+        {
+            let mut state = self.state.lock();
+            if state.failed {
+                // Another command failed while this one was prepared.
+                state.free |= 1 << slot;
+                request.finished = true;
+                return Err(BlockError::Io);
+            }
+            state.issued |= 1 << slot;
+            self.write(CI, 1 << slot);
+        }
+        let result = poll_fn(|cx| {
+            let mut state = self.state.lock();
+            match state.slots[slot].done.take() {
+                Some(result) => {
+                    state.free |= 1 << slot;
+                    Poll::Ready(result)
+                }
+                None => {
+                    state.slots[slot].waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        request.finished = true;
+        self.free_slots.wake_all();
+        result
+    }
+
+    async fn transfer(&self, write: bool, sector: u64, len: usize, dma: &DmaBuffer<'_>) -> Result<(), BlockError> {
+        self.issue(Command {
+            command: if write { ata::CMD_WRITE_DMA_EXT } else { ata::CMD_READ_DMA_EXT },
+            lba: sector,
+            count: (len / self.identify.sector_size) as u16,
+            write,
+            data: Some((dma, len)),
+        })
+        .await
+    }
+
+    /// Restarts the port whenever a command fails. Runs as a task, since
+    /// stopping the port can take a while.
+    pub(super) async fn recover(&self) {
+        loop {
+            self.restart.wait_until(|| self.state.lock().failed.then_some(())).await;
+            self.write(SERR, u32::MAX);
+            // Stopping the port clears CI, and with it the failed commands.
+            if let Err(err) = self.stop().and_then(|()| self.start()) {
+                // The port stays failed, so every command fails from now on.
+                crate::println!("ahci port {}: restart failed: {}", self.number, err);
+                return;
+            }
+            self.state.lock().failed = false;
+            self.free_slots.wake_all();
+        }
+    }
+
+    /// Completes commands the HBA has finished. A task file error fails
+    /// every outstanding command and leaves the restart to [`recover`].
+    ///
+    /// [`recover`]: Port::recover
+    pub(super) fn interrupt(&self) {
+        let status = self.read(IS);
+        self.write(IS, status);
+        let error = status & IS_ERRORS != 0;
+
+        let mut wakers = WakeList::new();
+        let mut freed = false;
+        {
+            let mut state = self.state.lock();
+            if error {
+                state.failed = true;
+            }
+            let running = if error { 0 } else { self.read(CI) };
+            for slot in 0..self.slots {
+                let bit = 1 << slot;
+                if state.issued & bit == 0 || running & bit != 0 {
+                    continue;
+                }
+                state.issued &= !bit;
+                let entry = &mut state.slots[slot];
+                if entry.abandoned {
+                    *entry = Slot::default();
+                    state.free |= bit;
+                    freed = true;
+                } else {
+                    entry.done = Some(if error { Err(BlockError::Io) } else { Ok(()) });
+                    if let Some(waker) = entry.waker.take() {
+                        // Wake a full batch without holding the lock.
+                        if wakers.is_full() {
+                            drop(state);
+                            wakers.wake_all();
+                            state = self.state.lock();
+                        }
+                        wakers.push(waker);
+                    }
+                }
+            }
+        }
+        wakers.wake_all();
+        if error {
+            self.restart.wake_one();
+        }
+        if freed {
+            self.free_slots.wake_all();
+        }
+    }
+}
+
+/// An issued command; abandons it if dropped before completion.
+struct Request<'a> {
+    port: &'a Port,
+    slot: usize,
+    finished: bool,
+}
+
+impl Drop for Request<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let mut state = self.port.state.lock();
+        let slot = &mut state.slots[self.slot];
+        if slot.done.take().is_some() {
+            state.free |= 1 << self.slot;
+            drop(state);
+            self.port.free_slots.wake_all();
+        } else {
+            slot.waker = None;
+            slot.abandoned = true;
+        }
+    }
+}
+
+impl BlockDevice for Port {
+    fn sector_size(&self) -> usize {
+        self.identify.sector_size
+    }
+
+    fn capacity(&self) -> u64 {
+        self.identify.sectors
+    }
+
+    fn max_sectors(&self) -> usize {
+        MAX_TRANSFER / self.identify.sector_size
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let count = block::check_range(self, sector, buf.len())?;
+            if count as usize > self.max_sectors() {
+                return Err(BlockError::OutOfRange);
+            }
+            let mut dma = self.dma.alloc(buf.len().div_ceil(PAGE_SIZE)).ok_or(BlockError::NoMemory)?;
+            dma.start();
+            let result = self.transfer(false, sector, buf.len(), &dma).await;
+            dma.finish();
+            dma.copy_to(0, buf);
+            result
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let count = block::check_range(self, sector, buf.len())?;
+            if count as usize > self.max_sectors() {
+                return Err(BlockError::OutOfRange);
+            }
+            let mut dma = self.dma.alloc(buf.len().div_ceil(PAGE_SIZE)).ok_or(BlockError::NoMemory)?;
+            dma.copy_from(0, buf);
+            dma.start();
+            let result = self.transfer(true, sector, buf.len(), &dma).await;
+            dma.finish();
+            result
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(self.issue(Command {
+            command: ata::CMD_FLUSH_CACHE_EXT,
+            lba: 0,
+            count: 0,
+            write: false,
+            data: None,
+        }))
+    }
+}
@@ -0,0 +1,68 @@
+//! ATA command set definitions shared by the disk controller drivers.
+
+use alloc::string::String;
+
+pub const CMD_READ_SECTORS: u8 = 0x20;
+pub const CMD_READ_SECTORS_EXT: u8 = 0x24;
+pub const CMD_READ_DMA_EXT: u8 = 0x25;
+pub const CMD_WRITE_SECTORS: u8 = 0x30;
+pub const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+pub const CMD_WRITE_DMA_EXT: u8 = 0x35;
+pub const CMD_FLUSH_CACHE: u8 = 0xe7;
+pub const CMD_FLUSH_CACHE_EXT: u8 = 0xea;
+pub const CMD_IDENTIFY: u8 = 0xec;
+
+/// Status register bits.
+pub const STATUS_ERR: u8 = 1 << 0;
+pub const STATUS_DRQ: u8 = 1 << 3;
+pub const STATUS_DF: u8 = 1 << 5;
+pub const STATUS_BSY: u8 = 1 << 7;
+
+/// Selects LBA addressing in the device register.
+pub const DEVICE_LBA: u8 = 1 << 6;
+
+/// The fields of IDENTIFY DEVICE data we use.
+#[derive(Debug, Clone, Default)]
+pub struct Identify {
+    pub model: String,
+    pub serial: String,
+    /// Addressable logical sectors.
+    pub sectors: u64,
+    pub sector_size: usize,
+    pub lba48: bool,
+}
+
+impl Identify {
+    pub fn parse(words: &[u16; 256]) -> Identify {
+        let lba48 = words[83] & (1 << 10) != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0, |sectors, i| sectors | (words[100 + i] as u64) << (16 * i))
+        } else {
+            words[60] as u64 | (words[61] as u64) << 16
+        };
+        // Word 106 is valid when bit 14 is set and bit 15 clear.
+        let sector_size = if words[106] & 0xc000 == 0x4000 && words[106] & (1 << 12) != 0 {
+            2 * (words[117] as usize | (words[118] as usize) << 16)
+        } else {
+            512
+        };
+        Identify {
+            model: string(&words[27..47]),
+            serial: string(&words[10..20]),
+            sectors,
+            sector_size,
+            lba48,
+        }
+    }
+}
+
+/// Decodes an ATA string, which stores two characters per word with the
+/// first in the high byte.
+fn string(words: &[u16]) -> String {
+    let text: String = words
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .map(|byte| if byte.is_ascii_graphic() { byte as char } else { ' ' })
+        .collect();
+    String::from(text.trim())
+}
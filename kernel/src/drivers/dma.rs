@@ -0,0 +1,102 @@
+//! Bounce buffers for device DMA.
+//!
+//! Storage drivers copy data through page-sized frames from the frame
+//! allocator rather than pointing devices at heap memory, whose physical
+//! pages are not contiguous. Frames go back to their pool when a request
+//! finishes; a request dropped while the device still owns its frames
+//! leaks them instead.
+
+use crate::memory;
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::PhysAddr;
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub struct DmaPool {
+    frames: SpinLock<Vec<PhysFrame>>,
+}
+
+impl DmaPool {
+    #[track_caller]
+    pub const fn new() -> DmaPool {
+        DmaPool { frames: SpinLock::new(Vec::new()) }
+    }
+
+    /// Takes `pages` frames from the pool, allocating more as needed.
+    pub fn alloc(&self, pages: usize) -> Option<DmaBuffer<'_>> {
+        let mut frames = Vec::with_capacity(pages);
+        while frames.len() < pages {
+            let frame = self.frames.lock().pop().or_else(memory::allocate_dma_frame);
+            match frame {
+                Some(frame) => frames.push(frame),
+                None => {
+                    self.frames.lock().append(&mut frames);
+                    return None;
+                }
+            }
+        }
+        Some(DmaBuffer { pool: self, frames, in_flight: false })
+    }
+}
+
+impl Default for DmaPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct DmaBuffer<'a> {
+    pool: &'a DmaPool,
+    frames: Vec<PhysFrame>,
+    in_flight: bool,
+}
+
+impl DmaBuffer<'_> {
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn phys(&self, page: usize) -> PhysAddr {
+        self.frames[page].start_address()
+    }
+
+    /// Bytes of `page`, which must not be owned by the device.
+    #[allow(clippy::mut_from_ref)]
+    pub fn page(&self, page: usize) -> &mut [u8] {
+        let start = memory::phys_to_virt(self.phys(page)).as_mut_ptr::<u8>();
+        unsafe { core::slice::from_raw_parts_mut(start, PAGE_SIZE) }
+    }
+
+    /// Copies `data` into the pages starting at `first`.
+    pub fn copy_from(&self, first: usize, data: &[u8]) {
+        for (i, chunk) in data.chunks(PAGE_SIZE).enumerate() {
+            self.page(first + i)[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    /// Fills `data` from the pages starting at `first`.
+    pub fn copy_to(&self, first: usize, data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(PAGE_SIZE).enumerate() {
+            chunk.copy_from_slice(&self.page(first + i)[..chunk.len()]);
+        }
+    }
+
+    /// Marks the frames as owned by the device until [`finish`](Self::finish).
+    pub fn start(&mut self) {
+        self.in_flight = true;
+    }
+
+    pub fn finish(&mut self) {
+        self.in_flight = false;
+    }
+}
+
+impl Drop for DmaBuffer<'_> {
+    fn drop(&mut self) {
+        if !self.in_flight {
+            self.pool.frames.lock().append(&mut self.frames);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Intel 8042 PS/2 controller.
+//!
+//! [`init`] runs with interrupts off and polls the controller: it self-tests
+//! the controller, probes and resets both ports, selects the keyboard
+//! scancode set and enables the mouse, then turns on IRQ1 and IRQ12.
+//!
+//! The keyboard always speaks set 2; unless `keyboard.set=2` is given on
+//! the kernel command line the controller translates it to set 1.
+
+use crate::{cmdline, time};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT_2: u8 = 0xa7;
+const CMD_ENABLE_PORT_2: u8 = 0xa8;
+const CMD_TEST_PORT_2: u8 = 0xa9;
+const CMD_SELF_TEST: u8 = 0xaa;
+const CMD_TEST_PORT_1: u8 = 0xab;
+const CMD_DISABLE_PORT_1: u8 = 0xad;
+const CMD_ENABLE_PORT_1: u8 = 0xae;
+const CMD_WRITE_PORT_2: u8 = 0xd4;
+
+const CONFIG_PORT_1_IRQ: u8 = 0x01;
+const CONFIG_PORT_2_IRQ: u8 = 0x02;
+const CONFIG_PORT_2_CLOCK_OFF: u8 = 0x20;
+const CONFIG_TRANSLATION: u8 = 0x40;
+
+const SELF_TEST_OK: u8 = 0x55;
+const PORT_TEST_OK: u8 = 0x00;
+
+const DEV_RESET: u8 = 0xff;
+const DEV_SET_DEFAULTS: u8 = 0xf6;
+const DEV_ENABLE_REPORTING: u8 = 0xf4;
+const DEV_IDENTIFY: u8 = 0xf2;
+const DEV_SET_SAMPLE_RATE: u8 = 0xf3;
+const DEV_SCANCODE_SET: u8 = 0xf0;
+const DEV_ACK: u8 = 0xfa;
+const DEV_SELF_TEST_OK: u8 = 0xaa;
+
+/// Mouse ID reported after the IntelliMouse sample rate sequence.
+pub const MOUSE_ID_WHEEL: u8 = 3;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Timeout,
+    SelfTestFailed(u8),
+    PortTestFailed(u8, u8),
+    NoAck(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Port1,
+    Port2,
+}
+
+static KEYBOARD_PRESENT: AtomicBool = AtomicBool::new(false);
+static MOUSE_PRESENT: AtomicBool = AtomicBool::new(false);
+static MOUSE_ID: AtomicU8 = AtomicU8::new(0);
+static SCANCODE_SET: AtomicU8 = AtomicU8::new(1);
+
+pub fn keyboard_present() -> bool {
+    KEYBOARD_PRESENT.load(Ordering::Relaxed)
+}
+
+pub fn mouse_present() -> bool {
+    MOUSE_PRESENT.load(Ordering::Relaxed)
+}
+
+/// The scancode set read from port 1: 1 (translated) or 2.
+pub fn scancode_set() -> u8 {
+    SCANCODE_SET.load(Ordering::Relaxed)
+}
+
+/// The mouse device ID; [`MOUSE_ID_WHEEL`] means 4-byte packets.
+pub fn mouse_id() -> u8 {
+    MOUSE_ID.load(Ordering::Relaxed)
+}
+
+pub fn init() -> Result<(), Error> {
+    command(CMD_DISABLE_PORT_1)?;
+    command(CMD_DISABLE_PORT_2)?;
+    flush();
+
+    let set = match cmdline::get("keyboard.set") {
+        Some("2") => 2,
+        _ => 1,
+    };
+    let mut config = read_config()?;
+    config &= !(CONFIG_PORT_1_IRQ | CONFIG_PORT_2_IRQ);
+    if set == 1 {
+        config |= CONFIG_TRANSLATION;
+    } else {
+        config &= !CONFIG_TRANSLATION;
+    }
+    write_config(config)?;
+
+    command(CMD_SELF_TEST)?;
+    match read()? {
+        SELF_TEST_OK => {}
+        other => return Err(Error::SelfTestFailed(other)),
+    }
+    // Some controllers reset their configuration during the self test.
+    write_config(config)?;
+
+    command(CMD_ENABLE_PORT_2)?;
+    let dual_channel = read_config()? & CONFIG_PORT_2_CLOCK_OFF == 0;
+    command(CMD_DISABLE_PORT_2)?;
+
+    command(CMD_TEST_PORT_1)?;
+    match read()? {
+        PORT_TEST_OK => {}
+        other => return Err(Error::PortTestFailed(1, other)),
+    }
+    let port_2_ok = dual_channel && {
+        command(CMD_TEST_PORT_2)?;
+        read()? == PORT_TEST_OK
+    };
+
+    command(CMD_ENABLE_PORT_1)?;
+    if init_keyboard().is_ok() {
+        KEYBOARD_PRESENT.store(true, Ordering::Relaxed);
+        SCANCODE_SET.store(set, Ordering::Relaxed);
+        config |= CONFIG_PORT_1_IRQ;
+    }
+
+    if port_2_ok {
+        command(CMD_ENABLE_PORT_2)?;
+        if let Ok(id) = init_mouse() {
+            MOUSE_ID.store(id, Ordering::Relaxed);
+            MOUSE_PRESENT.store(true, Ordering::Relaxed);
+            config |= CONFIG_PORT_2_IRQ;
+            config &= !CONFIG_PORT_2_CLOCK_OFF;
+        }
+    }
+
+    flush();
+    write_config(config)
+}
+
+fn init_keyboard() -> Result<(), Error> {
+    let device = Device::Port1;
+    reset(device)?;
+    send(device, DEV_SCANCODE_SET)?;
+    send(device, 2)?;
+    send(device, DEV_ENABLE_REPORTING)
+}
+
+/// Resets the mouse, switches it to IntelliMouse mode if it supports a
+/// wheel and enables streaming; returns the device ID.
+fn init_mouse() -> Result<u8, Error> {
+    let device = Device::Port2;
+    reset(device)?;
+    send(device, DEV_SET_DEFAULTS)?;
+
+    for rate in [200, 100, 80] {
+        send(device, DEV_SET_SAMPLE_RATE)?;
+        send(device, rate)?;
+    }
+    send(device, DEV_IDENTIFY)?;
+    let id = read()?;
+
+    send(device, DEV_ENABLE_REPORTING)?;
+    Ok(id)
+}
+
+fn reset(device: Device) -> Result<(), Error> {
+    send(device, DEV_RESET)?;
+    match read()? {
+        DEV_SELF_TEST_OK => {}
+        other => return Err(Error::SelfTestFailed(other)),
+    }
+    // Mice follow up with their device ID.
+    if device == Device::Port2 {
+        let _ = read();
+    }
+    Ok(())
+}
+
+/// Sends a byte to a device and waits for its ACK.
+fn send(device: Device, byte: u8) -> Result<(), Error> {
+    if device == Device::Port2 {
+        command(CMD_WRITE_PORT_2)?;
+    }
+    write(byte)?;
+    match read()? {
+        DEV_ACK => Ok(()),
+        other => Err(Error::NoAck(other)),
+    }
+}
+
+/// Writes a byte to the device on port 1 without waiting for the reply,
+/// which arrives through the IRQ.
+pub fn write_port_1(byte: u8) -> Result<(), Error> {
+    write(byte)
+}
+
+fn read_config() -> Result<u8, Error> {
+    command(CMD_READ_CONFIG)?;
+    read()
+}
+
+fn write_config(config: u8) -> Result<(), Error> {
+    command(CMD_WRITE_CONFIG)?;
+    write(config)
+}
+
+fn command(command: u8) -> Result<(), Error> {
+    wait(|status| status & STATUS_INPUT_FULL == 0)?;
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+fn write(byte: u8) -> Result<(), Error> {
+    wait(|status| status & STATUS_INPUT_FULL == 0)?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+fn read() -> Result<u8, Error> {
+    wait(|status| status & STATUS_OUTPUT_FULL != 0)?;
+    Ok(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn flush() {
+    let mut status = Port::<u8>::new(STATUS_PORT);
+    let mut data = Port::<u8>::new(DATA_PORT);
+    unsafe {
+        while status.read() & STATUS_OUTPUT_FULL != 0 {
+            data.read();
+        }
+    }
+}
+
+fn wait(ready: impl Fn(u8) -> bool) -> Result<(), Error> {
+    let mut status = Port::<u8>::new(STATUS_PORT);
+    let start = time::tsc();
+    loop {
+        if ready(unsafe { status.read() }) {
+            return Ok(());
+        }
+        if time::cycles_to_duration(time::tsc() - start) > TIMEOUT {
+            return Err(Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
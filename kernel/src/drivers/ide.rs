@@ -0,0 +1,347 @@
+//! Legacy IDE controllers in compatibility mode.
+//!
+//! The primary channel sits at I/O ports 0x1f0/0x3f6 on IRQ 14, the
+//! secondary at 0x170/0x376 on IRQ 15. The probe identifies the drives on
+//! both channels by polling, then routes the IRQs through the IOAPIC.
+//! Transfers use PIO: the drive raises its IRQ for every sector it has
+//! ready, and the waiting task copies the sector through the data port.
+//! Drives are registered as `hda` to `hdd`.
+
+use crate::apic::ioapic;
+use crate::block::{self, BlockDevice, BlockError, BlockFuture};
+use crate::drivers::ata::{self, Identify};
+use crate::interrupts::vector;
+use crate::pci::driver::{Driver, Match, ProbeResult};
+use crate::pci::PciFunction;
+use crate::sync::{Mutex, WaitQueue};
+use crate::time;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+pub static DRIVER: Driver = Driver {
+    name: "ide",
+    matches: &[Match::class(0x01, 0x01)],
+    probe,
+};
+
+/// Programming interface bits that put a channel in native PCI mode.
+const PROG_IF_PRIMARY_NATIVE: u8 = 1 << 0;
+const PROG_IF_SECONDARY_NATIVE: u8 = 1 << 2;
+
+const CHANNELS: [(u16, u16, u8, &str); 2] = [(0x1f0, 0x3f6, 14, "ide0"), (0x170, 0x376, 15, "ide1")];
+
+// Command block registers.
+const DATA: u16 = 0;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DEVICE: u16 = 6;
+const STATUS: u16 = 7;
+const COMMAND: u16 = 7;
+
+/// Device control register bit that masks the drive's IRQ.
+const CONTROL_NIEN: u8 = 1 << 1;
+/// Device control register bit that resets both drives of the channel.
+const CONTROL_SRST: u8 = 1 << 2;
+/// Device register bits that must be set on older drives.
+const DEVICE_OBSOLETE: u8 = 0xa0;
+const DEVICE_SLAVE: u8 = 1 << 4;
+
+const SECTOR_SIZE: usize = 512;
+const MAX_SECTORS: usize = 128;
+const LBA28_LIMIT: u64 = 1 << 28;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn probe(function: &PciFunction) -> ProbeResult {
+    let native = [PROG_IF_PRIMARY_NATIVE, PROG_IF_SECONDARY_NATIVE];
+    let mut found = false;
+    for (&(io, control, irq, name), native) in CHANNELS.iter().zip(native) {
+        if function.prog_if & native != 0 {
+            continue;
+        }
+        let channel = Arc::new(Channel::new(io, control));
+        let drives = [false, true].map(|slave| channel.identify(slave).map(|identify| (slave, identify)));
+        if drives.iter().all(Option::is_none) {
+            continue;
+        }
+        // Channels live as long as the kernel, so the handler's pointer
+        // stays valid.
+        let data = Arc::into_raw(channel.clone()) as usize;
+        let vector = vector::allocate(name, Channel::interrupt, data).ok_or("no free vectors")?;
+        ioapic::route_isa(irq, vector);
+        channel.write_control(0);
+        for (slave, identify) in drives.into_iter().flatten() {
+            let drive = Drive { channel: channel.clone(), slave, identify };
+            block::register("hd", Arc::new(drive));
+        }
+        found = true;
+    }
+    match found {
+        true => Ok(()),
+        false => Err("no drives in compatibility mode"),
+    }
+}
+
+struct Channel {
+    io: u16,
+    control: u16,
+    /// Held for the whole of a command, since both drives share the
+    /// registers.
+    busy: Mutex<()>,
+    irq: AtomicBool,
+    irq_waiters: WaitQueue,
+}
+
+impl Channel {
+    fn new(io: u16, control: u16) -> Channel {
+        Channel {
+            io,
+            control,
+            busy: Mutex::new(()),
+            irq: AtomicBool::new(false),
+            irq_waiters: WaitQueue::new(),
+        }
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.io + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.io + register).write(value) }
+    }
+
+    fn write_control(&self, value: u8) {
+        unsafe { Port::new(self.control).write(value) }
+    }
+
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::new(self.control).read() }
+    }
+
+    /// Waits the 400 ns a drive needs to update its status after a
+    /// command or drive selection, by reading the alternate status.
+    fn settle(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    fn read_sector(&self, buf: &mut [u8]) {
+        let mut port = Port::<u16>::new(self.io + DATA);
+        for bytes in buf.chunks_mut(2) {
+            bytes.copy_from_slice(&unsafe { port.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buf: &[u8]) {
+        let mut port = Port::<u16>::new(self.io + DATA);
+        for bytes in buf.chunks(2) {
+            unsafe { port.write(u16::from_le_bytes([bytes[0], bytes[1]])) };
+        }
+    }
+
+    /// Spins until BSY clears and returns the status; `None` on timeout.
+    fn wait_not_busy(&self) -> Option<u8> {
+        let start = time::tsc();
+        loop {
+            let status = self.alt_status();
+            if status & ata::STATUS_BSY == 0 {
+                return Some(status);
+            }
+            if time::cycles_to_duration(time::tsc() - start) > TIMEOUT {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Runs IDENTIFY DEVICE with the drive's IRQ masked. `None` if there
+    /// is no ATA drive, e.g. nothing attached or an ATAPI drive.
+    fn identify(&self, slave: bool) -> Option<Identify> {
+        self.write_control(CONTROL_NIEN);
+        // A floating bus reads all ones.
+        if self.alt_status() == 0xff {
+            return None;
+        }
+        self.write(DEVICE, DEVICE_OBSOLETE | if slave { DEVICE_SLAVE } else { 0 });
+        self.settle();
+        for register in [SECTOR_COUNT, LBA_LOW, LBA_MID, LBA_HIGH] {
+            self.write(register, 0);
+        }
+        self.write(COMMAND, ata::CMD_IDENTIFY);
+        self.settle();
+        if self.alt_status() == 0 {
+            return None;
+        }
+        self.wait_not_busy()?;
+        // ATAPI and SATA drives answer with a signature here instead.
+        if self.read(LBA_MID) != 0 || self.read(LBA_HIGH) != 0 {
+            return None;
+        }
+        let status = self.wait_not_busy()?;
+        if status & ata::STATUS_ERR != 0 || status & ata::STATUS_DRQ == 0 {
+            return None;
+        }
+        let mut bytes = [0u8; SECTOR_SIZE];
+        self.read_sector(&mut bytes);
+        let identify = Identify::parse(&core::array::from_fn(|i| u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]])));
+        (identify.sector_size == SECTOR_SIZE && identify.sectors > 0).then_some(identify)
+    }
+
+    /// Selects the drive and issues `command` for `count` sectors at
+    /// `sector`, using 48-bit addressing when `lba48` is set.
+    fn issue(&self, slave: bool, lba48: bool, command: u8, sector: u64, count: u16) {
+        let select = if slave { DEVICE_SLAVE } else { 0 };
+        let lba = sector.to_le_bytes();
+        let count_bytes = count.to_le_bytes();
+        if lba48 {
+            self.write(DEVICE, ata::DEVICE_LBA | select);
+            self.settle();
+            // High order bytes go first, through the same registers.
+            self.write(SECTOR_COUNT, count_bytes[1]);
+            self.write(LBA_LOW, lba[3]);
+            self.write(LBA_MID, lba[4]);
+            self.write(LBA_HIGH, lba[5]);
+        } else {
+            self.write(DEVICE, DEVICE_OBSOLETE | ata::DEVICE_LBA | select | (lba[3] & 0xf));
+            self.settle();
+        }
+        self.write(SECTOR_COUNT, count_bytes[0]);
+        self.write(LBA_LOW, lba[0]);
+        self.write(LBA_MID, lba[1]);
+        self.write(LBA_HIGH, lba[2]);
+        self.irq.store(false, Ordering::SeqCst);
+        self.write(COMMAND, command);
+    }
+
+    /// Resets both drives, abandoning whatever command is running.
+    fn reset(&self) {
+        self.write_control(CONTROL_SRST);
+        time::delay(Duration::from_micros(5));
+        self.write_control(0);
+        time::delay(Duration::from_millis(2));
+        self.wait_not_busy();
+    }
+
+    /// Waits for the drive's next IRQ and returns the status it left. If
+    /// no IRQ comes within [`TIMEOUT`], the status register decides: a
+    /// drive that is done lost its IRQ, one still busy is reset.
+    async fn wait_irq(&self) -> Result<u8, BlockError> {
+        let irq = self.irq_waiters.wait_until(|| self.irq.swap(false, Ordering::SeqCst).then_some(()));
+        if time::timeout(TIMEOUT, irq).await.is_none() && self.alt_status() & ata::STATUS_BSY != 0 {
+            self.reset();
+            return Err(BlockError::Io);
+        }
+        let status = self.alt_status();
+        match status & (ata::STATUS_ERR | ata::STATUS_DF) {
+            0 => Ok(status),
+            _ => Err(BlockError::Io),
+        }
+    }
+
+    fn interrupt(data: usize) {
+        let channel = unsafe { &*(data as *const Channel) };
+        // Reading the status register acknowledges the IRQ.
+        channel.read(STATUS);
+        channel.irq.store(true, Ordering::SeqCst);
+        channel.irq_waiters.wake_all();
+    }
+}
+
+struct Drive {
+    channel: Arc<Channel>,
+    slave: bool,
+    identify: Identify,
+}
+
+impl Drive {
+    fn lba48(&self, sector: u64, count: usize) -> bool {
+        self.identify.lba48 && sector + count as u64 > LBA28_LIMIT
+    }
+
+    fn check(&self, sector: u64, len: usize) -> Result<usize, BlockError> {
+        let count = block::check_range(self, sector, len)? as usize;
+        match count <= MAX_SECTORS {
+            true => Ok(count),
+            false => Err(BlockError::OutOfRange),
+        }
+    }
+}
+
+impl BlockDevice for Drive {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn capacity(&self) -> u64 {
+        self.identify.sectors
+    }
+
+    fn max_sectors(&self) -> usize {
+        MAX_SECTORS
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let count = self.check(sector, buf.len())?;
+            if count == 0 {
+                return Ok(());
+            }
+            let channel = &self.channel;
+            let _busy = channel.busy.lock().await;
+            let lba48 = self.lba48(sector, count);
+            let command = if lba48 { ata::CMD_READ_SECTORS_EXT } else { ata::CMD_READ_SECTORS };
+            channel.issue(self.slave, lba48, command, sector, count as u16);
+            for chunk in buf.chunks_mut(SECTOR_SIZE) {
+                let status = channel.wait_irq().await?;
+                if status & ata::STATUS_DRQ == 0 {
+                    return Err(BlockError::Io);
+                }
+                channel.read_sector(chunk);
+            }
+            Ok(())
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let count = self.check(sector, buf.len())?;
+            if count == 0 {
+                return Ok(());
+            }
+            let channel = &self.channel;
+            let _busy = channel.busy.lock().await;
+            let lba48 = self.lba48(sector, count);
+            let command = if lba48 { ata::CMD_WRITE_SECTORS_EXT } else { ata::CMD_WRITE_SECTORS };
+            channel.issue(self.slave, lba48, command, sector, count as u16);
+            // The first sector is requested without an IRQ; every later
+            // one, and the end of the command, raises one.
+            channel.settle();
+            let status = channel.wait_not_busy().ok_or(BlockError::Io)?;
+            if status & (ata::STATUS_ERR | ata::STATUS_DF) != 0 || status & ata::STATUS_DRQ == 0 {
+                return Err(BlockError::Io);
+            }
+            for chunk in buf.chunks(SECTOR_SIZE) {
+                channel.write_sector(chunk);
+                channel.wait_irq().await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move {
+            let channel = &self.channel;
+            let _busy = channel.busy.lock().await;
+            let command = if self.identify.lba48 { ata::CMD_FLUSH_CACHE_EXT } else { ata::CMD_FLUSH_CACHE };
+            channel.issue(self.slave, self.identify.lba48, command, 0, 0);
+            channel.wait_irq().await.map(|_| ())
+        })
+    }
+}
@@ -0,0 +1,154 @@
+//! PS/2 mouse: packet decoding, mouse events and the on-screen pointer.
+
+use super::i8042;
+use crate::framebuffer::writer::FRAMEBUFFER;
+use crate::sync::SpinLock;
+use crate::task::channel::{self, Receiver, Sender, TrySendError};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use futures_util::stream::StreamExt;
+
+/// Bytes from the IRQ handler, set up by [`process_packets`].
+static BYTES: OnceCell<Sender<u8>> = OnceCell::uninit();
+
+const BYTE_CAPACITY: usize = 256;
+
+/// Called by the IRQ12 handler; bytes are dropped until the decoder runs
+/// or while its queue is full.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(sender) = BYTES.try_get() {
+        let _ = sender.try_send(byte);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Movement in screen coordinates: `dy` grows downwards.
+    pub dx: i16,
+    pub dy: i16,
+    /// Wheel movement, positive when scrolling down; always 0 without a wheel.
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+const FLAG_LEFT: u8 = 0x01;
+const FLAG_RIGHT: u8 = 0x02;
+const FLAG_MIDDLE: u8 = 0x04;
+/// Always set in the first byte; used to find packet boundaries.
+const FLAG_ALWAYS_ONE: u8 = 0x08;
+const FLAG_X_OVERFLOW: u8 = 0x40;
+const FLAG_Y_OVERFLOW: u8 = 0x80;
+
+/// Assembles 3-byte packets, or 4-byte ones for an IntelliMouse.
+struct Decoder {
+    packet: [u8; 4],
+    len: usize,
+    size: usize,
+}
+
+impl Decoder {
+    fn new(wheel: bool) -> Self {
+        Decoder {
+            packet: [0; 4],
+            len: 0,
+            size: if wheel { 4 } else { 3 },
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<MouseEvent> {
+        // A first byte without the always-one bit means we lost sync;
+        // drop bytes until one looks like the start of a packet.
+        if self.len == 0 && byte & FLAG_ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.size {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y, extra] = self.packet;
+        if flags & (FLAG_X_OVERFLOW | FLAG_Y_OVERFLOW) != 0 {
+            return None;
+        }
+        // Movement is 9-bit two's complement with the sign bits in `flags`.
+        let dx = x as i16 - (((flags as i16) << 4) & 0x100);
+        let dy = y as i16 - (((flags as i16) << 3) & 0x100);
+        let wheel = if self.size == 4 { ((extra << 4) as i8) >> 4 } else { 0 };
+
+        Some(MouseEvent {
+            dx,
+            dy: -dy,
+            wheel,
+            buttons: Buttons {
+                left: flags & FLAG_LEFT != 0,
+                right: flags & FLAG_RIGHT != 0,
+                middle: flags & FLAG_MIDDLE != 0,
+            },
+        })
+    }
+}
+
+static SUBSCRIBERS: SpinLock<Vec<Sender<MouseEvent>>> = SpinLock::named("mouse::SUBSCRIBERS", Vec::new());
+
+/// Subscribes to every mouse event; events are dropped for a subscriber
+/// whose queue of `capacity` events is full.
+pub fn subscribe(capacity: usize) -> Receiver<MouseEvent> {
+    let (sender, receiver) = channel::bounded(capacity);
+    SUBSCRIBERS.lock().push(sender);
+    receiver
+}
+
+fn publish(event: MouseEvent) {
+    SUBSCRIBERS
+        .lock()
+        .retain(|subscriber| !matches!(subscriber.try_send(event), Err(TrySendError::Closed(_))));
+}
+
+/// Decodes mouse packets and publishes them as [`MouseEvent`]s.
+pub async fn process_packets() {
+    if !i8042::mouse_present() {
+        return;
+    }
+
+    let (sender, mut bytes) = channel::bounded(BYTE_CAPACITY);
+    BYTES
+        .try_init_once(|| sender)
+        .expect("process_packets should only be run once");
+    let mut decoder = Decoder::new(i8042::mouse_id() == i8042::MOUSE_ID_WHEEL);
+    while let Some(byte) = bytes.next().await {
+        if let Some(event) = decoder.push(byte) {
+            publish(event);
+        }
+    }
+}
+
+/// Moves the framebuffer pointer with the mouse, starting at the centre of
+/// the screen.
+pub async fn pointer() {
+    if !i8042::mouse_present() {
+        return;
+    }
+
+    let mut events = subscribe(64);
+    let (width, height) = {
+        let framebuffer = FRAMEBUFFER.lock();
+        (framebuffer.width(), framebuffer.height())
+    };
+    let (mut x, mut y) = (width as isize / 2, height as isize / 2);
+    FRAMEBUFFER.lock().set_pointer(x as usize, y as usize);
+
+    while let Some(event) = events.next().await {
+        x = (x + event.dx as isize).clamp(0, width as isize - 1);
+        y = (y + event.dy as isize).clamp(0, height as isize - 1);
+        FRAMEBUFFER.lock().set_pointer(x as usize, y as usize);
+    }
+}
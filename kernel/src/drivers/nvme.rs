@@ -0,0 +1,340 @@
+//! NVMe controllers.
+//!
+//! The probe resets the controller and runs the admin commands with
+//! polling, before interrupts are on: identify, queue count negotiation and
+//! I/O queue creation. Each CPU gets its own I/O queue pair, whose
+//! completion queue raises an MSI-X vector on that CPU. Every active
+//! namespace is registered as `nvme<controller>n<namespace>`.
+
+mod queue;
+
+use self::queue::{Command, QueuePair, QUEUE_SIZE};
+use crate::block::{self, BlockDevice, BlockError, BlockFuture};
+use crate::drivers::dma::{DmaBuffer, DmaPool, PAGE_SIZE};
+use crate::pci::driver::{Driver, Match, ProbeResult};
+use crate::pci::{Bar, PciFunction, COMMAND_BUS_MASTER, COMMAND_MEMORY};
+use crate::{memory, smp, time};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use x86_64::VirtAddr;
+
+pub static DRIVER: Driver = Driver {
+    name: "nvme",
+    matches: &[Match::class_prog_if(0x01, 0x08, 0x02)],
+    probe,
+};
+
+// Controller registers.
+const CAP: u64 = 0x00;
+const CC: u64 = 0x14;
+const CSTS: u64 = 0x1c;
+const AQA: u64 = 0x24;
+const ASQ: u64 = 0x28;
+const ACQ: u64 = 0x30;
+const DOORBELLS: u64 = 0x1000;
+
+const CAP_CSS_NVM: u64 = 1 << 37;
+const CC_EN: u32 = 1 << 0;
+/// 64-byte submission and 16-byte completion entries.
+const CC_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_CFS: u32 = 1 << 1;
+
+const ADMIN_CREATE_SQ: u8 = 0x01;
+const ADMIN_CREATE_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const ADMIN_SET_FEATURES: u8 = 0x09;
+
+const IDENTIFY_NAMESPACE: u32 = 0;
+const IDENTIFY_CONTROLLER: u32 = 1;
+const IDENTIFY_ACTIVE_NAMESPACES: u32 = 2;
+
+const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+/// Largest transfer we issue, unless the controller allows less.
+const MAX_TRANSFER: usize = 64 * 1024;
+
+static CONTROLLERS: AtomicUsize = AtomicUsize::new(0);
+
+struct Controller {
+    /// I/O queue pairs, one per CPU.
+    queues: Vec<Arc<QueuePair>>,
+    max_transfer: usize,
+    dma: DmaPool,
+}
+
+impl Controller {
+    fn queue(&self) -> &QueuePair {
+        &self.queues[smp::current_cpu() % self.queues.len()]
+    }
+}
+
+fn probe(function: &PciFunction) -> ProbeResult {
+    let Some(Bar::Memory { address, .. }) = function.bars[0] else {
+        return Err("BAR0 is not a memory BAR");
+    };
+    let regs = memory::phys_to_virt(address);
+    if !memory::is_mapped(regs) {
+        return Err("BAR0 is not mapped");
+    }
+    function.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER);
+
+    let cap = read64(regs, CAP);
+    if cap & CAP_CSS_NVM == 0 {
+        return Err("no NVM command set");
+    }
+    if (cap >> 48) & 0xf != 0 {
+        return Err("4 KiB pages not supported");
+    }
+    if (cap & 0xffff) + 1 < QUEUE_SIZE as u64 {
+        return Err("queues too small");
+    }
+    let stride = 4u64 << ((cap >> 32) & 0xf);
+    let timeout = Duration::from_millis(500 * ((cap >> 24) & 0xff).max(1));
+    let doorbell = |qid: u16, completion: bool| regs + DOORBELLS + (2 * qid as u64 + completion as u64) * stride;
+
+    write32(regs, CC, read32(regs, CC) & !CC_EN);
+    if !wait_for(timeout, || read32(regs, CSTS) & CSTS_RDY == 0) {
+        return Err("controller did not reset");
+    }
+    let admin = QueuePair::new(doorbell(0, false), doorbell(0, true)).ok_or("out of memory")?;
+    let size = QUEUE_SIZE as u32 - 1;
+    write32(regs, AQA, size << 16 | size);
+    write64(regs, ASQ, admin.sq().as_u64());
+    write64(regs, ACQ, admin.cq().as_u64());
+    write32(regs, CC, CC_ENTRY_SIZES | CC_EN);
+    if !wait_for(timeout, || read32(regs, CSTS) & (CSTS_RDY | CSTS_CFS) != 0) || read32(regs, CSTS) & CSTS_CFS != 0 {
+        return Err("controller did not become ready");
+    }
+    let admin_command = |command: Command| admin.submit_polled(&command, timeout);
+
+    let dma = DmaPool::new();
+    let page = dma.alloc(1).ok_or("out of memory")?;
+    admin_command(Command {
+        opcode: ADMIN_IDENTIFY,
+        prp1: page.phys(0).as_u64(),
+        cdw10: IDENTIFY_CONTROLLER,
+        ..Command::default()
+    })?;
+    // MDTS is a power of two in units of the minimum page size, CAP.MPSMIN.
+    let mdts = page.page(0)[77];
+    let min_page_size = 4096usize << ((cap >> 48) & 0xf);
+    let limit = 1usize.checked_shl(mdts as u32).and_then(|units| units.checked_mul(min_page_size));
+    let max_transfer = match limit {
+        Some(limit) if mdts != 0 => MAX_TRANSFER.min(limit),
+        _ => MAX_TRANSFER,
+    };
+
+    let msix = function.msix().map_err(|_| "no MSI-X")?;
+    // MSI-X entry 0 belongs to the admin queue, which we only poll.
+    let wanted = smp::cpu_count().min(msix.len() - 1).max(1) as u32;
+    let granted = admin_command(Command {
+        opcode: ADMIN_SET_FEATURES,
+        cdw10: FEATURE_NUMBER_OF_QUEUES,
+        cdw11: (wanted - 1) << 16 | (wanted - 1),
+        ..Command::default()
+    })?;
+    let count = wanted.min((granted & 0xffff) + 1).min((granted >> 16) + 1) as u16;
+
+    let mut queues = Vec::new();
+    for qid in 1..=count {
+        let queue = Arc::new(QueuePair::new(doorbell(qid, false), doorbell(qid, true)).ok_or("out of memory")?);
+        // Physically contiguous, interrupts enabled, vector `qid`.
+        admin_command(Command {
+            opcode: ADMIN_CREATE_CQ,
+            prp1: queue.cq().as_u64(),
+            cdw10: size << 16 | qid as u32,
+            cdw11: (qid as u32) << 16 | 0b11,
+            ..Command::default()
+        })?;
+        admin_command(Command {
+            opcode: ADMIN_CREATE_SQ,
+            prp1: queue.sq().as_u64(),
+            cdw10: size << 16 | qid as u32,
+            cdw11: (qid as u32) << 16 | 0b1,
+            ..Command::default()
+        })?;
+        // Queue pairs live as long as the kernel, so the handler's pointer
+        // stays valid.
+        let data = Arc::into_raw(queue.clone()) as usize;
+        msix.route(qid as usize, "nvme", QueuePair::interrupt, data, qid as usize - 1)
+            .map_err(|_| "no free vectors")?;
+        queues.push(queue);
+    }
+    msix.enable();
+
+    admin_command(Command {
+        opcode: ADMIN_IDENTIFY,
+        prp1: page.phys(0).as_u64(),
+        cdw10: IDENTIFY_ACTIVE_NAMESPACES,
+        ..Command::default()
+    })?;
+    let nsids: Vec<u32> = page
+        .page(0)
+        .chunks(4)
+        .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+        .take_while(|&nsid| nsid != 0)
+        .collect();
+    let mut namespaces = Vec::new();
+    for nsid in nsids {
+        admin_command(Command {
+            opcode: ADMIN_IDENTIFY,
+            nsid,
+            prp1: page.phys(0).as_u64(),
+            cdw10: IDENTIFY_NAMESPACE,
+            ..Command::default()
+        })?;
+        let data = page.page(0);
+        let sectors = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        let format = (data[26] & 0xf) as usize;
+        let sector_size = 1usize << data[128 + 4 * format + 2];
+        if sectors != 0 && (512..=PAGE_SIZE).contains(&sector_size) {
+            namespaces.push((nsid, sectors, sector_size));
+        }
+    }
+    drop(page);
+
+    let index = CONTROLLERS.fetch_add(1, Ordering::Relaxed);
+    let controller = Arc::new(Controller { queues, max_transfer, dma });
+    for (nsid, sectors, sector_size) in namespaces {
+        let namespace = Namespace {
+            controller: controller.clone(),
+            nsid,
+            sectors,
+            sector_size,
+        };
+        block::register_named(format!("nvme{}n{}", index, nsid), Arc::new(namespace));
+    }
+    Ok(())
+}
+
+struct Namespace {
+    controller: Arc<Controller>,
+    nsid: u32,
+    sectors: u64,
+    sector_size: usize,
+}
+
+impl Namespace {
+    async fn transfer(&self, opcode: u8, sector: u64, len: usize, dma: &DmaBuffer<'_>) -> Result<(), BlockError> {
+        let pages = len.div_ceil(PAGE_SIZE);
+        // A second page goes in PRP2 directly; more need a PRP list, kept
+        // in the page after the data.
+        let prp2 = match pages {
+            1 => 0,
+            2 => dma.phys(1).as_u64(),
+            _ => {
+                let list = dma.page(pages);
+                for page in 1..pages {
+                    list[(page - 1) * 8..page * 8].copy_from_slice(&dma.phys(page).as_u64().to_le_bytes());
+                }
+                dma.phys(pages).as_u64()
+            }
+        };
+        let command = Command {
+            opcode,
+            nsid: self.nsid,
+            prp1: dma.phys(0).as_u64(),
+            prp2,
+            cdw10: sector as u32,
+            cdw11: (sector >> 32) as u32,
+            cdw12: (len / self.sector_size) as u32 - 1,
+        };
+        self.controller.queue().submit(&command).await.map(|_| ())
+    }
+
+    fn buffer(&self, sector: u64, len: usize) -> Result<DmaBuffer<'_>, BlockError> {
+        let count = block::check_range(self, sector, len)?;
+        if count as usize > self.max_sectors() {
+            return Err(BlockError::OutOfRange);
+        }
+        let pages = len.div_ceil(PAGE_SIZE);
+        let list = (pages > 2) as usize;
+        self.controller.dma.alloc(pages + list).ok_or(BlockError::NoMemory)
+    }
+}
+
+impl BlockDevice for Namespace {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn capacity(&self) -> u64 {
+        self.sectors
+    }
+
+    fn max_sectors(&self) -> usize {
+        self.controller.max_transfer / self.sector_size
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let mut dma = self.buffer(sector, buf.len())?;
+            dma.start();
+            let result = self.transfer(IO_READ, sector, buf.len(), &dma).await;
+            dma.finish();
+            dma.copy_to(0, buf);
+            result
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let mut dma = self.buffer(sector, buf.len())?;
+            dma.copy_from(0, buf);
+            dma.start();
+            let result = self.transfer(IO_WRITE, sector, buf.len(), &dma).await;
+            dma.finish();
+            result
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move {
+            let command = Command {
+                opcode: IO_FLUSH,
+                nsid: self.nsid,
+                ..Command::default()
+            };
+            self.controller.queue().submit(&command).await.map(|_| ())
+        })
+    }
+}
+
+fn read32(regs: VirtAddr, register: u64) -> u32 {
+    unsafe { (regs + register).as_ptr::<u32>().read_volatile() }
+}
+
+fn write32(regs: VirtAddr, register: u64, value: u32) {
+    unsafe { (regs + register).as_mut_ptr::<u32>().write_volatile(value) }
+}
+
+/// 64-bit registers are accessed as two halves, low first.
+fn read64(regs: VirtAddr, register: u64) -> u64 {
+    read32(regs, register) as u64 | (read32(regs, register + 4) as u64) << 32
+}
+
+fn write64(regs: VirtAddr, register: u64, value: u64) {
+    write32(regs, register, value as u32);
+    write32(regs, register + 4, (value >> 32) as u32);
+}
+
+/// Spins until `condition` holds; `false` on timeout.
+fn wait_for(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+    let start = time::tsc();
+    while !condition() {
+        if time::cycles_to_duration(time::tsc() - start) > timeout {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
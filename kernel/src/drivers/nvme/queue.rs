@@ -0,0 +1,274 @@
+use crate::block::BlockError;
+use crate::memory;
+use crate::sync::{IrqMutex, WaitQueue, WakeList};
+use crate::time;
+use alloc::vec::Vec;
+use core::future::poll_fn;
+use core::sync::atomic::{fence, Ordering};
+use core::task::{Poll, Waker};
+use core::time::Duration;
+use x86_64::{PhysAddr, VirtAddr};
+
+const SQ_ENTRY_SIZE: usize = 64;
+const CQ_ENTRY_SIZE: usize = 16;
+
+/// Entries per queue; a submission queue of this size fills one frame.
+pub(super) const QUEUE_SIZE: u16 = 64;
+
+/// A submission queue entry, minus the command identifier, which the queue
+/// assigns.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Command {
+    pub opcode: u8,
+    pub nsid: u32,
+    pub prp1: u64,
+    pub prp2: u64,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+}
+
+/// A submission queue and the completion queue it posts to.
+pub(super) struct QueuePair {
+    sq: PhysAddr,
+    cq: PhysAddr,
+    sq_doorbell: VirtAddr,
+    cq_doorbell: VirtAddr,
+    state: IrqMutex<State>,
+    free_slots: WaitQueue,
+}
+
+struct State {
+    sq_tail: u16,
+    cq_head: u16,
+    /// Phase tag of new completion entries; flips on every wrap.
+    phase: bool,
+    /// Free command identifiers.
+    free: u64,
+    slots: Vec<Slot>,
+}
+
+#[derive(Default)]
+struct Slot {
+    waker: Option<Waker>,
+    /// Command specific result, or the status field on failure.
+    done: Option<Result<u32, u16>>,
+    /// The submitter went away; free the slot once the command ends.
+    abandoned: bool,
+}
+
+impl QueuePair {
+    pub(super) fn new(sq_doorbell: VirtAddr, cq_doorbell: VirtAddr) -> Option<QueuePair> {
+        let frame = || memory::allocate_dma_frame().map(|frame| frame.start_address());
+        // One identifier short of the queue size, so the queue never fills.
+        let slots = QUEUE_SIZE as usize - 1;
+        Some(QueuePair {
+            sq: frame()?,
+            cq: frame()?,
+            sq_doorbell,
+            cq_doorbell,
+            state: IrqMutex::new(State {
+                sq_tail: 0,
+                cq_head: 0,
+                phase: true,
+                free: u64::MAX >> (64 - slots),
+                slots: (0..slots).map(|_| Slot::default()).collect(),
+            }),
+            free_slots: WaitQueue::new(),
+        })
+    }
+
+    pub(super) fn sq(&self) -> PhysAddr {
+        self.sq
+    }
+
+    pub(super) fn cq(&self) -> PhysAddr {
+        self.cq
+    }
+
+    fn take_slot(&self) -> Option<u16> {
+        let mut state = self.state.lock();
+        if state.free == 0 {
+            return None;
+        }
+        let slot = state.free.trailing_zeros();
+        state.free &= !(1 << slot);
+        Some(slot as u16)
+    }
+
+    /// Copies `command` into the submission queue and rings the doorbell.
+    fn push(&self, state: &mut State, cid: u16, command: &Command) {
+        let entry = memory::phys_to_virt(self.sq + state.sq_tail as u64 * SQ_ENTRY_SIZE as u64).as_mut_ptr::<u32>();
+        let mut dwords = [0u32; SQ_ENTRY_SIZE / 4];
+        dwords[0] = command.opcode as u32 | (cid as u32) << 16;
+        dwords[1] = command.nsid;
+        dwords[6] = command.prp1 as u32;
+        dwords[7] = (command.prp1 >> 32) as u32;
+        dwords[8] = command.prp2 as u32;
+        dwords[9] = (command.prp2 >> 32) as u32;
+        dwords[10] = command.cdw10;
+        dwords[11] = command.cdw11;
+        dwords[12] = command.cdw12;
+        for (i, dword) in dwords.into_iter().enumerate() {
+            unsafe { entry.add(i).write_volatile(dword) };
+        }
+        state.sq_tail = (state.sq_tail + 1) % QUEUE_SIZE;
+        fence(Ordering::SeqCst);
+        unsafe { self.sq_doorbell.as_mut_ptr::<u32>().write_volatile(state.sq_tail as u32) };
+    }
+
+    /// Takes the next completion entry, if the controller has posted one,
+    /// as its command identifier and result.
+    fn pop(&self, state: &mut State) -> Option<(u16, Result<u32, u16>)> {
+        let entry = memory::phys_to_virt(self.cq + state.cq_head as u64 * CQ_ENTRY_SIZE as u64).as_ptr::<u32>();
+        let dw3 = unsafe { entry.add(3).read_volatile() };
+        if (dw3 >> 16) & 1 != state.phase as u32 {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let result = unsafe { entry.read_volatile() };
+        let status = (dw3 >> 17) as u16;
+        state.cq_head = (state.cq_head + 1) % QUEUE_SIZE;
+        if state.cq_head == 0 {
+            state.phase = !state.phase;
+        }
+        let cid = dw3 as u16;
+        Some((cid, if status == 0 { Ok(result) } else { Err(status) }))
+    }
+
+    fn ring_cq(&self, state: &State) {
+        unsafe { self.cq_doorbell.as_mut_ptr::<u32>().write_volatile(state.cq_head as u32) };
+    }
+
+    /// Runs a command with interrupts off, spinning for its completion.
+    /// Only for a queue nobody else submits to.
+    pub(super) fn submit_polled(&self, command: &Command, timeout: Duration) -> Result<u32, &'static str> {
+        let cid = self.take_slot().ok_or("queue full")?;
+        let mut state = self.state.lock();
+        self.push(&mut state, cid, command);
+        let start = time::tsc();
+        loop {
+            if let Some((done, result)) = self.pop(&mut state) {
+                self.ring_cq(&state);
+                if done == cid {
+                    state.free |= 1 << cid;
+                    return result.map_err(|_| "command failed");
+                }
+                // A command an earlier call gave up on.
+                if let Some(slot) = state.slots.get_mut(done as usize)
+                    && slot.abandoned
+                {
+                    *slot = Slot::default();
+                    state.free |= 1 << done;
+                }
+            }
+            if time::cycles_to_duration(time::tsc() - start) > timeout {
+                // The controller may still complete it; keep the identifier
+                // until it does.
+                state.slots[cid as usize].abandoned = true;
+                return Err("command timed out");
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Submits `command` and waits for the interrupt that completes it.
+    pub(super) async fn submit(&self, command: &Command) -> Result<u32, BlockError> {
+        let cid = self.free_slots.wait_until(|| self.take_slot()).await;
+        let mut request = Request { queue: self, cid, finished: false };
+        self.push(&mut self.state.lock(), cid, command);
+        let result = poll_fn(|cx| {
+            let mut state = self.state.lock();
+            match state.slots[cid as usize].done.take() {
+                Some(result) => {
+                    state.free |= 1 << cid;
+                    Poll::Ready(result)
+                }
+                None => {
+                    state.slots[cid as usize].waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        request.finished = true;
+        self.free_slots.wake_all();
+        result.map_err(|_| BlockError::Io)
+    }
+
+    /// MSI-X handler; `data` is a pointer to the queue pair leaked by the
+    /// controller.
+    pub(super) fn interrupt(data: usize) {
+        let queue = unsafe { &*(data as *const QueuePair) };
+        queue.complete();
+    }
+
+    /// Collects completion entries and wakes their submitters, a batch at
+    /// a time so nothing is allocated.
+    fn complete(&self) {
+        let mut wakers = WakeList::new();
+        let mut freed = false;
+        loop {
+            let mut drained = false;
+            {
+                let mut state = self.state.lock();
+                let mut any = false;
+                while !wakers.is_full() {
+                    let Some((cid, result)) = self.pop(&mut state) else {
+                        drained = true;
+                        break;
+                    };
+                    any = true;
+                    let Some(slot) = state.slots.get_mut(cid as usize) else {
+                        continue;
+                    };
+                    if slot.abandoned {
+                        *slot = Slot::default();
+                        state.free |= 1 << cid;
+                        freed = true;
+                    } else {
+                        slot.done = Some(result);
+                        if let Some(waker) = slot.waker.take() {
+                            wakers.push(waker);
+                        }
+                    }
+                }
+                if any {
+                    self.ring_cq(&state);
+                }
+            }
+            wakers.wake_all();
+            if drained {
+                break;
+            }
+        }
+        if freed {
+            self.free_slots.wake_all();
+        }
+    }
+}
+
+/// A submitted command; abandons it if dropped before completion.
+struct Request<'a> {
+    queue: &'a QueuePair,
+    cid: u16,
+    finished: bool,
+}
+
+impl Drop for Request<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let mut state = self.queue.state.lock();
+        let slot = &mut state.slots[self.cid as usize];
+        if slot.done.take().is_some() {
+            state.free |= 1 << self.cid;
+            drop(state);
+            self.queue.free_slots.wake_all();
+        } else {
+            slot.waker = None;
+            slot.abandoned = true;
+        }
+    }
+}
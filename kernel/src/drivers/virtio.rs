@@ -0,0 +1,82 @@
+//! Virtio 1.x devices over the modern PCI transport.
+//!
+//! [`VirtioPci`] finds the common, notify, ISR and device configuration
+//! structures through the vendor-specific PCI capabilities, negotiates
+//! features and sets up [`VirtQueue`]s. Each queue gets its own MSI-X
+//! vector, whose handler completes requests and wakes the tasks awaiting
+//! them.
+
+pub mod blk;
+mod pci;
+mod queue;
+
+pub use self::pci::VirtioPci;
+pub use self::queue::{Buffer, VirtQueue};
+
+use crate::pci::driver::Match;
+use crate::pci::MsiError;
+
+pub const VENDOR_ID: u16 = 0x1af4;
+
+pub const DEVICE_NET: u16 = 1;
+pub const DEVICE_BLOCK: u16 = 2;
+pub const DEVICE_CONSOLE: u16 = 3;
+pub const DEVICE_ENTROPY: u16 = 4;
+
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Matches both the modern and the transitional PCI IDs of `device_type`.
+pub const fn matches(device_type: u16) -> [Match; 2] {
+    [
+        Match::id(VENDOR_ID, 0x1040 + device_type),
+        Match::id(VENDOR_ID, transitional_id(device_type)),
+    ]
+}
+
+/// Transitional device IDs were assigned in a different order from the
+/// virtio device types.
+const fn transitional_id(device_type: u16) -> u16 {
+    match device_type {
+        DEVICE_NET => 0x1000,
+        DEVICE_BLOCK => 0x1001,
+        DEVICE_CONSOLE => 0x1003,
+        DEVICE_ENTROPY => 0x1005,
+        _ => 0xffff,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    NotVirtio,
+    MissingCapability(&'static str),
+    /// A configuration structure points outside a mapped memory BAR.
+    BadBar,
+    Msi(MsiError),
+    /// The device did not accept the features we asked for.
+    FeaturesRejected,
+    QueueUnavailable(u16),
+    NoMemory,
+    /// The device set its NEEDS_RESET or FAILED status.
+    DeviceFailed,
+}
+
+impl VirtioError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VirtioError::NotVirtio => "not a virtio 1.x device",
+            VirtioError::MissingCapability(_) => "missing virtio capability",
+            VirtioError::BadBar => "bad BAR",
+            VirtioError::Msi(_) => "MSI-X unavailable",
+            VirtioError::FeaturesRejected => "features rejected",
+            VirtioError::QueueUnavailable(_) => "queue unavailable",
+            VirtioError::NoMemory => "out of memory",
+            VirtioError::DeviceFailed => "device failed",
+        }
+    }
+}
+
+impl From<MsiError> for VirtioError {
+    fn from(err: MsiError) -> Self {
+        VirtioError::Msi(err)
+    }
+}
@@ -0,0 +1,204 @@
+//! Virtio block devices, registered as `vda`, `vdb` and so on.
+
+use super::{VirtQueue, VirtioError, VirtioPci, Buffer, DEVICE_BLOCK};
+use crate::block::{self, BlockDevice, BlockError, BlockFuture};
+use crate::drivers::dma::{DmaPool, PAGE_SIZE};
+use crate::memory;
+use crate::pci::driver::{Driver, Match, ProbeResult};
+use crate::pci::PciFunction;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-blk",
+    matches: &MATCHES,
+    probe,
+};
+
+static MATCHES: [Match; 2] = super::matches(DEVICE_BLOCK);
+
+const F_SEG_MAX: u64 = 1 << 2;
+const F_RO: u64 = 1 << 5;
+const F_BLK_SIZE: u64 = 1 << 6;
+const F_FLUSH: u64 = 1 << 9;
+
+// Device configuration.
+const CONFIG_CAPACITY: u32 = 0;
+const CONFIG_SEG_MAX: u32 = 12;
+const CONFIG_BLK_SIZE: u32 = 20;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+const S_UNSUPP: u8 = 2;
+
+/// Request headers always count 512-byte sectors.
+const VIRTIO_SECTOR: usize = 512;
+/// Largest transfer we issue; data is bounced through one frame per page.
+const MAX_TRANSFER: usize = 64 * 1024;
+
+fn probe(function: &PciFunction) -> ProbeResult {
+    let device = VirtioBlock::new(function).map_err(|err| err.as_str())?;
+    block::register("vd", Arc::new(device));
+    Ok(())
+}
+
+pub struct VirtioBlock {
+    queue: Arc<VirtQueue>,
+    sector_size: usize,
+    capacity: u64,
+    max_sectors: usize,
+    read_only: bool,
+    flush: bool,
+    /// Bounce frames: the first page of a request holds its header and
+    /// status, the rest its data.
+    dma: DmaPool,
+}
+
+enum Data<'a> {
+    None,
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl VirtioBlock {
+    fn new(function: &PciFunction) -> Result<VirtioBlock, VirtioError> {
+        let mut transport = VirtioPci::new(function)?;
+        let features = transport.init(F_SEG_MAX | F_RO | F_BLK_SIZE | F_FLUSH)?;
+        let config = |offset| transport.read_device_config::<u32>(offset).ok_or(VirtioError::MissingCapability("device"));
+
+        let sector_size = match features & F_BLK_SIZE {
+            0 => VIRTIO_SECTOR,
+            _ => config(CONFIG_BLK_SIZE)? as usize,
+        };
+        if !sector_size.is_power_of_two() || !(VIRTIO_SECTOR..=PAGE_SIZE).contains(&sector_size) {
+            return Err(VirtioError::DeviceFailed);
+        }
+        let seg_max = match features & F_SEG_MAX {
+            0 => usize::MAX,
+            _ => config(CONFIG_SEG_MAX)?.max(1) as usize,
+        };
+        let capacity = transport
+            .read_device_config::<u64>(CONFIG_CAPACITY)
+            .ok_or(VirtioError::MissingCapability("device"))?;
+
+        let queue = transport.setup_queue(0, "virtio-blk", 0)?;
+        // A request takes a descriptor for the header, one per data page
+        // and one for the status.
+        if queue.size() <= 2 {
+            return Err(VirtioError::QueueUnavailable(0));
+        }
+        transport.driver_ok()?;
+
+        let pages = (MAX_TRANSFER / PAGE_SIZE).min(seg_max).min(queue.size() as usize - 2);
+        Ok(VirtioBlock {
+            queue,
+            sector_size,
+            capacity: capacity / (sector_size / VIRTIO_SECTOR) as u64,
+            max_sectors: pages * PAGE_SIZE / sector_size,
+            read_only: features & F_RO != 0,
+            flush: features & F_FLUSH != 0,
+            dma: DmaPool::new(),
+        })
+    }
+
+    async fn request(&self, kind: u32, sector: u64, data: Data<'_>) -> Result<(), BlockError> {
+        let len = match &data {
+            Data::None => 0,
+            Data::Read(buf) => buf.len(),
+            Data::Write(buf) => buf.len(),
+        };
+        let mut dma = self.dma.alloc(1 + len.div_ceil(PAGE_SIZE)).ok_or(BlockError::NoMemory)?;
+
+        let header = memory::phys_to_virt(dma.phys(0));
+        let status = header + 16u64;
+        unsafe {
+            let header = header.as_mut_ptr::<u32>();
+            header.write_volatile(kind);
+            header.add(1).write_volatile(0);
+            header.add(2).cast::<u64>().write_volatile(sector * (self.sector_size / VIRTIO_SECTOR) as u64);
+            status.as_mut_ptr::<u8>().write_volatile(0xff);
+        }
+        if let Data::Write(buf) = &data {
+            dma.copy_from(1, buf);
+        }
+
+        let mut buffers = Vec::with_capacity(dma.pages() + 1);
+        buffers.push(Buffer { addr: dma.phys(0), len: 16, writable: false });
+        for page in 1..dma.pages() {
+            buffers.push(Buffer {
+                addr: dma.phys(page),
+                len: (len - (page - 1) * PAGE_SIZE).min(PAGE_SIZE) as u32,
+                writable: matches!(data, Data::Read(_)),
+            });
+        }
+        buffers.push(Buffer { addr: dma.phys(0) + 16u64, len: 1, writable: true });
+
+        dma.start();
+        self.queue.submit(&buffers).await;
+        dma.finish();
+
+        if let Data::Read(buf) = data {
+            dma.copy_to(1, buf);
+        }
+        match unsafe { status.as_ptr::<u8>().read_volatile() } {
+            S_OK => Ok(()),
+            S_UNSUPP => Err(BlockError::Unsupported),
+            _ => Err(BlockError::Io),
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn max_sectors(&self) -> usize {
+        self.max_sectors
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            let count = block::check_range(self, sector, buf.len())?;
+            if count as usize > self.max_sectors {
+                return Err(BlockError::OutOfRange);
+            }
+            self.request(T_IN, sector, Data::Read(buf)).await
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            if self.read_only {
+                return Err(BlockError::ReadOnly);
+            }
+            let count = block::check_range(self, sector, buf.len())?;
+            if count as usize > self.max_sectors {
+                return Err(BlockError::OutOfRange);
+            }
+            self.request(T_OUT, sector, Data::Write(buf)).await
+        })
+    }
+
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move {
+            // Without the feature the device writes through.
+            if !self.flush {
+                return Ok(());
+            }
+            self.request(T_FLUSH, 0, Data::None).await
+        })
+    }
+}
@@ -0,0 +1,270 @@
+use super::queue::VirtQueue;
+use super::{VirtioError, F_VERSION_1, VENDOR_ID};
+use crate::memory;
+use crate::pci::capability::ID_VENDOR;
+use crate::pci::config::{read_config, read_u8};
+use crate::pci::{Bar, MsiX, PciFunction, COMMAND_BUS_MASTER, COMMAND_MEMORY};
+use alloc::sync::Arc;
+use core::ptr;
+use x86_64::VirtAddr;
+
+const CAP_COMMON: u8 = 1;
+const CAP_NOTIFY: u8 = 2;
+const CAP_ISR: u8 = 3;
+const CAP_DEVICE: u8 = 4;
+
+// Common configuration registers.
+const DEVICE_FEATURE_SELECT: u64 = 0x00;
+const DEVICE_FEATURE: u64 = 0x04;
+const DRIVER_FEATURE_SELECT: u64 = 0x08;
+const DRIVER_FEATURE: u64 = 0x0c;
+const MSIX_CONFIG: u64 = 0x10;
+const NUM_QUEUES: u64 = 0x12;
+const DEVICE_STATUS: u64 = 0x14;
+const CONFIG_GENERATION: u64 = 0x15;
+const QUEUE_SELECT: u64 = 0x16;
+const QUEUE_SIZE: u64 = 0x18;
+const QUEUE_MSIX_VECTOR: u64 = 0x1a;
+const QUEUE_ENABLE: u64 = 0x1c;
+const QUEUE_NOTIFY_OFF: u64 = 0x1e;
+const QUEUE_DESC: u64 = 0x20;
+const QUEUE_DRIVER: u64 = 0x28;
+const QUEUE_DEVICE: u64 = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_NEEDS_RESET: u8 = 64;
+const STATUS_FAILED: u8 = 128;
+
+/// Written to an MSI-X vector register to leave the event without one.
+const NO_VECTOR: u16 = 0xffff;
+
+/// Largest queue we set up; keeps the descriptor table in one frame.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+/// A virtio device on the modern PCI transport.
+pub struct VirtioPci {
+    function: PciFunction,
+    common: VirtAddr,
+    notify: VirtAddr,
+    notify_multiplier: u32,
+    isr: VirtAddr,
+    device: Option<(VirtAddr, u32)>,
+    msix: MsiX,
+}
+
+impl VirtioPci {
+    pub fn new(function: &PciFunction) -> Result<VirtioPci, VirtioError> {
+        if function.vendor_id != VENDOR_ID {
+            return Err(VirtioError::NotVirtio);
+        }
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut device = None;
+        for capability in function.capabilities.iter().filter(|capability| capability.id == ID_VENDOR) {
+            let offset = capability.offset;
+            let cfg_type = read_u8(function.address, offset + 3);
+            let bar = read_u8(function.address, offset + 4);
+            let bar_offset = read_config(function.address, offset + 8);
+            let length = read_config(function.address, offset + 12);
+            // The first structure of each type is the preferred one.
+            let slot = match cfg_type {
+                CAP_COMMON => &mut common,
+                CAP_NOTIFY => &mut notify,
+                CAP_ISR => &mut isr,
+                CAP_DEVICE => &mut device,
+                _ => continue,
+            };
+            if slot.is_none() {
+                let multiplier = match cfg_type {
+                    CAP_NOTIFY => read_config(function.address, offset + 16),
+                    _ => 0,
+                };
+                *slot = Some((locate(function, bar, bar_offset, length)?, length, multiplier));
+            }
+        }
+
+        let common = common.ok_or(VirtioError::MissingCapability("common"))?;
+        let notify = notify.ok_or(VirtioError::MissingCapability("notify"))?;
+        let isr = isr.ok_or(VirtioError::MissingCapability("ISR"))?;
+        function.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER);
+        let msix = function.msix()?;
+        Ok(VirtioPci {
+            function: function.clone(),
+            common: common.0,
+            notify: notify.0,
+            notify_multiplier: notify.2,
+            isr: isr.0,
+            device: device.map(|(address, length, _)| (address, length)),
+            msix,
+        })
+    }
+
+    pub fn function(&self) -> &PciFunction {
+        &self.function
+    }
+
+    /// Resets the device and negotiates features: `VERSION_1` plus those
+    /// of `wanted` the device offers. Returns the negotiated set.
+    pub fn init(&mut self, wanted: u64) -> Result<u64, VirtioError> {
+        self.set_status(0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let offered = self.device_features();
+        if offered & F_VERSION_1 == 0 {
+            self.fail();
+            return Err(VirtioError::NotVirtio);
+        }
+        let features = offered & (wanted | F_VERSION_1);
+        self.write32(DRIVER_FEATURE_SELECT, 0);
+        self.write32(DRIVER_FEATURE, features as u32);
+        self.write32(DRIVER_FEATURE_SELECT, 1);
+        self.write32(DRIVER_FEATURE, (features >> 32) as u32);
+
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            return Err(VirtioError::FeaturesRejected);
+        }
+        self.write16(MSIX_CONFIG, NO_VECTOR);
+        Ok(features)
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write32(DEVICE_FEATURE_SELECT, 0);
+        let low = self.read32(DEVICE_FEATURE) as u64;
+        self.write32(DEVICE_FEATURE_SELECT, 1);
+        let high = self.read32(DEVICE_FEATURE) as u64;
+        high << 32 | low
+    }
+
+    pub fn num_queues(&self) -> u16 {
+        self.read16(NUM_QUEUES)
+    }
+
+    /// Sets up queue `index` with its own MSI-X vector on `cpu`. Call after
+    /// [`init`](Self::init) and before [`driver_ok`](Self::driver_ok).
+    pub fn setup_queue(&mut self, index: u16, name: &'static str, cpu: usize) -> Result<Arc<VirtQueue>, VirtioError> {
+        self.write16(QUEUE_SELECT, index);
+        let max_size = self.read16(QUEUE_SIZE);
+        if max_size == 0 || self.read16(QUEUE_ENABLE) != 0 {
+            return Err(VirtioError::QueueUnavailable(index));
+        }
+        // Split queues must have a power-of-two size.
+        let size = 1 << (u16::BITS - 1 - max_size.min(MAX_QUEUE_SIZE).leading_zeros());
+
+        let notify_off = self.read16(QUEUE_NOTIFY_OFF) as u64;
+        let notify = self.notify + notify_off * self.notify_multiplier as u64;
+        let queue = Arc::new(VirtQueue::new(index, size, notify)?);
+
+        // The queue lives as long as the device, so the handler's pointer
+        // stays valid.
+        let data = Arc::into_raw(queue.clone()) as usize;
+        let vector = index as usize;
+        self.msix.route(vector, name, VirtQueue::interrupt, data, cpu)?;
+
+        let (desc, driver, device) = queue.addresses();
+        self.write16(QUEUE_SIZE, size);
+        self.write16(QUEUE_MSIX_VECTOR, vector as u16);
+        if self.read16(QUEUE_MSIX_VECTOR) != vector as u16 {
+            return Err(VirtioError::Msi(crate::pci::MsiError::NoVectors));
+        }
+        self.write64(QUEUE_DESC, desc.as_u64());
+        self.write64(QUEUE_DRIVER, driver.as_u64());
+        self.write64(QUEUE_DEVICE, device.as_u64());
+        self.write16(QUEUE_ENABLE, 1);
+        Ok(queue)
+    }
+
+    /// Tells the device the driver is ready; queues are live from here on.
+    pub fn driver_ok(&mut self) -> Result<(), VirtioError> {
+        self.msix.enable();
+        self.set_status(self.status() | STATUS_DRIVER_OK);
+        match self.status() & (STATUS_NEEDS_RESET | STATUS_FAILED) {
+            0 => Ok(()),
+            _ => Err(VirtioError::DeviceFailed),
+        }
+    }
+
+    fn fail(&self) {
+        self.set_status(self.status() | STATUS_FAILED);
+    }
+
+    /// Reads and clears the ISR status; only meaningful without MSI-X.
+    pub fn isr_status(&self) -> u8 {
+        unsafe { self.isr.as_ptr::<u8>().read_volatile() }
+    }
+
+    /// Reads device-specific configuration, retrying if the device changed
+    /// it in the middle. `None` if the read falls outside the structure.
+    pub fn read_device_config<T: Copy>(&self, offset: u32) -> Option<T> {
+        let (address, length) = self.device?;
+        if offset as usize + size_of::<T>() > length as usize {
+            return None;
+        }
+        let field = (address + offset as u64).as_ptr::<T>();
+        loop {
+            let generation = self.read8(CONFIG_GENERATION);
+            let value = unsafe { ptr::read_volatile(field) };
+            if self.read8(CONFIG_GENERATION) == generation {
+                return Some(value);
+            }
+        }
+    }
+
+    fn status(&self) -> u8 {
+        self.read8(DEVICE_STATUS)
+    }
+
+    fn set_status(&self, status: u8) {
+        unsafe { (self.common + DEVICE_STATUS).as_mut_ptr::<u8>().write_volatile(status) }
+    }
+
+    fn read8(&self, register: u64) -> u8 {
+        unsafe { (self.common + register).as_ptr::<u8>().read_volatile() }
+    }
+
+    fn read16(&self, register: u64) -> u16 {
+        unsafe { (self.common + register).as_ptr::<u16>().read_volatile() }
+    }
+
+    fn read32(&self, register: u64) -> u32 {
+        unsafe { (self.common + register).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write16(&self, register: u64, value: u16) {
+        unsafe { (self.common + register).as_mut_ptr::<u16>().write_volatile(value) }
+    }
+
+    fn write32(&self, register: u64, value: u32) {
+        unsafe { (self.common + register).as_mut_ptr::<u32>().write_volatile(value) }
+    }
+
+    /// 64-bit registers are written as two halves, low first.
+    fn write64(&self, register: u64, value: u64) {
+        self.write32(register, value as u32);
+        self.write32(register + 4, (value >> 32) as u32);
+    }
+}
+
+/// Virtual address of `length` bytes at `offset` into memory BAR `bar`.
+fn locate(function: &PciFunction, bar: u8, offset: u32, length: u32) -> Result<VirtAddr, VirtioError> {
+    let Some(Some(Bar::Memory { address, size, .. })) = function.bars.get(bar as usize) else {
+        return Err(VirtioError::BadBar);
+    };
+    if offset as u64 + length as u64 > *size {
+        return Err(VirtioError::BadBar);
+    }
+    let virt = memory::phys_to_virt(*address + offset as u64);
+    if !memory::is_mapped(virt) {
+        return Err(VirtioError::BadBar);
+    }
+    Ok(virt)
+}
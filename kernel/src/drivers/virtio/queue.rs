@@ -0,0 +1,282 @@
+use super::VirtioError;
+use crate::memory;
+use crate::sync::{IrqMutex, WaitQueue, WakeList};
+use alloc::vec::Vec;
+use core::future::poll_fn;
+use core::sync::atomic::{fence, Ordering};
+use core::task::{Poll, Waker};
+use x86_64::{PhysAddr, VirtAddr};
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// One physically contiguous piece of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: PhysAddr,
+    pub len: u32,
+    /// Whether the device writes this buffer rather than reads it.
+    pub writable: bool,
+}
+
+/// A split virtqueue. Descriptor table, available ring and used ring each
+/// live in their own DMA frame.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    desc: PhysAddr,
+    avail: PhysAddr,
+    used: PhysAddr,
+    notify: VirtAddr,
+    state: IrqMutex<State>,
+    /// Submitters waiting for free descriptors.
+    free_waiters: WaitQueue,
+}
+
+struct State {
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used: u16,
+    /// Indexed by the head descriptor of each chain.
+    slots: Vec<Slot>,
+}
+
+#[derive(Default)]
+struct Slot {
+    waker: Option<Waker>,
+    /// Bytes the device wrote, once it has used the chain.
+    done: Option<u32>,
+    /// The submitter went away; free the chain as soon as it is used.
+    abandoned: bool,
+}
+
+impl VirtQueue {
+    pub(super) fn new(index: u16, size: u16, notify: VirtAddr) -> Result<VirtQueue, VirtioError> {
+        let frame = || memory::allocate_dma_frame().map(|frame| frame.start_address()).ok_or(VirtioError::NoMemory);
+        let queue = VirtQueue {
+            index,
+            size,
+            desc: frame()?,
+            avail: frame()?,
+            used: frame()?,
+            notify,
+            state: IrqMutex::new(State {
+                free_head: 0,
+                num_free: size,
+                avail_idx: 0,
+                last_used: 0,
+                slots: (0..size).map(|_| Slot::default()).collect(),
+            }),
+            free_waiters: WaitQueue::new(),
+        };
+        for i in 0..size {
+            queue.descriptor(i).next = (i + 1) % size;
+        }
+        Ok(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Physical addresses of the descriptor table, available ring and used
+    /// ring.
+    pub(super) fn addresses(&self) -> (PhysAddr, PhysAddr, PhysAddr) {
+        (self.desc, self.avail, self.used)
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn descriptor(&self, index: u16) -> &mut Descriptor {
+        let table = memory::phys_to_virt(self.desc).as_mut_ptr::<Descriptor>();
+        unsafe { &mut *table.add(index as usize) }
+    }
+
+    fn avail_ring(&self) -> *mut u16 {
+        memory::phys_to_virt(self.avail).as_mut_ptr()
+    }
+
+    fn used_ring(&self) -> *mut u16 {
+        memory::phys_to_virt(self.used).as_mut_ptr()
+    }
+
+    /// Hands `buffers` to the device as one request and waits until the
+    /// device has used it, returning how many bytes it wrote.
+    ///
+    /// The buffers must stay valid until the device is done with them, even
+    /// if the returned future is dropped early.
+    pub async fn submit(&self, buffers: &[Buffer]) -> u32 {
+        assert!(!buffers.is_empty() && buffers.len() <= self.size as usize, "bad virtqueue request length");
+        let head = self.free_waiters.wait_until(|| self.try_push(buffers)).await;
+        self.notify();
+
+        let mut request = Request { queue: self, head, finished: false };
+        poll_fn(|cx| {
+            let mut state = self.state.lock();
+            match state.slots[head as usize].done.take() {
+                Some(len) => {
+                    self.free_chain(&mut state, head);
+                    drop(state);
+                    request.finished = true;
+                    self.free_waiters.wake_all();
+                    Poll::Ready(len)
+                }
+                None => {
+                    state.slots[head as usize].waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Fills a descriptor chain and publishes it in the available ring, or
+    /// returns `None` if there are not enough free descriptors.
+    fn try_push(&self, buffers: &[Buffer]) -> Option<u16> {
+        let mut state = self.state.lock();
+        if (state.num_free as usize) < buffers.len() {
+            return None;
+        }
+        let head = state.free_head;
+        let mut index = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let descriptor = self.descriptor(index);
+            descriptor.addr = buffer.addr.as_u64();
+            descriptor.len = buffer.len;
+            descriptor.flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                descriptor.flags |= DESC_F_NEXT;
+            }
+            index = descriptor.next;
+        }
+        state.free_head = index;
+        state.num_free -= buffers.len() as u16;
+        state.slots[head as usize] = Slot::default();
+
+        let ring = self.avail_ring();
+        let avail_idx = state.avail_idx;
+        unsafe {
+            ring.add(2 + (avail_idx % self.size) as usize).write_volatile(head);
+            // The device must see the entry before the new index.
+            fence(Ordering::SeqCst);
+            ring.add(1).write_volatile(avail_idx.wrapping_add(1));
+        }
+        state.avail_idx = avail_idx.wrapping_add(1);
+        Some(head)
+    }
+
+    fn notify(&self) {
+        fence(Ordering::SeqCst);
+        unsafe { self.notify.as_mut_ptr::<u16>().write_volatile(self.index) }
+    }
+
+    /// Returns the chain starting at `head` to the free list.
+    fn free_chain(&self, state: &mut State, head: u16) {
+        let mut tail = head;
+        let mut count = 1;
+        while self.descriptor(tail).flags & DESC_F_NEXT != 0 {
+            tail = self.descriptor(tail).next;
+            count += 1;
+        }
+        self.descriptor(tail).next = state.free_head;
+        state.free_head = head;
+        state.num_free += count;
+    }
+
+    /// Collects finished requests from the used ring and wakes their
+    /// submitters, a batch at a time so nothing is allocated.
+    fn complete(&self) {
+        let mut wakers = WakeList::new();
+        let mut freed = false;
+        loop {
+            let mut drained = false;
+            {
+                let mut state = self.state.lock();
+                let ring = self.used_ring();
+                while !wakers.is_full() {
+                    let used_idx = unsafe { ring.add(1).read_volatile() };
+                    if state.last_used == used_idx {
+                        drained = true;
+                        break;
+                    }
+                    fence(Ordering::SeqCst);
+                    let element = unsafe {
+                        let elements = ring.add(2) as *const UsedElement;
+                        elements.add((state.last_used % self.size) as usize).read_volatile()
+                    };
+                    state.last_used = state.last_used.wrapping_add(1);
+
+                    let head = element.id as u16;
+                    let slot = &mut state.slots[head as usize];
+                    if slot.abandoned {
+                        *slot = Slot::default();
+                        self.free_chain(&mut state, head);
+                        freed = true;
+                    } else {
+                        slot.done = Some(element.len);
+                        if let Some(waker) = slot.waker.take() {
+                            wakers.push(waker);
+                        }
+                    }
+                }
+            }
+            wakers.wake_all();
+            if drained {
+                break;
+            }
+        }
+        if freed {
+            self.free_waiters.wake_all();
+        }
+    }
+
+    /// MSI-X handler; `data` is a pointer to the queue leaked by
+    /// [`VirtioPci::setup_queue`](super::VirtioPci::setup_queue).
+    pub(super) fn interrupt(data: usize) {
+        let queue = unsafe { &*(data as *const VirtQueue) };
+        queue.complete();
+    }
+}
+
+/// An in-flight request; abandons it if dropped before completion.
+struct Request<'a> {
+    queue: &'a VirtQueue,
+    head: u16,
+    finished: bool,
+}
+
+impl Drop for Request<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let mut state = self.queue.state.lock();
+        let slot = &mut state.slots[self.head as usize];
+        if slot.done.take().is_some() {
+            self.queue.free_chain(&mut state, self.head);
+            drop(state);
+            self.queue.free_waiters.wake_all();
+        } else {
+            slot.waker = None;
+            slot.abandoned = true;
+        }
+    }
+}
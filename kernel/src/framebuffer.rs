@@ -1,10 +1,20 @@
+pub mod font;
+pub mod image;
+pub mod pointer;
 pub mod writer;
 
 pub fn init(framebuffer: &'static mut bootloader_api::info::FrameBuffer) {
+    let font = font::select(framebuffer.info().height);
     writer::FRAMEBUFFER.lock().init(framebuffer);
+    writer::FRAMEBUFFER.lock().set_font(font::FontHandle::Static(font));
     writer::FRAMEBUFFER.lock().clear();
 }
 
+/// Switches the console font, e.g. to a [`font::Psf`] loaded after boot.
+pub fn set_font(font: font::FontHandle) {
+    writer::FRAMEBUFFER.lock().set_font(font);
+}
+
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
@@ -0,0 +1,57 @@
+pub mod noto;
+pub mod psf;
+
+pub use self::noto::NotoFont;
+pub use self::psf::{Psf, PsfError};
+
+use alloc::sync::Arc;
+use core::ops::Deref;
+
+/// A monospaced bitmap font the console can render with.
+pub trait Font: Send + Sync {
+    /// Glyph cell width in pixels.
+    fn width(&self) -> usize;
+
+    /// Glyph cell height in pixels.
+    fn height(&self) -> usize;
+
+    /// Calls `plot(x, y, intensity)` for every pixel of the glyph for `c`,
+    /// falling back to a replacement glyph if `c` is not in the font.
+    fn draw(&self, c: char, plot: &mut dyn FnMut(usize, usize, u8));
+}
+
+/// The console font: a built-in one or one loaded at run time, which is
+/// freed once the console switches away from it.
+#[derive(Clone)]
+pub enum FontHandle {
+    Static(&'static dyn Font),
+    Loaded(Arc<dyn Font>),
+}
+
+impl Deref for FontHandle {
+    type Target = dyn Font;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            FontHandle::Static(font) => *font,
+            FontHandle::Loaded(font) => &**font,
+        }
+    }
+}
+
+/// Picks the boot console font.
+///
+/// `font.size=<px>` on the kernel command line wins; otherwise the size is
+/// derived from the framebuffer height.
+pub fn select(height: usize) -> &'static dyn Font {
+    let size = crate::cmdline::get("font.size").and_then(|size| size.parse().ok());
+    match size {
+        Some(size) => NotoFont::for_size(size),
+        None => match height {
+            0..600 => NotoFont::for_size(16),
+            600..800 => NotoFont::for_size(20),
+            800..1440 => NotoFont::for_size(24),
+            _ => NotoFont::for_size(32),
+        },
+    }
+}
@@ -0,0 +1,59 @@
+use super::Font;
+use noto_sans_mono_bitmap::{get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar};
+
+const BACKUP_CHAR: char = '�';
+const FONT_WEIGHT: FontWeight = FontWeight::Regular;
+
+pub static NOTO_16: NotoFont = NotoFont::new(RasterHeight::Size16);
+pub static NOTO_20: NotoFont = NotoFont::new(RasterHeight::Size20);
+pub static NOTO_24: NotoFont = NotoFont::new(RasterHeight::Size24);
+pub static NOTO_32: NotoFont = NotoFont::new(RasterHeight::Size32);
+
+/// The built-in Noto Sans Mono font, pre-rasterized at a fixed height.
+pub struct NotoFont {
+    height: RasterHeight,
+    width: usize,
+}
+
+impl NotoFont {
+    pub const fn new(height: RasterHeight) -> Self {
+        NotoFont {
+            height,
+            width: get_raster_width(FONT_WEIGHT, height),
+        }
+    }
+
+    /// Returns the largest built-in size not taller than `px`, or the
+    /// smallest one.
+    pub fn for_size(px: usize) -> &'static NotoFont {
+        match px {
+            0..20 => &NOTO_16,
+            20..24 => &NOTO_20,
+            24..32 => &NOTO_24,
+            _ => &NOTO_32,
+        }
+    }
+
+    fn raster(&self, c: char) -> RasterizedChar {
+        let get = |c| get_raster(c, FONT_WEIGHT, self.height);
+        get(c).unwrap_or_else(|| get(BACKUP_CHAR).expect("Should get raster of backup char."))
+    }
+}
+
+impl Font for NotoFont {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height.val()
+    }
+
+    fn draw(&self, c: char, plot: &mut dyn FnMut(usize, usize, u8)) {
+        for (y, row) in self.raster(c).raster().iter().enumerate() {
+            for (x, byte) in row.iter().enumerate() {
+                plot(x, y, *byte);
+            }
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! PC Screen Font (PSF1 and PSF2) bitmap fonts.
+//!
+//! A font parses from an embedded `include_bytes!` blob or from a buffer
+//! read from a filesystem, which it then owns.
+
+use super::Font;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::ops::Range;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TAB: u8 = 0x02;
+const PSF1_MODE_SEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQ: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQ: u8 = 0xfe;
+
+const BACKUP_CHARS: [char; 2] = ['�', '?'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsfError {
+    BadMagic,
+    Truncated,
+    BadHeader,
+}
+
+impl fmt::Display for PsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PsfError::BadMagic => "not a PSF font",
+            PsfError::Truncated => "truncated font",
+            PsfError::BadHeader => "bad font header",
+        })
+    }
+}
+
+pub struct Psf {
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+    bytes_per_glyph: usize,
+    length: usize,
+    data: Cow<'static, [u8]>,
+    /// Where the glyph bitmaps are in `data`.
+    glyphs: Range<usize>,
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl Psf {
+    pub fn parse(data: impl Into<Cow<'static, [u8]>>) -> Result<Psf, PsfError> {
+        let data = data.into();
+        if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else {
+            Err(PsfError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(data: Cow<'static, [u8]>) -> Result<Psf, PsfError> {
+        let mode = *data.get(2).ok_or(PsfError::Truncated)?;
+        let height = *data.get(3).ok_or(PsfError::Truncated)? as usize;
+        let length = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        if height == 0 {
+            return Err(PsfError::BadHeader);
+        }
+
+        let glyphs_end = 4 + length * height;
+        if data.len() < glyphs_end {
+            return Err(PsfError::Truncated);
+        }
+
+        let unicode = if mode & (PSF1_MODE_HAS_TAB | PSF1_MODE_SEQ) != 0 {
+            Some(parse_psf1_table(&data[glyphs_end..], length))
+        } else {
+            None
+        };
+
+        Ok(Psf {
+            width: 8,
+            height,
+            bytes_per_row: 1,
+            bytes_per_glyph: height,
+            length,
+            data,
+            glyphs: 4..glyphs_end,
+            unicode,
+        })
+    }
+
+    fn parse_psf2(data: Cow<'static, [u8]>) -> Result<Psf, PsfError> {
+        let field = |index: usize| -> Result<usize, PsfError> {
+            let offset = 4 + index * 4;
+            let bytes = data.get(offset..offset + 4).ok_or(PsfError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let header_size = field(1)?;
+        let flags = field(2)? as u32;
+        let length = field(3)?;
+        let bytes_per_glyph = field(4)?;
+        let height = field(5)?;
+        let width = field(6)?;
+
+        let bytes_per_row = width.div_ceil(8);
+        if width == 0 || height == 0 || length == 0 || bytes_per_glyph < bytes_per_row * height {
+            return Err(PsfError::BadHeader);
+        }
+
+        let glyphs_end = length
+            .checked_mul(bytes_per_glyph)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(PsfError::BadHeader)?;
+        if data.len() < glyphs_end {
+            return Err(PsfError::Truncated);
+        }
+
+        let unicode = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            Some(parse_psf2_table(&data[glyphs_end..], length))
+        } else {
+            None
+        };
+
+        Ok(Psf {
+            width,
+            height,
+            bytes_per_row,
+            bytes_per_glyph,
+            length,
+            data,
+            glyphs: header_size..glyphs_end,
+            unicode,
+        })
+    }
+
+    fn glyph_index(&self, c: char) -> Option<usize> {
+        match &self.unicode {
+            Some(table) => table.get(&c).copied(),
+            None => Some(c as usize).filter(|&index| index < self.length),
+        }
+    }
+
+    fn glyph(&self, c: char) -> &[u8] {
+        let index = self
+            .glyph_index(c)
+            .or_else(|| BACKUP_CHARS.iter().find_map(|&c| self.glyph_index(c)))
+            .unwrap_or(0);
+        let start = self.glyphs.start + index * self.bytes_per_glyph;
+        &self.data[start..start + self.bytes_per_glyph]
+    }
+}
+
+impl Font for Psf {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn draw(&self, c: char, plot: &mut dyn FnMut(usize, usize, u8)) {
+        let glyph = self.glyph(c);
+        for (y, row) in glyph.chunks(self.bytes_per_row).take(self.height).enumerate() {
+            for x in 0..self.width {
+                let set = row[x / 8] & (0x80 >> (x % 8)) != 0;
+                plot(x, y, if set { 0xff } else { 0 });
+            }
+        }
+    }
+}
+
+/// Single code points map to their glyph; combining sequences are skipped.
+fn parse_psf1_table(table: &[u8], length: usize) -> BTreeMap<char, usize> {
+    let mut map = BTreeMap::new();
+    let mut glyph = 0;
+    let mut in_sequence = false;
+
+    for entry in table.as_chunks::<2>().0 {
+        if glyph >= length {
+            break;
+        }
+        match u16::from_le_bytes(*entry) {
+            PSF1_SEPARATOR => {
+                glyph += 1;
+                in_sequence = false;
+            }
+            PSF1_START_SEQ => in_sequence = true,
+            code if !in_sequence => {
+                if let Some(c) = char::from_u32(code as u32) {
+                    map.entry(c).or_insert(glyph);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    map
+}
+
+/// Entries are UTF-8; single code points map to their glyph and combining
+/// sequences are skipped.
+fn parse_psf2_table(table: &[u8], length: usize) -> BTreeMap<char, usize> {
+    let mut map = BTreeMap::new();
+
+    for (glyph, entry) in table.split(|&b| b == PSF2_SEPARATOR).take(length).enumerate() {
+        let singles = entry.split(|&b| b == PSF2_START_SEQ).next().unwrap_or(&[]);
+        if let Ok(singles) = core::str::from_utf8(singles) {
+            for c in singles.chars() {
+                map.entry(c).or_insert(glyph);
+            }
+        }
+    }
+
+    map
+}
@@ -0,0 +1,189 @@
+//! BMP and QOI decoders for the boot logo.
+//!
+//! Pixels are streamed straight to the caller, so images can be drawn before
+//! the heap is set up.
+
+use super::writer::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    UnknownFormat,
+    Truncated,
+    Unsupported,
+    Corrupt,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Bmp {
+        offset: usize,
+        bytes_per_pixel: usize,
+        row_size: usize,
+        bottom_up: bool,
+    },
+    Qoi,
+}
+
+pub struct Image<'a> {
+    data: &'a [u8],
+    format: Format,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Image<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        if data.starts_with(b"BM") {
+            Self::parse_bmp(data)
+        } else if data.starts_with(b"qoif") {
+            Self::parse_qoi(data)
+        } else {
+            Err(ImageError::UnknownFormat)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Calls `plot(x, y, color)` for every pixel of the image. Alpha, if any,
+    /// is blended against black.
+    pub fn draw(&self, plot: &mut dyn FnMut(usize, usize, Color)) -> Result<(), ImageError> {
+        match self.format {
+            Format::Bmp {
+                offset,
+                bytes_per_pixel,
+                row_size,
+                bottom_up,
+            } => {
+                for row in 0..self.height {
+                    let start = offset + row * row_size;
+                    let pixels = self
+                        .data
+                        .get(start..start + self.width * bytes_per_pixel)
+                        .ok_or(ImageError::Truncated)?;
+                    let y = if bottom_up { self.height - 1 - row } else { row };
+                    for (x, bgr) in pixels.chunks_exact(bytes_per_pixel).enumerate() {
+                        plot(x, y, Color::new(bgr[2], bgr[1], bgr[0]));
+                    }
+                }
+                Ok(())
+            }
+            Format::Qoi => self.draw_qoi(plot),
+        }
+    }
+
+    fn parse_bmp(data: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        let u16_at = |offset: usize| -> Result<u16, ImageError> {
+            let bytes = data.get(offset..offset + 2).ok_or(ImageError::Truncated)?;
+            Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let u32_at = |offset: usize| -> Result<u32, ImageError> {
+            let bytes = data.get(offset..offset + 4).ok_or(ImageError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let offset = u32_at(10)? as usize;
+        let width = u32_at(18)? as i32;
+        let height = u32_at(22)? as i32;
+        let bits_per_pixel = u16_at(28)?;
+        let compression = u32_at(30)?;
+
+        // Only uncompressed 24-bit BGR and 32-bit BGRA/BGRX pixel data.
+        const BI_RGB: u32 = 0;
+        const BI_BITFIELDS: u32 = 3;
+        let bytes_per_pixel = match (bits_per_pixel, compression) {
+            (24, BI_RGB) => 3,
+            (32, BI_RGB | BI_BITFIELDS) => 4,
+            _ => return Err(ImageError::Unsupported),
+        };
+        if width <= 0 || height == 0 {
+            return Err(ImageError::Corrupt);
+        }
+
+        let width = width as usize;
+        Ok(Image {
+            data,
+            format: Format::Bmp {
+                offset,
+                bytes_per_pixel,
+                row_size: (width * bytes_per_pixel).div_ceil(4) * 4,
+                bottom_up: height > 0,
+            },
+            width,
+            height: height.unsigned_abs() as usize,
+        })
+    }
+
+    fn parse_qoi(data: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        let header = data.get(..QOI_HEADER_SIZE).ok_or(ImageError::Truncated)?;
+        let width = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+        if width == 0 || height == 0 {
+            return Err(ImageError::Corrupt);
+        }
+        Ok(Image {
+            data,
+            format: Format::Qoi,
+            width,
+            height,
+        })
+    }
+
+    fn draw_qoi(&self, plot: &mut dyn FnMut(usize, usize, Color)) -> Result<(), ImageError> {
+        let mut bytes = self.data[QOI_HEADER_SIZE..].iter().copied();
+        let mut next = || bytes.next().ok_or(ImageError::Truncated);
+
+        let mut index = [[0u8; 4]; 64];
+        let mut pixel = [0u8, 0, 0, 0xff];
+        let mut run = 0;
+
+        for i in 0..self.width * self.height {
+            if run > 0 {
+                run -= 1;
+            } else {
+                let op = next()?;
+                match op {
+                    QOI_OP_RGB => pixel = [next()?, next()?, next()?, pixel[3]],
+                    QOI_OP_RGBA => pixel = [next()?, next()?, next()?, next()?],
+                    _ => match op & 0xc0 {
+                        QOI_OP_INDEX => pixel = index[(op & 0x3f) as usize],
+                        QOI_OP_DIFF => {
+                            pixel[0] = pixel[0].wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                            pixel[1] = pixel[1].wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                            pixel[2] = pixel[2].wrapping_add(op & 0x03).wrapping_sub(2);
+                        }
+                        QOI_OP_LUMA => {
+                            let dg = (op & 0x3f).wrapping_sub(32);
+                            let rb = next()?;
+                            pixel[0] = pixel[0].wrapping_add(dg).wrapping_add(rb >> 4).wrapping_sub(8);
+                            pixel[1] = pixel[1].wrapping_add(dg);
+                            pixel[2] = pixel[2].wrapping_add(dg).wrapping_add(rb & 0x0f).wrapping_sub(8);
+                        }
+                        _ => run = op & 0x3f,
+                    },
+                }
+                let [r, g, b, a] = pixel;
+                let hash = r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11;
+                index[hash % 64] = pixel;
+            }
+
+            let [r, g, b, a] = pixel;
+            let color = Color::new(r, g, b).scale(a);
+            plot(i % self.width, i / self.width, color);
+        }
+
+        Ok(())
+    }
+}
+
+const QOI_HEADER_SIZE: usize = 14;
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
@@ -0,0 +1,25 @@
+//! Mouse pointer shape.
+
+pub const WIDTH: usize = 11;
+pub const HEIGHT: usize = 17;
+
+/// `B` is the black outline, `W` the white fill and `.` transparent.
+pub const SHAPE: [&[u8; WIDTH]; HEIGHT] = [
+    b"B..........",
+    b"BB.........",
+    b"BWB........",
+    b"BWWB.......",
+    b"BWWWB......",
+    b"BWWWWB.....",
+    b"BWWWWWB....",
+    b"BWWWWWWB...",
+    b"BWWWWWWWB..",
+    b"BWWWWWWWWB.",
+    b"BWWWWWBBBBB",
+    b"BWWBWWB....",
+    b"BWB.BWWB...",
+    b"BB..BWWB...",
+    b"B....BWWB..",
+    b".....BWWB..",
+    b"......BB...",
+];
@@ -1,40 +1,56 @@
-use noto_sans_mono_bitmap::{
-    get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar,
-};
-
+use super::font::{FontHandle, noto::NOTO_24};
+use super::image::{Image, ImageError};
+use super::pointer;
 use bootloader_api::info::FrameBuffer;
 use bootloader_api::info::PixelFormat;
-use spin::Mutex;
-
-const CHAR_RASTER_HEIGHT: RasterHeight = RasterHeight::Size24;
-const CHAR_RASTER_WIDTH: usize = get_raster_width(FontWeight::Regular, CHAR_RASTER_HEIGHT);
-const BACKUP_CHAR: char = '�';
-const FONT_WEIGHT: FontWeight = FontWeight::Regular;
+use crate::sync::SpinLock;
 
 const LINE_SPACING: usize = 0;
 const LETTER_SPACING: usize = 0;
 const BORDER_PADDING: usize = 3;
+const CURSOR_HEIGHT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
 
-const LINE_HEIGHT: usize = LINE_SPACING + CHAR_RASTER_HEIGHT.val();
-const FONT_WIDTH: usize = CHAR_RASTER_WIDTH + LETTER_SPACING;
+impl Color {
+    pub const WHITE: Color = Color::new(0xff, 0xff, 0xff);
+    pub const GRAY: Color = Color::new(0x80, 0x80, 0x80);
+    pub const GREEN: Color = Color::new(0x40, 0xd0, 0x40);
+    pub const RED: Color = Color::new(0xf0, 0x40, 0x40);
 
-fn get_char_raster(c: char) -> RasterizedChar {
-    fn get(c: char) -> Option<RasterizedChar> {
-        get_raster(c, FONT_WEIGHT, CHAR_RASTER_HEIGHT)
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    pub(crate) fn scale(self, intensity: u8) -> Color {
+        let scale = |c: u8| (c as u16 * intensity as u16 / 0xff) as u8;
+        Color::new(scale(self.r), scale(self.g), scale(self.b))
     }
-    get(c).unwrap_or_else(|| get(BACKUP_CHAR).expect("Should get raster of backup char."))
 }
 
-pub static FRAMEBUFFER: Mutex<Framebuffer> = Mutex::new(Framebuffer {
-    buffer: None,
-    x_pos: BORDER_PADDING,
-    y_pos: BORDER_PADDING,
-    width: 0,
-    height: 0,
-    pixel_format: PixelFormat::Rgb,
-    bytes_per_pixel: 0,
-    stride: 0,
-});
+pub static FRAMEBUFFER: SpinLock<Framebuffer> = SpinLock::named(
+    "FRAMEBUFFER",
+    Framebuffer {
+        buffer: None,
+        x_pos: BORDER_PADDING,
+        y_pos: BORDER_PADDING,
+        width: 0,
+        height: 0,
+        pixel_format: PixelFormat::Rgb,
+        bytes_per_pixel: 0,
+        stride: 0,
+        font: FontHandle::Static(&NOTO_24),
+        color: Color::WHITE,
+        pointer: None,
+        pointer_drawn: false,
+        pointer_backup: [[0; 4]; pointer::WIDTH * pointer::HEIGHT],
+    },
+);
 
 pub struct Framebuffer {
     buffer: Option<&'static mut [u8]>,
@@ -45,6 +61,13 @@ pub struct Framebuffer {
     pixel_format: PixelFormat,
     bytes_per_pixel: usize,
     stride: usize,
+    font: FontHandle,
+    color: Color,
+    /// Mouse pointer position, if it has been placed.
+    pointer: Option<(usize, usize)>,
+    pointer_drawn: bool,
+    /// Pixels under the pointer while it is drawn.
+    pointer_backup: [[u8; 4]; pointer::WIDTH * pointer::HEIGHT],
 }
 
 impl Framebuffer {
@@ -58,14 +81,101 @@ impl Framebuffer {
         self.buffer = Some(framebuffer.buffer_mut());
     }
 
+    pub fn set_font(&mut self, font: FontHandle) {
+        self.font = font;
+    }
+
+    pub fn font(&self) -> FontHandle {
+        self.font.clone()
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Moves the text cursor to the start of the line at pixel row `y`.
+    pub fn move_to_row(&mut self, y: usize) {
+        self.x_pos = BORDER_PADDING;
+        self.y_pos = y;
+    }
+
+    /// Draws `image` horizontally centered with its top edge at pixel row `y`.
+    pub fn draw_image_centered(&mut self, image: &Image, y: usize) -> Result<(), ImageError> {
+        let x = self.width.saturating_sub(image.width()) / 2;
+        self.hide_pointer();
+        let result = image.draw(&mut |dx, dy, color| {
+            self.set_pixel(x + dx, y + dy, color.r, color.g, color.b)
+        });
+        self.show_pointer();
+        result
+    }
+
+    /// Moves the mouse pointer, showing it if it was not placed yet.
+    pub fn set_pointer(&mut self, x: usize, y: usize) {
+        self.hide_pointer();
+        self.pointer = Some((x, y));
+        self.show_pointer();
+    }
+
+    /// Restores the pixels under the pointer, e.g. before drawing text.
+    pub fn hide_pointer(&mut self) {
+        let Some((x_pos, y_pos)) = self.pointer.filter(|_| self.pointer_drawn) else {
+            return;
+        };
+        self.pointer_drawn = false;
+        for y in 0..pointer::HEIGHT {
+            for x in 0..pointer::WIDTH {
+                let saved = self.pointer_backup[y * pointer::WIDTH + x];
+                self.write_raw_pixel(x_pos + x, y_pos + y, saved);
+            }
+        }
+    }
+
+    /// Draws the pointer on top of the screen contents.
+    pub fn show_pointer(&mut self) {
+        let Some((x_pos, y_pos)) = self.pointer.filter(|_| !self.pointer_drawn) else {
+            return;
+        };
+        self.pointer_drawn = true;
+        for (y, row) in pointer::SHAPE.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                self.pointer_backup[y * pointer::WIDTH + x] =
+                    self.read_raw_pixel(x_pos + x, y_pos + y);
+                match cell {
+                    b'B' => self.set_pixel(x_pos + x, y_pos + y, 0, 0, 0),
+                    b'W' => self.set_pixel(x_pos + x, y_pos + y, 0xff, 0xff, 0xff),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn line_height(&self) -> usize {
+        LINE_SPACING + self.font.height()
+    }
+
+    fn font_width(&self) -> usize {
+        self.font.width() + LETTER_SPACING
+    }
+
     pub fn clear(&mut self) {
         self.x_pos = BORDER_PADDING;
         self.y_pos = BORDER_PADDING;
+        self.pointer_drawn = false;
         self.buffer.as_mut().unwrap().fill(0);
+        self.show_pointer();
     }
 
     fn newline(&mut self) {
-        self.y_pos += LINE_HEIGHT;
+        self.y_pos += self.line_height();
         self.carriage_return()
     }
 
@@ -73,18 +183,41 @@ impl Framebuffer {
         self.x_pos = BORDER_PADDING;
     }
 
+    /// Moves the cursor one cell left, to the end of the previous line if
+    /// it is at the start of a wrapped one.
     pub fn back(&mut self) {
-        self.x_pos -= FONT_WIDTH;
+        if self.x_pos >= BORDER_PADDING + self.font_width() {
+            self.x_pos -= self.font_width();
+        } else if self.y_pos >= BORDER_PADDING + self.line_height() {
+            let columns = (self.width - BORDER_PADDING - 1) / self.font_width();
+            self.y_pos -= self.line_height();
+            self.x_pos = BORDER_PADDING + (columns.max(1) - 1) * self.font_width();
+        }
     }
 
     pub fn forward(&mut self) {
-        self.x_pos += FONT_WIDTH;
+        self.x_pos += self.font_width();
+    }
+
+    /// Draws or erases an underline cursor in the current cell.
+    pub fn draw_cursor(&mut self, visible: bool) {
+        let Color { r, g, b } = if visible { self.color } else { Color::new(0, 0, 0) };
+        let (x_pos, font_width) = (self.x_pos, self.font_width());
+        let y_pos = self.y_pos + self.line_height() - CURSOR_HEIGHT;
+        self.hide_pointer();
+        for y in y_pos..y_pos + CURSOR_HEIGHT {
+            for x in x_pos..x_pos + font_width {
+                self.set_pixel(x, y, r, g, b);
+            }
+        }
+        self.show_pointer();
     }
 
     fn scroll(&mut self) {
-        let lines = self.height / LINE_HEIGHT * LINE_HEIGHT;
-        let src_start = LINE_HEIGHT * self.stride * self.bytes_per_pixel;
-        let bytes_to_copy = (lines - LINE_HEIGHT) * self.stride * self.bytes_per_pixel;
+        let line_height = self.line_height();
+        let lines = self.height / line_height * line_height;
+        let src_start = line_height * self.stride * self.bytes_per_pixel;
+        let bytes_to_copy = (lines - line_height) * self.stride * self.bytes_per_pixel;
 
         unsafe {
             core::ptr::copy(
@@ -96,7 +229,7 @@ impl Framebuffer {
 
         let clear_start = bytes_to_copy;
         self.buffer.as_mut().unwrap()[clear_start..].fill(0);
-        self.y_pos = self.y_pos.saturating_sub(LINE_HEIGHT);
+        self.y_pos = self.y_pos.saturating_sub(line_height);
     }
 
     fn write_char(&mut self, c: char) {
@@ -104,29 +237,29 @@ impl Framebuffer {
             '\n' => self.newline(),
             '\r' => self.carriage_return(),
             c => {
-                let new_xpos = self.x_pos + FONT_WIDTH;
+                let new_xpos = self.x_pos + self.font_width();
                 if new_xpos >= self.width {
                     self.newline();
                 }
 
-                let new_ypos = self.y_pos + LINE_HEIGHT;
+                let new_ypos = self.y_pos + self.line_height();
                 if new_ypos > self.height {
                     //self.y_pos = BORDER_PADDING;
                     self.scroll()
                 }
 
-                self.write_rendered_char(get_char_raster(c));
+                self.write_rendered_char(c);
             }
         }
     }
 
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, byte) in row.iter().enumerate() {
-                self.set_pixel(self.x_pos + x, self.y_pos + y, *byte, *byte, *byte);
-            }
-        }
-        self.x_pos += FONT_WIDTH;
+    fn write_rendered_char(&mut self, c: char) {
+        let (x_pos, y_pos, font) = (self.x_pos, self.y_pos, self.font.clone());
+        font.draw(c, &mut |x, y, byte| {
+            let Color { r, g, b } = self.color.scale(byte);
+            self.set_pixel(x_pos + x, y_pos + y, r, g, b)
+        });
+        self.x_pos += self.font_width();
     }
 
     fn set_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
@@ -142,6 +275,25 @@ impl Framebuffer {
                 .copy_from_slice(&color[..self.bytes_per_pixel])
         }
     }
+
+    fn read_raw_pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let mut pixel = [0; 4];
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            let index = (y * self.stride + x) * self.bytes_per_pixel;
+            pixel[..self.bytes_per_pixel].copy_from_slice(
+                &self.buffer.as_ref().unwrap()[index..(index + self.bytes_per_pixel)],
+            );
+        }
+        pixel
+    }
+
+    fn write_raw_pixel(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            let index = (y * self.stride + x) * self.bytes_per_pixel;
+            self.buffer.as_mut().unwrap()[index..(index + self.bytes_per_pixel)]
+                .copy_from_slice(&pixel[..self.bytes_per_pixel])
+        }
+    }
 }
 
 unsafe impl Send for Framebuffer {}
@@ -149,9 +301,11 @@ unsafe impl Sync for Framebuffer {}
 
 impl core::fmt::Write for Framebuffer {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.hide_pointer();
         for c in s.chars() {
             self.write_char(c);
         }
+        self.show_pointer();
         Ok(())
     }
 }
@@ -0,0 +1,295 @@
+//! The virtual filesystem.
+//!
+//! Filesystems implement [`FileSystem`] and [`Inode`] and are [`mount`]ed
+//! on directories of the tree. Paths are walked through [`Dentry`]s, which
+//! cache the inodes found under each name and record what is mounted where.
+//! Walking handles `.`, `..` and symbolic links and crosses into mounted
+//! filesystems; `..` at the root of a mount leads out of it. Paths are
+//! resolved from the root, with or without a leading `/`.
+//!
+//! [`OpenOptions::open`] returns a [`File`], an open file description with
+//! its own offset. Everything else works on paths directly.
+
+pub mod commands;
+mod dentry;
+mod file;
+pub mod initramfs;
+mod mount;
+mod path;
+pub mod tmpfs;
+
+pub use self::dentry::Dentry;
+pub use self::file::{File, OpenOptions, SeekFrom};
+pub use self::mount::{mount, mounts, Mount};
+
+use self::path::{resolve, resolve_parent};
+use crate::block::BlockError;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotDirectory,
+    IsDirectory,
+    NotEmpty,
+    /// An empty name, `.` or `..` where a new name is needed, or a name
+    /// that is too long.
+    InvalidName,
+    InvalidArgument,
+    /// Symbolic links nest too deep or form a loop.
+    TooManyLinks,
+    /// A rename between different mounts.
+    CrossDevice,
+    /// The entry is a mount point or has one below it.
+    Busy,
+    /// The file was not opened for reading or writing.
+    BadMode,
+    ReadOnly,
+    NoSpace,
+    Unsupported,
+    Io(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(err: BlockError) -> Self {
+        FsError::Io(err)
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::AlreadyExists => "file exists",
+            FsError::NotDirectory => "not a directory",
+            FsError::IsDirectory => "is a directory",
+            FsError::NotEmpty => "directory not empty",
+            FsError::InvalidName => "invalid file name",
+            FsError::InvalidArgument => "invalid argument",
+            FsError::TooManyLinks => "too many levels of symbolic links",
+            FsError::CrossDevice => "cross-device link",
+            FsError::Busy => "device or resource busy",
+            FsError::BadMode => "bad file mode",
+            FsError::ReadOnly => "read-only filesystem",
+            FsError::NoSpace => "no space left on device",
+            FsError::Unsupported => "operation not supported",
+            FsError::Io(err) => return write!(f, "{}", err),
+        };
+        f.write_str(message)
+    }
+}
+
+pub type FsResult<T = ()> = Result<T, FsError>;
+
+pub type FsFuture<'a, T = ()> = Pin<Box<dyn Future<Output = FsResult<T>> + Send + 'a>>;
+
+/// Longest name a directory entry may have, in bytes.
+pub const NAME_MAX: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    /// Inode number, unique within its filesystem.
+    pub ino: u64,
+    pub kind: FileType,
+    /// Size in bytes; for a symbolic link, the length of its target.
+    pub size: u64,
+    pub links: u32,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Directory
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+/// A mountable filesystem.
+pub trait FileSystem: Send + Sync {
+    /// Name of the filesystem type, such as `tmpfs`.
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Writes back anything cached.
+    fn sync(&self) -> FsFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A file, directory or symbolic link of some filesystem.
+///
+/// The VFS checks the inode's type before calling a method, so a regular
+/// file only sees file operations and a directory only directory ones.
+/// Directory methods never see `.` or `..`. Operations a filesystem lacks
+/// fail with [`FsError::Unsupported`].
+pub trait Inode: Any + Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// Reads from `offset` and returns the number of bytes read, zero at
+    /// the end of the file.
+    fn read<'a>(&'a self, _offset: u64, _buf: &'a mut [u8]) -> FsFuture<'a, usize> {
+        unsupported()
+    }
+
+    /// Writes at `offset`, extending the file if needed, and returns the
+    /// number of bytes written.
+    fn write<'a>(&'a self, _offset: u64, _buf: &'a [u8]) -> FsFuture<'a, usize> {
+        unsupported()
+    }
+
+    fn truncate(&self, _size: u64) -> FsFuture<'_> {
+        unsupported()
+    }
+
+    fn lookup<'a>(&'a self, _name: &'a str) -> FsFuture<'a, Arc<dyn Inode>> {
+        unsupported()
+    }
+
+    /// Creates an empty file or directory.
+    fn create<'a>(&'a self, _name: &'a str, _kind: FileType) -> FsFuture<'a, Arc<dyn Inode>> {
+        unsupported()
+    }
+
+    fn symlink<'a>(&'a self, _name: &'a str, _target: &'a str) -> FsFuture<'a, Arc<dyn Inode>> {
+        unsupported()
+    }
+
+    /// Removes an entry; a directory only if it is empty.
+    fn remove<'a>(&'a self, _name: &'a str) -> FsFuture<'a> {
+        unsupported()
+    }
+
+    /// Moves entry `name` to `new_name` in `new_dir`, an inode of the same
+    /// filesystem, replacing what is there. The VFS has checked that a
+    /// replaced entry has the same type as the moved one.
+    fn rename<'a>(&'a self, _name: &'a str, _new_dir: &'a Arc<dyn Inode>, _new_name: &'a str) -> FsFuture<'a> {
+        unsupported()
+    }
+
+    fn read_dir(&self) -> FsFuture<'_, Vec<DirEntry>> {
+        unsupported()
+    }
+
+    fn read_link(&self) -> FsFuture<'_, String> {
+        unsupported()
+    }
+}
+
+fn unsupported<'a, T>() -> FsFuture<'a, T> {
+    Box::pin(async { Err(FsError::Unsupported) })
+}
+
+/// Returns the metadata of what `path` names, following symbolic links.
+pub async fn stat(path: &str) -> FsResult<Metadata> {
+    Ok(resolve(path, true).await?.inode().metadata())
+}
+
+/// Like [`stat`], but describes a final symbolic link itself.
+pub async fn lstat(path: &str) -> FsResult<Metadata> {
+    Ok(resolve(path, false).await?.inode().metadata())
+}
+
+pub async fn read_dir(path: &str) -> FsResult<Vec<DirEntry>> {
+    let dir = resolve(path, true).await?;
+    dir.check_dir()?;
+    dir.inode().read_dir().await
+}
+
+pub async fn mkdir(path: &str) -> FsResult {
+    let (dir, name) = resolve_parent(path).await?;
+    dir.create(&name, FileType::Directory).await.map(drop)
+}
+
+/// Creates a symbolic link at `path` that points to `target`.
+pub async fn symlink(target: &str, path: &str) -> FsResult {
+    let (dir, name) = resolve_parent(path).await?;
+    dir.symlink(&name, target).await.map(drop)
+}
+
+pub async fn read_link(path: &str) -> FsResult<String> {
+    let link = resolve(path, false).await?;
+    if link.inode().metadata().kind != FileType::Symlink {
+        return Err(FsError::InvalidArgument);
+    }
+    link.inode().read_link().await
+}
+
+/// Removes anything but a directory.
+pub async fn unlink(path: &str) -> FsResult {
+    let (dir, name) = resolve_parent(path).await?;
+    let entry = dir.lookup(&name).await?;
+    if entry.inode().metadata().is_dir() {
+        return Err(FsError::IsDirectory);
+    }
+    dir.remove(&name).await
+}
+
+/// Removes an empty directory.
+pub async fn rmdir(path: &str) -> FsResult {
+    let (dir, name) = resolve_parent(path).await?;
+    let entry = dir.lookup(&name).await?;
+    entry.check_dir()?;
+    dir.remove(&name).await
+}
+
+/// Moves `from` to `to`, replacing any file there, or any empty directory
+/// if `from` is a directory.
+pub async fn rename(from: &str, to: &str) -> FsResult {
+    let (from_dir, from_name) = resolve_parent(from).await?;
+    let (to_dir, to_name) = resolve_parent(to).await?;
+    let source = from_dir.lookup(&from_name).await?;
+    // Renaming drops the cached subtree, and with it any mounts inside.
+    if source.has_mounts() {
+        return Err(FsError::Busy);
+    }
+    if from_dir.mount_id() != to_dir.mount_id() {
+        return Err(FsError::CrossDevice);
+    }
+    let is_dir = source.inode().metadata().is_dir();
+    // A directory cannot move into itself.
+    if is_dir && to_dir.ancestors().any(|dentry| Arc::ptr_eq(&dentry, &source)) {
+        return Err(FsError::InvalidArgument);
+    }
+    match to_dir.lookup(&to_name).await {
+        Ok(target) if Arc::ptr_eq(&target, &source) => return Ok(()),
+        Ok(target) if target.has_mounts() => return Err(FsError::Busy),
+        Ok(target) => match (is_dir, target.inode().metadata().is_dir()) {
+            (true, false) => return Err(FsError::NotDirectory),
+            (false, true) => return Err(FsError::IsDirectory),
+            _ => {}
+        },
+        Err(FsError::NotFound) => {}
+        Err(err) => return Err(err),
+    }
+    from_dir.inode().rename(&from_name, &to_dir.inode(), &to_name).await?;
+    from_dir.forget(&from_name);
+    to_dir.forget(&to_name);
+    Ok(())
+}
+
+/// Returns the absolute path of what `path` names, without `.`, `..` or
+/// symbolic links.
+pub async fn canonicalize(path: &str) -> FsResult<String> {
+    Ok(resolve(path, true).await?.path())
+}
@@ -0,0 +1,137 @@
+//! Shell commands for the filesystem.
+
+use crate::fs::{self, FileType, FsError, FsResult};
+use crate::task::shell::command::{self, Command, CommandError, CommandResult};
+use crate::task::shell::exec;
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+const COMMANDS: &[Command] = &[
+    Command { name: "ls", help: "list directory contents", run: ls },
+    Command { name: "cat", help: "print files, or the input", run: cat },
+    Command { name: "stat", help: "show file metadata", run: stat },
+    Command { name: "mkdir", help: "create directories", run: mkdir },
+    Command { name: "rm", help: "remove files", run: rm },
+    Command { name: "rmdir", help: "remove empty directories", run: rmdir },
+    Command { name: "mv", help: "rename a file", run: mv },
+    Command { name: "ln", help: "create a symbolic link", run: ln },
+    Command { name: "mount", help: "list mounted filesystems", run: mount },
+];
+
+pub fn register() {
+    for command in COMMANDS {
+        command::register(*command);
+    }
+}
+
+fn fs_failed(path: &str, err: FsError) -> CommandError {
+    CommandError::Failed(format!("{}: {}", path, err))
+}
+
+/// Runs `op` on each path in `args`, which must not be empty.
+fn each_path(args: &[String], usage: &'static str, op: impl AsyncFn(&str) -> FsResult) -> CommandResult {
+    if args.is_empty() {
+        return Err(CommandError::Usage(usage));
+    }
+    for path in args {
+        command::block_on(op(path))?.map_err(|err| fs_failed(path, err))?;
+    }
+    Ok(())
+}
+
+fn type_char(kind: FileType) -> char {
+    match kind {
+        FileType::Regular => '-',
+        FileType::Directory => 'd',
+        FileType::Symlink => 'l',
+    }
+}
+
+fn ls(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let path = match args {
+        [] => "/",
+        [path] => path.as_str(),
+        _ => return Err(CommandError::Usage("ls [directory]")),
+    };
+    let mut entries = command::block_on(fs::read_dir(path))?.map_err(|err| fs_failed(path, err))?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in entries {
+        let entry_path = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+        let size = command::block_on(fs::lstat(&entry_path))?.map_or(0, |metadata| metadata.size);
+        write!(out, "{} {:8}  {}", type_char(entry.kind), size, entry.name)?;
+        match entry.kind {
+            FileType::Symlink => match command::block_on(fs::read_link(&entry_path))? {
+                Ok(target) => writeln!(out, " -> {}", target)?,
+                Err(_) => writeln!(out)?,
+            },
+            _ => writeln!(out)?,
+        }
+    }
+    Ok(())
+}
+
+fn cat(args: &[String], input: &str, out: &mut dyn Write) -> CommandResult {
+    if args.is_empty() {
+        write!(out, "{}", input)?;
+    }
+    for path in args {
+        command::check_interrupt()?;
+        write!(out, "{}", exec::read_file(path)?)?;
+    }
+    Ok(())
+}
+
+fn stat(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let [path] = args else {
+        return Err(CommandError::Usage("stat <path>"));
+    };
+    let metadata = command::block_on(fs::lstat(path))?.map_err(|err| fs_failed(path, err))?;
+    let kind = match metadata.kind {
+        FileType::Regular => "regular file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symbolic link",
+    };
+    writeln!(out, "path:  {}", path)?;
+    writeln!(out, "type:  {}", kind)?;
+    writeln!(out, "size:  {}", metadata.size)?;
+    writeln!(out, "inode: {}", metadata.ino)?;
+    writeln!(out, "links: {}", metadata.links)?;
+    Ok(())
+}
+
+fn mkdir(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    each_path(args, "mkdir <directory>...", fs::mkdir)
+}
+
+fn rm(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    each_path(args, "rm <file>...", fs::unlink)
+}
+
+fn rmdir(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    each_path(args, "rmdir <directory>...", fs::rmdir)
+}
+
+fn mv(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    let [from, to] = args else {
+        return Err(CommandError::Usage("mv <from> <to>"));
+    };
+    command::block_on(fs::rename(from, to))?.map_err(|err| fs_failed(from, err))
+}
+
+fn ln(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    let [flag, target, path] = args else {
+        return Err(CommandError::Usage("ln -s <target> <link>"));
+    };
+    if flag != "-s" {
+        return Err(CommandError::Failed(String::from("only symbolic links are supported")));
+    }
+    command::block_on(fs::symlink(target, path))?.map_err(|err| fs_failed(path, err))
+}
+
+fn mount(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    for mount in fs::mounts() {
+        writeln!(out, "{} on {}", mount.fs().name(), mount.path())?;
+    }
+    Ok(())
+}
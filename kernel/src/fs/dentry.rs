@@ -0,0 +1,213 @@
+use super::{FileType, FsError, FsResult, Inode, NAME_MAX};
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Dentries alive before a lookup prunes the unused ones from the cache.
+const MAX_DENTRIES: usize = 128;
+
+static DENTRIES: AtomicUsize = AtomicUsize::new(0);
+
+/// A name in the directory tree and the inode it refers to.
+///
+/// Dentries stay cached under their parent once looked up, until the entry
+/// is removed or renamed, or until the cache grows past [`MAX_DENTRIES`]
+/// and nothing else holds them. The root dentry of a mounted filesystem takes the
+/// name and parent of the directory it is mounted on, so paths and `..`
+/// run through mounts without special cases.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    parent: Option<Weak<Dentry>>,
+    /// The mount the inode belongs to.
+    mount_id: u64,
+    children: SpinLock<BTreeMap<String, Arc<Dentry>>>,
+    /// Root of the filesystem mounted here, if any.
+    mounted: SpinLock<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    pub(super) fn new(name: String, inode: Arc<dyn Inode>, parent: Option<Weak<Dentry>>, mount_id: u64) -> Dentry {
+        DENTRIES.fetch_add(1, Ordering::Relaxed);
+        Dentry {
+            name,
+            inode,
+            parent,
+            mount_id,
+            children: SpinLock::new(BTreeMap::new()),
+            mounted: SpinLock::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> Arc<dyn Inode> {
+        self.inode.clone()
+    }
+
+    pub fn mount_id(&self) -> u64 {
+        self.mount_id
+    }
+
+    pub fn parent(&self) -> Option<Arc<Dentry>> {
+        self.parent.as_ref().and_then(Weak::upgrade)
+    }
+
+    /// The dentry's parent, its parent's parent and so on up to the root,
+    /// starting with the dentry itself.
+    pub fn ancestors(self: &Arc<Self>) -> impl Iterator<Item = Arc<Dentry>> {
+        core::iter::successors(Some(self.clone()), |dentry| dentry.parent())
+    }
+
+    /// Absolute path of the dentry, as it was when it was looked up.
+    pub fn path(self: &Arc<Self>) -> String {
+        let names: Vec<Arc<Dentry>> = self.ancestors().collect();
+        let mut path = String::new();
+        for dentry in names.iter().rev().filter(|dentry| !dentry.name.is_empty()) {
+            path.push('/');
+            path.push_str(&dentry.name);
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
+    pub fn is_mountpoint(&self) -> bool {
+        self.mounted.lock().is_some()
+    }
+
+    /// Whether something is mounted here or on a cached dentry below.
+    pub(super) fn has_mounts(&self) -> bool {
+        if self.is_mountpoint() {
+            return true;
+        }
+        let children: Vec<Arc<Dentry>> = self.children.lock().values().cloned().collect();
+        children.iter().any(|child| child.has_mounts())
+    }
+
+    pub(super) fn set_mounted(&self, root: Arc<Dentry>) {
+        *self.mounted.lock() = Some(root);
+    }
+
+    /// Steps into whatever is mounted here, and onto whatever is mounted on
+    /// top of that.
+    pub(super) fn follow_mounts(self: Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self;
+        loop {
+            let mounted = dentry.mounted.lock().clone();
+            match mounted {
+                Some(root) => dentry = root,
+                None => return dentry,
+            }
+        }
+    }
+
+    pub(super) fn check_dir(&self) -> FsResult {
+        match self.inode.metadata().kind {
+            FileType::Directory => Ok(()),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// Looks `name` up in this directory, asking the filesystem unless it
+    /// is cached. Does not step into mounts.
+    pub(super) async fn lookup(self: &Arc<Self>, name: &str) -> FsResult<Arc<Dentry>> {
+        self.check_dir()?;
+        if let Some(child) = self.children.lock().get(name) {
+            return Ok(child.clone());
+        }
+        let inode = self.inode.lookup(name).await?;
+        Ok(self.insert(name, inode))
+    }
+
+    /// Caches `inode` under `name`, or returns what a concurrent lookup
+    /// cached first.
+    fn insert(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        if DENTRIES.load(Ordering::Relaxed) >= MAX_DENTRIES {
+            super::mount::prune();
+        }
+        let mut children = self.children.lock();
+        children
+            .entry(String::from(name))
+            .or_insert_with(|| {
+                Arc::new(Dentry::new(String::from(name), inode, Some(Arc::downgrade(self)), self.mount_id))
+            })
+            .clone()
+    }
+
+    /// Drops every cached dentry below this one that nothing uses.
+    pub(super) fn prune(&self) {
+        let mounted = self.mounted.lock().clone();
+        if let Some(root) = mounted {
+            root.prune();
+        }
+        let children: Vec<Arc<Dentry>> = self.children.lock().values().cloned().collect();
+        for child in &children {
+            child.prune();
+        }
+        drop(children);
+        self.children.lock().retain(|_, child| !child.is_unused());
+    }
+
+    /// Held only by its parent's cache, with no cached children, which
+    /// would hold weak references, and nothing mounted on it.
+    fn is_unused(self: &Arc<Self>) -> bool {
+        Arc::strong_count(self) == 1 && Arc::weak_count(self) == 0 && !self.is_mountpoint()
+    }
+
+    /// Drops the cached entry for `name`.
+    pub(super) fn forget(&self, name: &str) {
+        self.children.lock().remove(name);
+    }
+
+    pub(super) async fn create(self: &Arc<Self>, name: &str, kind: FileType) -> FsResult<Arc<Dentry>> {
+        self.check_new(name).await?;
+        let inode = self.inode.create(name, kind).await?;
+        Ok(self.insert(name, inode))
+    }
+
+    pub(super) async fn symlink(self: &Arc<Self>, name: &str, target: &str) -> FsResult<Arc<Dentry>> {
+        self.check_new(name).await?;
+        let inode = self.inode.symlink(name, target).await?;
+        Ok(self.insert(name, inode))
+    }
+
+    pub(super) async fn remove(self: &Arc<Self>, name: &str) -> FsResult {
+        if self.lookup(name).await?.is_mountpoint() {
+            return Err(FsError::Busy);
+        }
+        self.inode.remove(name).await?;
+        self.forget(name);
+        Ok(())
+    }
+
+    /// Checks that `name` is free to be created in this directory.
+    async fn check_new(self: &Arc<Self>, name: &str) -> FsResult {
+        check_name(name)?;
+        match self.lookup(name).await {
+            Ok(_) => Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Drop for Dentry {
+    fn drop(&mut self) {
+        DENTRIES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(super) fn check_name(name: &str) -> FsResult {
+    match name {
+        "" | "." | ".." => Err(FsError::InvalidName),
+        _ if name.len() > NAME_MAX || name.contains('/') => Err(FsError::InvalidName),
+        _ => Ok(()),
+    }
+}
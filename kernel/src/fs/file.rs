@@ -0,0 +1,230 @@
+use super::path::{resolve, resolve_parent};
+use super::{Dentry, DirEntry, FileType, FsError, FsResult, Metadata};
+use crate::sync::Mutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// How to open a file; reading only, unless set otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenOptions {
+    pub const fn new() -> OpenOptions {
+        OpenOptions { read: true, write: false, append: false, truncate: false, create: false, create_new: false }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Every write goes to the end of the file. Implies `write`.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Empties the file if it exists and is opened for writing.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Creates the file if it does not exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates the file, failing if anything exists under its name.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    pub async fn open(&self, path: &str) -> FsResult<File> {
+        let writable = self.write || self.append;
+        let dentry = match (self.create, self.create_new) {
+            (false, false) => resolve(path, true).await?,
+            (create, create_new) => {
+                let (dir, name) = resolve_parent(path).await?;
+                match dir.create(&name, FileType::Regular).await {
+                    Err(FsError::AlreadyExists) if create && !create_new => resolve(path, true).await?,
+                    result => result?,
+                }
+            }
+        };
+        let metadata = dentry.inode().metadata();
+        if metadata.is_dir() && writable {
+            return Err(FsError::IsDirectory);
+        }
+        if self.truncate && writable && metadata.size > 0 {
+            dentry.inode().truncate(0).await?;
+        }
+        Ok(File {
+            dentry,
+            readable: self.read,
+            writable,
+            append: self.append,
+            offset: Mutex::new(0),
+        })
+    }
+}
+
+/// An open file description: a file, the mode it was opened in and an
+/// offset shared by everyone using the description.
+pub struct File {
+    dentry: Arc<Dentry>,
+    readable: bool,
+    writable: bool,
+    append: bool,
+    /// Held for the whole of a read, write or seek, so each moves the
+    /// offset as a unit.
+    offset: Mutex<u64>,
+}
+
+impl File {
+    /// Opens `path` for reading.
+    pub async fn open(path: &str) -> FsResult<File> {
+        OpenOptions::new().open(path).await
+    }
+
+    /// Opens `path` for writing, creating it or emptying it.
+    pub async fn create(path: &str) -> FsResult<File> {
+        OpenOptions::new().write(true).create(true).truncate(true).open(path).await
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.dentry.inode().metadata()
+    }
+
+    fn check_read(&self) -> FsResult {
+        match (self.readable, self.metadata().kind) {
+            (false, _) => Err(FsError::BadMode),
+            (true, FileType::Directory) => Err(FsError::IsDirectory),
+            (true, _) => Ok(()),
+        }
+    }
+
+    fn check_write(&self) -> FsResult {
+        match self.writable {
+            true => Ok(()),
+            false => Err(FsError::BadMode),
+        }
+    }
+
+    /// Reads at the file offset and advances it.
+    pub async fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        self.check_read()?;
+        let mut offset = self.offset.lock().await;
+        let read = self.dentry.inode().read(*offset, buf).await?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    /// Writes at the file offset, or at the end in append mode, and moves
+    /// the offset past the data.
+    pub async fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        self.check_write()?;
+        let mut offset = self.offset.lock().await;
+        if self.append {
+            *offset = self.metadata().size;
+        }
+        let written = self.dentry.inode().write(*offset, buf).await?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    /// Reads at `offset`, leaving the file offset alone.
+    pub async fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        self.check_read()?;
+        self.dentry.inode().read(offset, buf).await
+    }
+
+    /// Writes at `offset`, leaving the file offset alone.
+    pub async fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.check_write()?;
+        self.dentry.inode().write(offset, buf).await
+    }
+
+    /// Reads from the file offset to the end of the file into `buf`.
+    pub async fn read_to_end(&self, buf: &mut Vec<u8>) -> FsResult<usize> {
+        let start = buf.len();
+        let mut chunk = alloc::vec![0; 4096];
+        loop {
+            match self.read(&mut chunk).await? {
+                0 => return Ok(buf.len() - start),
+                read => buf.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+
+    /// Writes all of `buf`, in as many writes as it takes.
+    pub async fn write_all(&self, mut buf: &[u8]) -> FsResult {
+        while !buf.is_empty() {
+            match self.write(buf).await? {
+                0 => return Err(FsError::NoSpace),
+                written => buf = &buf[written..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the file offset and returns where it ends up. It may point
+    /// past the end, but not before the start.
+    pub async fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let mut offset = self.offset.lock().await;
+        let new = match pos {
+            SeekFrom::Start(start) => Some(start),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.metadata().size.checked_add_signed(delta),
+        };
+        *offset = new.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    pub async fn set_len(&self, size: u64) -> FsResult {
+        self.check_write()?;
+        self.dentry.inode().truncate(size).await
+    }
+
+    pub async fn read_dir(&self) -> FsResult<Vec<DirEntry>> {
+        self.dentry.check_dir()?;
+        self.dentry.inode().read_dir().await
+    }
+
+    /// The path the file was opened under.
+    pub fn path(&self) -> String {
+        self.dentry.path()
+    }
+}
@@ -0,0 +1,140 @@
+//! The initial root filesystem.
+//!
+//! The bootloader loads a cpio archive in the `newc` format as its ramdisk.
+//! At boot a tmpfs is mounted on `/` and the archive is unpacked into it.
+//! Directories, regular files and symbolic links are extracted; other
+//! entries, such as device nodes, are skipped.
+//!
+//! The archive stays in memory and regular files point into it, so they
+//! cost no heap until written. The first write copies the whole file to
+//! the heap, which fails with [`FsError::NoSpace`] for files larger than
+//! the heap has room for.
+
+use super::path::resolve_parent;
+use super::tmpfs::{self, TmpFs};
+use super::{mkdir, mount, symlink, FsError};
+use crate::task;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::str;
+
+const MAGIC: &[u8] = b"070701";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const MODE_TYPE: u32 = 0o170000;
+const MODE_DIR: u32 = 0o040000;
+const MODE_FILE: u32 = 0o100000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+// Fields of the header, in eight hex digit units after the magic.
+const FIELD_MODE: usize = 1;
+const FIELD_FILE_SIZE: usize = 6;
+const FIELD_NAME_SIZE: usize = 11;
+
+#[derive(Debug)]
+pub enum UnpackError {
+    /// The archive is not valid `newc`; carries what is wrong.
+    Malformed(&'static str),
+    /// Creating an entry failed.
+    Fs(String, FsError),
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnpackError::Malformed(reason) => write!(f, "malformed archive: {}", reason),
+            UnpackError::Fs(path, err) => write!(f, "{}: {}", path, err),
+        }
+    }
+}
+
+/// Mounts a tmpfs on `/` and unpacks `archive` into it, if there is one.
+/// Nothing here waits on a device, so this can run before the executor.
+pub fn init(archive: Option<&'static [u8]>) -> Result<(), UnpackError> {
+    task::block_on(async {
+        mount("/", TmpFs::new()).await.map_err(|err| UnpackError::Fs(String::from("/"), err))?;
+        match archive {
+            Some(archive) => unpack(archive).await,
+            None => Ok(()),
+        }
+    })
+}
+
+/// An entry of the archive.
+struct Entry<'a> {
+    name: &'a str,
+    mode: u32,
+    data: &'a [u8],
+}
+
+/// Extracts every entry of `archive` below `/`.
+pub async fn unpack(archive: &'static [u8]) -> Result<(), UnpackError> {
+    let mut rest = archive;
+    loop {
+        let (entry, next) = parse_entry(rest)?;
+        if entry.name == TRAILER {
+            return Ok(());
+        }
+        rest = next;
+        let name = entry.name.trim_start_matches("./").trim_matches('/');
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        let path = format!("/{}", name);
+        extract(&path, &entry).await.map_err(|err| UnpackError::Fs(path, err))?;
+    }
+}
+
+async fn extract(path: &str, entry: &Entry<'static>) -> Result<(), FsError> {
+    match entry.mode & MODE_TYPE {
+        // Directories may already exist, made for an earlier entry.
+        MODE_DIR => match mkdir(path).await {
+            Err(FsError::AlreadyExists) => Ok(()),
+            result => result,
+        },
+        MODE_FILE => {
+            let (dir, name) = resolve_parent(path).await?;
+            tmpfs::add_static(&dir.inode(), &name, entry.data)?;
+            dir.forget(&name);
+            Ok(())
+        }
+        MODE_SYMLINK => {
+            let target = str::from_utf8(entry.data).map_err(|_| FsError::InvalidName)?;
+            symlink(target, path).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Splits the entry at the start of `archive` from the entries after it.
+fn parse_entry(archive: &[u8]) -> Result<(Entry<'_>, &[u8]), UnpackError> {
+    let header = archive.get(..HEADER_SIZE).ok_or(UnpackError::Malformed("truncated header"))?;
+    if !header.starts_with(MAGIC) {
+        return Err(UnpackError::Malformed("bad magic"));
+    }
+    let field = |index: usize| {
+        let start = MAGIC.len() + index * 8;
+        str::from_utf8(&header[start..start + 8])
+            .ok()
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or(UnpackError::Malformed("bad header field"))
+    };
+    let mode = field(FIELD_MODE)?;
+    let file_size = field(FIELD_FILE_SIZE)? as usize;
+    let name_size = field(FIELD_NAME_SIZE)? as usize;
+
+    // The name ends in a NUL; it and the data are padded to four bytes.
+    let name_end = HEADER_SIZE + name_size;
+    let data_start = name_end.next_multiple_of(4);
+    let data_end = data_start + file_size;
+    let name = archive
+        .get(HEADER_SIZE..name_end.saturating_sub(1))
+        .filter(|_| name_size > 0)
+        .ok_or(UnpackError::Malformed("truncated name"))?;
+    let name = str::from_utf8(name).map_err(|_| UnpackError::Malformed("name is not UTF-8"))?;
+    let data = archive.get(data_start..data_end).ok_or(UnpackError::Malformed("truncated data"))?;
+    let next = archive.get(data_end.next_multiple_of(4)..).unwrap_or(&[]);
+    Ok((Entry { name, mode, data }, next))
+}
@@ -0,0 +1,78 @@
+use super::path::resolve;
+use super::{Dentry, FileSystem, FsError, FsResult};
+use crate::sync::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A filesystem mounted somewhere in the tree.
+pub struct Mount {
+    id: u64,
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+impl Mount {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Where the filesystem was mounted.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn fs(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+}
+
+/// The root of the first filesystem mounted, where every walk starts.
+static ROOT: SpinLock<Option<Arc<Dentry>>> = SpinLock::named("fs root", None);
+static MOUNTS: SpinLock<Vec<Arc<Mount>>> = SpinLock::named("mounts", Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub(super) fn root() -> FsResult<Arc<Dentry>> {
+    let root = ROOT.lock().clone().ok_or(FsError::NotFound)?;
+    Ok(root.follow_mounts())
+}
+
+/// Drops the dentries nothing uses from the whole cache.
+pub(super) fn prune() {
+    let root = ROOT.lock().clone();
+    if let Some(root) = root {
+        root.prune();
+    }
+}
+
+/// Mounts `fs` on the directory at `path`, covering what is there. The
+/// first mount must be on `/`.
+pub async fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let path = if ROOT.lock().is_none() {
+        if !path.split('/').all(str::is_empty) {
+            return Err(FsError::NotFound);
+        }
+        let root = Arc::new(Dentry::new(String::new(), fs.root(), None, id));
+        let mut slot = ROOT.lock();
+        if slot.is_some() {
+            return Err(FsError::Busy);
+        }
+        *slot = Some(root);
+        String::from("/")
+    } else {
+        let point = resolve(path, true).await?;
+        point.check_dir()?;
+        let root = Dentry::new(String::from(point.name()), fs.root(), point.parent().as_ref().map(Arc::downgrade), id);
+        point.set_mounted(Arc::new(root));
+        point.path()
+    };
+    MOUNTS.lock().push(Arc::new(Mount { id, path, fs }));
+    Ok(())
+}
+
+/// Mounted filesystems, in the order they were mounted.
+pub fn mounts() -> Vec<Arc<Mount>> {
+    MOUNTS.lock().clone()
+}
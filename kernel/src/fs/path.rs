@@ -0,0 +1,68 @@
+use super::dentry::check_name;
+use super::mount::root;
+use super::{Dentry, FileType, FsError, FsResult};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Symbolic links a single walk may follow.
+const MAX_LINKS: usize = 40;
+
+/// Walks `path` from the root. A symbolic link in the last component is
+/// followed only if `follow` is set; links elsewhere always are.
+pub(super) async fn resolve(path: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+    walk(root()?, path, follow).await
+}
+
+/// Walks to the directory holding the last component of `path` and
+/// returns it with that component, which must be a valid new name.
+pub(super) async fn resolve_parent(path: &str) -> FsResult<(Arc<Dentry>, String)> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    check_name(name)?;
+    let dir = walk(root()?, dir, true).await?;
+    dir.check_dir()?;
+    Ok((dir, String::from(name)))
+}
+
+async fn walk(start: Arc<Dentry>, path: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+    let mut current = start;
+    // Components still to walk, the next one last.
+    let mut pending: Vec<String> = components(path).rev().collect();
+    let mut links = 0;
+    while let Some(name) = pending.pop() {
+        current.check_dir()?;
+        match name.as_str() {
+            "." => {}
+            ".." => {
+                if let Some(parent) = current.parent() {
+                    current = parent.follow_mounts();
+                }
+            }
+            _ => {
+                let child = current.lookup(&name).await?.follow_mounts();
+                let inode = child.inode();
+                if inode.metadata().kind == FileType::Symlink && (follow || !pending.is_empty()) {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return Err(FsError::TooManyLinks);
+                    }
+                    // The target replaces the link's component and is
+                    // relative to the link's directory, unless absolute.
+                    let target = inode.read_link().await?;
+                    if target.starts_with('/') {
+                        current = root()?;
+                    }
+                    pending.extend(components(&target).rev());
+                    continue;
+                }
+                current = child;
+            }
+        }
+    }
+    Ok(current)
+}
+
+fn components(path: &str) -> impl DoubleEndedIterator<Item = String> + '_ {
+    path.split('/').filter(|name| !name.is_empty()).map(String::from)
+}
@@ -0,0 +1,274 @@
+//! A filesystem that keeps everything in memory.
+//!
+//! Files can also point at memory that lives as long as the kernel, such as
+//! the initramfs archive; they are copied to the heap when first changed.
+
+use super::dentry::check_name;
+use super::{DirEntry, FileSystem, FileType, FsError, FsFuture, FsResult, Inode, Metadata};
+use crate::sync::SpinLock;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Inode numbers are unique across all tmpfs instances, which is more
+/// than they need to be.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<TmpFs> {
+        Arc::new(TmpFs { root: TmpInode::new(Data::Dir(BTreeMap::new())) })
+    }
+}
+
+/// Adds a regular file `name` holding `contents` to `dir`, a tmpfs
+/// directory, without copying them. Replaces a file already there.
+pub fn add_static(dir: &Arc<dyn Inode>, name: &str, contents: &'static [u8]) -> FsResult {
+    check_name(name)?;
+    let dir = (&**dir as &dyn Any).downcast_ref::<TmpInode>().ok_or(FsError::Unsupported)?;
+    if dir.get(name)?.is_some_and(|old| old.kind == FileType::Directory) {
+        return Err(FsError::IsDirectory);
+    }
+    if let Some(replaced) = dir.put(name, TmpInode::new(Data::File(Cow::Borrowed(contents))))? {
+        replaced.unlinked.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct TmpInode {
+    ino: u64,
+    /// Matches the variant of `data`, without taking its lock.
+    kind: FileType,
+    /// Removed from its directory; it lives on while files have it open.
+    unlinked: AtomicBool,
+    data: SpinLock<Data>,
+}
+
+enum Data {
+    File(Cow<'static, [u8]>),
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+}
+
+impl TmpInode {
+    fn new(data: Data) -> Arc<TmpInode> {
+        let kind = match data {
+            Data::File(_) => FileType::Regular,
+            Data::Dir(_) => FileType::Directory,
+            Data::Symlink(_) => FileType::Symlink,
+        };
+        Arc::new(TmpInode {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            kind,
+            unlinked: AtomicBool::new(false),
+            data: SpinLock::new(data),
+        })
+    }
+
+    fn get(&self, name: &str) -> FsResult<Option<Arc<TmpInode>>> {
+        match &*self.data.lock() {
+            Data::Dir(entries) => Ok(entries.get(name).cloned()),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// Adds `inode` as `name`, returning the inode it replaces.
+    fn put(&self, name: &str, inode: Arc<TmpInode>) -> FsResult<Option<Arc<TmpInode>>> {
+        match &mut *self.data.lock() {
+            Data::Dir(entries) => Ok(entries.insert(String::from(name), inode)),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    fn take(&self, name: &str) -> FsResult<Arc<TmpInode>> {
+        match &mut *self.data.lock() {
+            Data::Dir(entries) => entries.remove(name).ok_or(FsError::NotFound),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// Makes `contents` owned, with room for at least `len` bytes.
+    fn reserve<'a>(contents: &'a mut Cow<'static, [u8]>, len: usize) -> FsResult<&'a mut Vec<u8>> {
+        if let Cow::Borrowed(borrowed) = *contents {
+            let mut owned = Vec::new();
+            owned.try_reserve(len.max(borrowed.len())).map_err(|_| FsError::NoSpace)?;
+            owned.extend_from_slice(borrowed);
+            *contents = Cow::Owned(owned);
+        }
+        let Cow::Owned(owned) = contents else { unreachable!() };
+        owned.try_reserve(len.saturating_sub(owned.len())).map_err(|_| FsError::NoSpace)?;
+        Ok(owned)
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&*self.data.lock(), Data::Dir(entries) if entries.is_empty())
+    }
+
+    /// Adds a new inode as `name`, which must be free.
+    fn add(&self, name: &str, data: Data) -> FsResult<Arc<dyn Inode>> {
+        let inode = TmpInode::new(data);
+        match &mut *self.data.lock() {
+            Data::Dir(entries) if entries.contains_key(name) => Err(FsError::AlreadyExists),
+            Data::Dir(entries) => {
+                entries.insert(String::from(name), inode.clone());
+                Ok(inode)
+            }
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let data = self.data.lock();
+        let (size, links) = match &*data {
+            Data::File(contents) => (contents.len() as u64, 1),
+            // One link from the parent, one from `.` and one from the `..`
+            // of each subdirectory.
+            Data::Dir(entries) => {
+                let subdirs = entries.values().filter(|inode| inode.kind == FileType::Directory).count();
+                (0, 2 + subdirs as u32)
+            }
+            Data::Symlink(target) => (target.len() as u64, 1),
+        };
+        let links = if self.unlinked.load(Ordering::Relaxed) { 0 } else { links };
+        Metadata { ino: self.ino, kind: self.kind, size, links }
+    }
+
+    fn read<'a>(&'a self, offset: u64, buf: &'a mut [u8]) -> FsFuture<'a, usize> {
+        Box::pin(async move {
+            let Data::File(contents) = &*self.data.lock() else {
+                return Err(FsError::IsDirectory);
+            };
+            let start = contents.len().min(offset as usize);
+            let len = buf.len().min(contents.len() - start);
+            buf[..len].copy_from_slice(&contents[start..start + len]);
+            Ok(len)
+        })
+    }
+
+    fn write<'a>(&'a self, offset: u64, buf: &'a [u8]) -> FsFuture<'a, usize> {
+        Box::pin(async move {
+            let Data::File(contents) = &mut *self.data.lock() else {
+                return Err(FsError::IsDirectory);
+            };
+            let start = usize::try_from(offset).map_err(|_| FsError::NoSpace)?;
+            let end = start.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+            let contents = TmpInode::reserve(contents, end)?;
+            if end > contents.len() {
+                contents.resize(end, 0);
+            }
+            contents[start..end].copy_from_slice(buf);
+            Ok(buf.len())
+        })
+    }
+
+    fn truncate(&self, size: u64) -> FsFuture<'_> {
+        Box::pin(async move {
+            let Data::File(contents) = &mut *self.data.lock() else {
+                return Err(FsError::IsDirectory);
+            };
+            let size = usize::try_from(size).map_err(|_| FsError::NoSpace)?;
+            if let Cow::Borrowed(borrowed) = *contents
+                && size <= borrowed.len()
+            {
+                *contents = Cow::Borrowed(&borrowed[..size]);
+                return Ok(());
+            }
+            let contents = TmpInode::reserve(contents, size)?;
+            contents.resize(size, 0);
+            contents.shrink_to_fit();
+            Ok(())
+        })
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> FsFuture<'a, Arc<dyn Inode>> {
+        Box::pin(async move {
+            let inode: Arc<dyn Inode> = self.get(name)?.ok_or(FsError::NotFound)?;
+            Ok(inode)
+        })
+    }
+
+    fn create<'a>(&'a self, name: &'a str, kind: FileType) -> FsFuture<'a, Arc<dyn Inode>> {
+        Box::pin(async move {
+            let data = match kind {
+                FileType::Regular => Data::File(Cow::Owned(Vec::new())),
+                FileType::Directory => Data::Dir(BTreeMap::new()),
+                FileType::Symlink => return Err(FsError::InvalidArgument),
+            };
+            self.add(name, data)
+        })
+    }
+
+    fn symlink<'a>(&'a self, name: &'a str, target: &'a str) -> FsFuture<'a, Arc<dyn Inode>> {
+        Box::pin(async move { self.add(name, Data::Symlink(String::from(target))) })
+    }
+
+    fn remove<'a>(&'a self, name: &'a str) -> FsFuture<'a> {
+        Box::pin(async move {
+            let inode = self.get(name)?.ok_or(FsError::NotFound)?;
+            if inode.kind == FileType::Directory && !inode.is_empty_dir() {
+                return Err(FsError::NotEmpty);
+            }
+            self.take(name)?.unlinked.store(true, Ordering::Relaxed);
+            Ok(())
+        })
+    }
+
+    fn rename<'a>(&'a self, name: &'a str, new_dir: &'a Arc<dyn Inode>, new_name: &'a str) -> FsFuture<'a> {
+        Box::pin(async move {
+            let new_dir = (&**new_dir as &dyn Any).downcast_ref::<TmpInode>().ok_or(FsError::CrossDevice)?;
+            self.get(name)?.ok_or(FsError::NotFound)?;
+            if let Some(target) = new_dir.get(new_name)?
+                && target.kind == FileType::Directory
+                && !target.is_empty_dir()
+            {
+                return Err(FsError::NotEmpty);
+            }
+            let inode = self.take(name)?;
+            if let Some(replaced) = new_dir.put(new_name, inode)? {
+                replaced.unlinked.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        })
+    }
+
+    fn read_dir(&self) -> FsFuture<'_, Vec<DirEntry>> {
+        Box::pin(async move {
+            let Data::Dir(entries) = &*self.data.lock() else {
+                return Err(FsError::NotDirectory);
+            };
+            Ok(entries
+                .iter()
+                .map(|(name, inode)| DirEntry { name: name.clone(), ino: inode.ino, kind: inode.kind })
+                .collect())
+        })
+    }
+
+    fn read_link(&self) -> FsFuture<'_, String> {
+        Box::pin(async move {
+            match &*self.data.lock() {
+                Data::Symlink(target) => Ok(target.clone()),
+                _ => Err(FsError::InvalidArgument),
+            }
+        })
+    }
+}
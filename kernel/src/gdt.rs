@@ -1,3 +1,4 @@
+use alloc::boxed::Box;
 use core::ptr::addr_of;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::SegmentSelector;
@@ -43,14 +44,34 @@ struct Selectors {
 }
 
 pub fn init() {
+    load(&GDT.0, &GDT.1);
+}
+
+/// Loads a GDT and TSS of its own on an application processor; a TSS can
+/// only be loaded on one CPU at a time.
+pub fn init_ap(double_fault_stack_top: VirtAddr) {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack_top;
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+    let gdt: &'static mut GlobalDescriptorTable = Box::leak(Box::new(GlobalDescriptorTable::new()));
+    let selectors = Selectors {
+        code_selector: gdt.append(Descriptor::kernel_code_segment()),
+        data_selector: gdt.append(Descriptor::kernel_data_segment()),
+        tss_selector: gdt.append(Descriptor::tss_segment(tss)),
+    };
+    load(gdt, &selectors);
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
     use x86_64::instructions::segmentation::{Segment, CS, DS, SS};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        DS::set_reg(GDT.1.data_selector);
-        SS::set_reg(GDT.1.data_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(selectors.code_selector);
+        DS::set_reg(selectors.data_selector);
+        SS::set_reg(selectors.data_selector);
+        load_tss(selectors.tss_selector);
     }
 }
@@ -1,7 +1,12 @@
 mod handler;
 mod index;
+pub mod vector;
+
+pub use self::index::InterruptIndex;
 
 use crate::gdt;
+use crate::smp::{self, MAX_CPUS};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use x86_64::structures::idt::InterruptDescriptorTable;
 
@@ -68,7 +73,28 @@ lazy_static! {
             .set_handler_fn(handler::timer_interrupt_handler);
         idt[index::InterruptIndex::Keyboard.as_u8()]
             .set_handler_fn(handler::keyboard_interrupt_handler);
-        
+        idt[index::InterruptIndex::Mouse.as_u8()]
+            .set_handler_fn(handler::mouse_interrupt_handler);
+        idt[index::InterruptIndex::Wakeup.as_u8()]
+            .set_handler_fn(handler::wakeup_interrupt_handler);
+
+        macro_rules! dynamic_vectors {
+            ($($vector:literal)*) => {
+                $(idt[$vector].set_handler_fn(vector::dynamic_handler::<$vector>);)*
+            };
+        }
+        // Must cover vector::DYNAMIC_START..+vector::DYNAMIC_COUNT.
+        dynamic_vectors! {
+            0x60 0x61 0x62 0x63 0x64 0x65 0x66 0x67
+            0x68 0x69 0x6a 0x6b 0x6c 0x6d 0x6e 0x6f
+            0x70 0x71 0x72 0x73 0x74 0x75 0x76 0x77
+            0x78 0x79 0x7a 0x7b 0x7c 0x7d 0x7e 0x7f
+            0x80 0x81 0x82 0x83 0x84 0x85 0x86 0x87
+            0x88 0x89 0x8a 0x8b 0x8c 0x8d 0x8e 0x8f
+            0x90 0x91 0x92 0x93 0x94 0x95 0x96 0x97
+            0x98 0x99 0x9a 0x9b 0x9c 0x9d 0x9e 0x9f
+        }
+
         idt
     };
 }
@@ -83,4 +109,40 @@ pub fn enable() {
 
 pub fn disable() {
     x86_64::instructions::interrupts::disable();
-}
\ No newline at end of file
+}
+
+static IRQ_DEPTH: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// Marks the current CPU as running a hardware interrupt handler until
+/// dropped.
+pub struct IrqContext(usize);
+
+pub fn enter_irq() -> IrqContext {
+    let cpu = smp::current_cpu();
+    IRQ_DEPTH[cpu].fetch_add(1, Ordering::Relaxed);
+    IrqContext(cpu)
+}
+
+impl Drop for IrqContext {
+    fn drop(&mut self) {
+        IRQ_DEPTH[self.0].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Whether the current CPU is inside a hardware interrupt handler.
+pub fn in_irq() -> bool {
+    IRQ_DEPTH[smp::current_cpu()].load(Ordering::Relaxed) > 0
+}
+
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn count(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how often each vector has fired, skipping vectors that never did.
+pub fn counts() -> impl Iterator<Item = (u8, u64)> {
+    (0..=u8::MAX)
+        .map(|vector| (vector, COUNTS[vector as usize].load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count > 0)
+}
@@ -1,3 +1,4 @@
+use super::index::InterruptIndex;
 use crate::{println, apic};
 use x86_64::structures::idt::InterruptStackFrame;
 use x86_64::structures::idt::PageFaultErrorCode;
@@ -163,6 +164,9 @@ pub extern "x86-interrupt" fn security_exception_handler(
 
 /// #32
 pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::enter_irq();
+    super::count(InterruptIndex::Timer.as_u8());
+    crate::time::TICK.wake_all();
     apic::lapic::LAPIC.lock().end_inferrupts();
 }
 
@@ -170,9 +174,34 @@ pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptSta
 pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
     use x86_64::instructions::port::Port;
 
+    let _irq = super::enter_irq();
+    super::count(InterruptIndex::Keyboard.as_u8());
+
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
 
     apic::lapic::LAPIC.lock().end_inferrupts();
 }
+
+/// #44
+pub extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    let _irq = super::enter_irq();
+    super::count(InterruptIndex::Mouse.as_u8());
+
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    crate::drivers::mouse::add_byte(byte);
+
+    apic::lapic::LAPIC.lock().end_inferrupts();
+}
+
+/// #240
+pub extern "x86-interrupt" fn wakeup_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::enter_irq();
+    // Returning from the interrupt is enough to leave `hlt`.
+    super::count(InterruptIndex::Wakeup.as_u8());
+    apic::lapic::LAPIC.lock().end_inferrupts();
+}
@@ -3,10 +3,23 @@
 pub enum InterruptIndex {
     Timer = 32,
     Keyboard = 33,
+    Mouse = 44,
+    /// IPI that wakes an idle CPU to run new work.
+    Wakeup = 0xf0,
 }
 
 impl InterruptIndex {
     pub fn as_u8(self) -> u8 {
         self as u8
     }
+
+    pub fn from_u8(vector: u8) -> Option<Self> {
+        match vector {
+            32 => Some(InterruptIndex::Timer),
+            33 => Some(InterruptIndex::Keyboard),
+            44 => Some(InterruptIndex::Mouse),
+            0xf0 => Some(InterruptIndex::Wakeup),
+            _ => None,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,76 @@
+//! Vectors handed out at run time, e.g. for MSI and MSI-X.
+//!
+//! The IDT routes every vector in the dynamic range to a stub that looks up
+//! the handler registered for it. Handlers take the `usize` registered with
+//! them, typically a pointer or index identifying the device queue.
+
+use crate::apic::lapic::LAPIC;
+use crate::sync::SpinLock;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+
+pub const DYNAMIC_START: u8 = 0x60;
+pub const DYNAMIC_COUNT: usize = 64;
+
+pub type Handler = fn(usize);
+
+/// Bit `i` is set while vector `DYNAMIC_START + i` is allocated.
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+/// Handler function pointers; zero for none.
+static HANDLERS: [AtomicUsize; DYNAMIC_COUNT] = [const { AtomicUsize::new(0) }; DYNAMIC_COUNT];
+static DATA: [AtomicUsize; DYNAMIC_COUNT] = [const { AtomicUsize::new(0) }; DYNAMIC_COUNT];
+static NAMES: SpinLock<[Option<&'static str>; DYNAMIC_COUNT]> =
+    SpinLock::named("interrupt vector names", [None; DYNAMIC_COUNT]);
+
+/// Reserves a vector that calls `handler(data)` on every interrupt.
+pub fn allocate(name: &'static str, handler: Handler, data: usize) -> Option<u8> {
+    let mut allocated = ALLOCATED.load(Ordering::Relaxed);
+    let index = loop {
+        let index = (!allocated).trailing_zeros() as usize;
+        if index >= DYNAMIC_COUNT {
+            return None;
+        }
+        match ALLOCATED.compare_exchange_weak(allocated, allocated | 1 << index, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => break index,
+            Err(current) => allocated = current,
+        }
+    };
+    DATA[index].store(data, Ordering::Relaxed);
+    HANDLERS[index].store(handler as usize, Ordering::Release);
+    NAMES.lock()[index] = Some(name);
+    Some(DYNAMIC_START + index as u8)
+}
+
+/// Releases `vector`; the device must no longer send it.
+pub fn free(vector: u8) {
+    let Some(index) = index(vector) else {
+        return;
+    };
+    HANDLERS[index].store(0, Ordering::Release);
+    NAMES.lock()[index] = None;
+    ALLOCATED.fetch_and(!(1 << index), Ordering::AcqRel);
+}
+
+/// Name given to a dynamic vector when it was allocated.
+pub fn name(vector: u8) -> Option<&'static str> {
+    NAMES.lock()[index(vector)?]
+}
+
+fn index(vector: u8) -> Option<usize> {
+    let index = vector.checked_sub(DYNAMIC_START)? as usize;
+    (index < DYNAMIC_COUNT).then_some(index)
+}
+
+pub(super) extern "x86-interrupt" fn dynamic_handler<const VECTOR: u8>(_stack_frame: InterruptStackFrame) {
+    let _irq = super::enter_irq();
+    super::count(VECTOR);
+
+    let index = (VECTOR - DYNAMIC_START) as usize;
+    let handler = HANDLERS[index].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: Handler = unsafe { core::mem::transmute::<usize, Handler>(handler) };
+        handler(DATA[index].load(Ordering::Relaxed));
+    }
+
+    LAPIC.lock().end_inferrupts();
+}
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(abi_x86_interrupt)]
 #![feature(allocator_api)]
 
@@ -6,34 +6,80 @@ extern crate alloc;
 
 pub mod allocator;
 pub mod apic;
+pub mod backtrace;
+pub mod block;
+pub mod boot;
+pub mod cmdline;
+pub mod cpu;
+pub mod drivers;
 pub mod framebuffer;
+pub mod fs;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod pci;
+pub mod power;
+pub mod smp;
+pub mod sync;
 pub mod task;
+pub mod time;
 
 use crate::memory::BootInfoFrameAllocator;
+use acpi::AcpiError;
 use bootloader_api::info::FrameBuffer;
 use bootloader_api::BootInfo;
 use x86_64::VirtAddr;
 
 pub fn init(boot_info: &'static mut BootInfo) {
+    smp::init_bsp();
+
     // Init framebuffer
     let fb_option: Option<&'static mut FrameBuffer> = boot_info.framebuffer.as_mut();
     framebuffer::init(fb_option.unwrap());
+    boot::splash();
+
+    boot::try_stage("TSC calibration", time::init);
 
     // Init interrupts
-    gdt::init();
-    interrupts::init();
+    boot::stage("GDT", gdt::init);
+    boot::stage("IDT", interrupts::init);
 
     // Init memory and allocator
-    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    let (mut mapper, mut frame_allocator) = boot::try_stage("Memory", || {
+        let phys_mem_offset = boot_info
+            .physical_memory_offset
+            .as_ref()
+            .ok_or("no physical memory mapping")?;
+        let mapper = unsafe { memory::init(VirtAddr::new(*phys_mem_offset)) };
+        let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+        smp::reserve_trampoline(&mut frame_allocator);
+        Ok::<_, &str>((mapper, frame_allocator))
+    });
+    boot::try_stage("Heap", || allocator::init_heap(&mut mapper, &mut frame_allocator));
+    *memory::FRAME_ALLOCATOR.lock() = Some(frame_allocator);
 
     // Init LAPIC
-    apic::init(boot_info.rsdp_addr.as_ref().unwrap());
+    boot::try_stage("APIC", || {
+        let rsdp_addr = boot_info.rsdp_addr.as_ref().ok_or(AcpiError::NoValidRsdp)?;
+        apic::init(rsdp_addr)
+    });
+
+    boot::try_stage("SMP", || smp::start_aps(&mut mapper));
+    boot::stage("PCI", pci::init);
+    boot::stage("Drivers", drivers::register);
+    boot::try_stage("Initramfs", || {
+        // The bootloader maps the ramdisk into our address space.
+        let ramdisk = boot_info.ramdisk_addr.as_ref().map(|&addr| unsafe {
+            core::slice::from_raw_parts(addr as *const u8, boot_info.ramdisk_len as usize)
+        });
+        fs::initramfs::init(ramdisk)
+    });
+    boot::try_stage("PS/2 controller", drivers::i8042::init);
+
+    // Shell commands of the subsystems; the shell adds its own when it starts.
+    fs::commands::register();
+    block::commands::register();
+    sync::lockdep::register_command();
 
     // Enable interrupts
     interrupts::enable();
@@ -20,13 +20,17 @@ entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     kernel::init(boot_info);
 
-    use kernel::task::keyboard::print_keypresses;
+    use kernel::drivers::mouse;
+    use kernel::task::keyboard::process_scancodes;
     use kernel::task::shell::shell;
-    use kernel::task::{executor::Executor, Task};
+    use kernel::task::{self, executor::Executor, Priority};
+
+    task::spawn_with_priority("keyboard", Priority::BottomHalf, process_scancodes());
+    task::spawn_with_priority("mouse", Priority::BottomHalf, mouse::process_packets());
+    task::spawn("pointer", mouse::pointer());
+    task::spawn("shell", shell());
 
     let mut executor = Executor::new();
-    executor.spawn(Task::new(print_keypresses()));
-    executor.spawn(Task::new(shell()));
     executor.run();
 }
 
@@ -1,4 +1,5 @@
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use crate::sync::SpinLock;
 use x86_64::{
     PhysAddr, VirtAddr,
     structures::paging::{FrameAllocator, PhysFrame, Size4KiB},
@@ -7,6 +8,9 @@ use x86_64::{
 
 static mut PHYSICAL_MEMORY_OFFSET: VirtAddr = VirtAddr::zero();
 
+/// The frame allocator handed over by [`crate::init`] once the heap is set up.
+pub static FRAME_ALLOCATOR: SpinLock<Option<BootInfoFrameAllocator>> = SpinLock::named("FRAME_ALLOCATOR", None);
+
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
@@ -55,6 +59,27 @@ impl BootInfoFrameAllocator {
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
         frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
+
+    /// Allocates a frame below `limit`, e.g. for real-mode code, if the next
+    /// free frames include one. Frames skipped on the way are not reused.
+    pub fn allocate_frame_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        let (index, frame) = self
+            .usable_frames()
+            .enumerate()
+            .skip(self.next)
+            .take_while(|(_, frame)| frame.start_address() < limit)
+            .find(|(_, frame)| frame.start_address().as_u64() != 0)?;
+        self.next = index + 1;
+        Some(frame)
+    }
+
+    pub fn allocated_frames(&self) -> usize {
+        self.next
+    }
+
+    pub fn usable_frame_count(&self) -> usize {
+        self.usable_frames().count()
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
@@ -65,6 +90,26 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+/// Allocates a zeroed frame for device DMA, reachable by the CPU through
+/// [`phys_to_virt`]. Frames are never freed.
+pub fn allocate_dma_frame() -> Option<PhysFrame> {
+    let frame = FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()?;
+    unsafe { phys_to_virt(frame.start_address()).as_mut_ptr::<u8>().write_bytes(0, 4096) };
+    Some(frame)
+}
+
+/// Whether `addr` is mapped in the active page table.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    use x86_64::structures::paging::Translate;
+
+    let offset = unsafe { PHYSICAL_MEMORY_OFFSET };
+    if offset.is_null() {
+        return false;
+    }
+    let table = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    table.translate_addr(addr).is_some()
+}
+
 pub fn phys_to_virt(phys_addr: PhysAddr) -> VirtAddr {
     unsafe { VirtAddr::new(phys_addr.as_u64() + PHYSICAL_MEMORY_OFFSET.as_u64()) }
 }
@@ -0,0 +1,237 @@
+//! PCI and PCI Express enumeration.
+//!
+//! [`init`] finds the ECAM windows in the ACPI MCFG table, falling back to
+//! the legacy configuration ports when there are none, then scans every bus
+//! once. The functions found are kept in a registry with their decoded BARs
+//! and capabilities, and offered to the drivers registered through
+//! [`driver::register`].
+
+pub mod bar;
+pub mod capability;
+pub mod config;
+pub mod driver;
+pub mod msi;
+
+pub use self::bar::Bar;
+pub use self::capability::{Capability, ExtendedCapability};
+pub use self::config::read_config;
+pub use self::msi::{Msi, MsiError, MsiX};
+
+use self::config::{read_u16, read_u8, write_u16, EcamRegion};
+use crate::sync::SpinLock;
+use crate::{apic, memory};
+use acpi::mcfg::Mcfg;
+use alloc::vec::Vec;
+use core::fmt;
+use x86_64::PhysAddr;
+
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:02x}:{:02x}.{}", self.segment, self.bus, self.device, self.function)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PciFunction {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    /// Legacy INTx pin, 1 for INTA# to 4 for INTD#; 0 if none.
+    pub interrupt_pin: u8,
+    pub interrupt_line: u8,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    pub extended_capabilities: Vec<ExtendedCapability>,
+}
+
+impl PciFunction {
+    pub fn capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities.iter().copied().find(|capability| capability.id == id)
+    }
+
+    /// Sets `bits` in the command register, e.g. [`COMMAND_BUS_MASTER`].
+    pub fn enable(&self, bits: u16) {
+        let command = read_u16(self.address, COMMAND);
+        write_u16(self.address, COMMAND, command | bits);
+    }
+
+    pub fn disable(&self, bits: u16) {
+        let command = read_u16(self.address, COMMAND);
+        write_u16(self.address, COMMAND, command & !bits);
+    }
+
+    pub fn msi(&self) -> Result<Msi, MsiError> {
+        Msi::new(self)
+    }
+
+    pub fn msix(&self) -> Result<MsiX, MsiError> {
+        MsiX::new(self)
+    }
+}
+
+struct Entry {
+    function: PciFunction,
+    driver: Option<&'static str>,
+}
+
+static FUNCTIONS: SpinLock<Vec<Entry>> = SpinLock::named("PCI functions", Vec::new());
+
+/// Scans the buses, fills the registry and binds registered drivers.
+/// Returns the number of functions found.
+pub fn init() -> usize {
+    config::init(ecam_regions());
+    let functions = scan();
+    let count = functions.len();
+    *FUNCTIONS.lock() = functions
+        .iter()
+        .cloned()
+        .map(|function| Entry { function, driver: None })
+        .collect();
+    for function in &functions {
+        driver::bind(function);
+    }
+    count
+}
+
+/// ECAM windows from the MCFG table that the physical memory mapping covers.
+fn ecam_regions() -> Vec<EcamRegion> {
+    let Ok(tables) = apic::acpi_tables() else {
+        return Vec::new();
+    };
+    let Ok(mcfg) = tables.find_table::<Mcfg>() else {
+        return Vec::new();
+    };
+    mcfg.entries()
+        .iter()
+        .map(|&entry| EcamRegion {
+            segment: entry.pci_segment_group,
+            buses: entry.bus_number_start..=entry.bus_number_end,
+            base: PhysAddr::new(entry.base_address),
+        })
+        .filter(|region| {
+            let size = ((*region.buses.end() - *region.buses.start()) as u64 + 1) << 20;
+            memory::is_mapped(memory::phys_to_virt(region.base))
+                && memory::is_mapped(memory::phys_to_virt(region.base + (size - 1)))
+        })
+        .collect()
+}
+
+/// Every function found by [`init`].
+pub fn functions() -> Vec<PciFunction> {
+    FUNCTIONS.lock().iter().map(|entry| entry.function.clone()).collect()
+}
+
+pub fn find(predicate: impl Fn(&PciFunction) -> bool) -> Option<PciFunction> {
+    FUNCTIONS
+        .lock()
+        .iter()
+        .find(|entry| predicate(&entry.function))
+        .map(|entry| entry.function.clone())
+}
+
+/// Name of the driver bound to the function at `address`.
+pub fn driver_of(address: PciAddress) -> Option<&'static str> {
+    FUNCTIONS
+        .lock()
+        .iter()
+        .find(|entry| entry.function.address == address)
+        .and_then(|entry| entry.driver)
+}
+
+fn unbound() -> Vec<PciFunction> {
+    FUNCTIONS
+        .lock()
+        .iter()
+        .filter(|entry| entry.driver.is_none())
+        .map(|entry| entry.function.clone())
+        .collect()
+}
+
+fn set_driver(address: PciAddress, driver: &'static str) {
+    if let Some(entry) = FUNCTIONS.lock().iter_mut().find(|entry| entry.function.address == address) {
+        entry.driver = Some(driver);
+    }
+}
+
+fn probe(address: PciAddress) -> Option<PciFunction> {
+    let id = read_config(address, 0x00);
+    let vendor_id = id as u16;
+    if vendor_id == 0xffff {
+        return None;
+    }
+    let class = read_config(address, 0x08);
+    let header_type = read_u8(address, 0x0e) & 0x7f;
+    let (bar_count, subsystem) = match header_type {
+        0x00 => (6, read_config(address, 0x2c)),
+        0x01 => (2, 0),
+        _ => (0, 0),
+    };
+    Some(PciFunction {
+        address,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+        header_type,
+        subsystem_vendor_id: subsystem as u16,
+        subsystem_id: (subsystem >> 16) as u16,
+        interrupt_line: read_u8(address, 0x3c),
+        interrupt_pin: read_u8(address, 0x3d),
+        bars: bar::read_bars(address, bar_count),
+        capabilities: capability::read_capabilities(address),
+        extended_capabilities: capability::read_extended_capabilities(address),
+    })
+}
+
+/// Brute-force scan of every bus, device and function.
+fn scan() -> Vec<PciFunction> {
+    let mut functions = Vec::new();
+    for (segment, buses) in config::bus_ranges() {
+        for bus in buses {
+            for device in 0..32 {
+                let address = PciAddress {
+                    segment,
+                    bus,
+                    device,
+                    function: 0,
+                };
+                let Some(function) = probe(address) else {
+                    continue;
+                };
+                functions.push(function);
+
+                if read_u8(address, 0x0e) & 0x80 != 0 {
+                    for function in 1..8 {
+                        functions.extend(probe(PciAddress { function, ..address }));
+                    }
+                }
+            }
+        }
+    }
+    functions
+}
@@ -0,0 +1,88 @@
+//! Base address registers.
+
+use super::config::{read_config, read_u16, write_config, write_u16};
+use super::{PciAddress, COMMAND, COMMAND_IO, COMMAND_MEMORY};
+use x86_64::PhysAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: PhysAddr,
+        size: u64,
+        prefetchable: bool,
+        is_64: bool,
+    },
+    Io {
+        port: u16,
+        size: u32,
+    },
+}
+
+impl Bar {
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory { size, .. } => size,
+            Bar::Io { size, .. } => size as u64,
+        }
+    }
+}
+
+/// Decodes the `count` BARs of a function, sizing each by writing all ones.
+/// The upper half of a 64-bit BAR reads as `None`.
+pub(super) fn read_bars(address: PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+
+    // Stop the function from decoding the all-ones probe addresses.
+    let command = read_u16(address, COMMAND);
+    write_u16(address, COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+
+    let mut index = 0;
+    while index < count {
+        let offset = 0x10 + index as u16 * 4;
+        let low = read_config(address, offset);
+        if low & 1 == 1 {
+            let mask = probe(address, offset, low) & !0x3;
+            if mask != 0 {
+                bars[index] = Some(Bar::Io {
+                    port: (low & !0x3) as u16,
+                    size: (!mask & 0xffff) + 1,
+                });
+            }
+            index += 1;
+            continue;
+        }
+
+        let is_64 = (low >> 1) & 0x3 == 0x2 && index + 1 < count;
+        let mut base = (low & !0xf) as u64;
+        let mut mask = (probe(address, offset, low) & !0xf) as u64;
+        if is_64 {
+            let high = read_config(address, offset + 4);
+            base |= (high as u64) << 32;
+            mask |= (probe(address, offset + 4, high) as u64) << 32;
+        } else {
+            mask |= 0xffff_ffff_0000_0000;
+        }
+        // A 64-bit BAR of 4 GiB or more has no size bits in its low half.
+        if mask & 0xffff_ffff != 0 || (is_64 && mask != 0) {
+            bars[index] = Some(Bar::Memory {
+                address: PhysAddr::new(base),
+                size: !mask + 1,
+                prefetchable: low & 0x8 != 0,
+                is_64,
+            });
+        }
+        index += if is_64 { 2 } else { 1 };
+    }
+
+    write_u16(address, COMMAND, command);
+    bars
+}
+
+/// Returns the size mask read back after writing all ones, restoring the
+/// original value.
+fn probe(address: PciAddress, offset: u16, original: u32) -> u32 {
+    write_config(address, offset, u32::MAX);
+    let mask = read_config(address, offset);
+    write_config(address, offset, original);
+    mask
+}
@@ -0,0 +1,101 @@
+//! Capability lists in configuration space.
+
+use super::config::{read_config, read_u16, read_u8};
+use super::{PciAddress, STATUS};
+use alloc::vec::Vec;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const CAPABILITIES_POINTER: u16 = 0x34;
+const EXTENDED_START: u16 = 0x100;
+
+pub const ID_POWER_MANAGEMENT: u8 = 0x01;
+pub const ID_MSI: u8 = 0x05;
+pub const ID_VENDOR: u8 = 0x09;
+pub const ID_PCI_EXPRESS: u8 = 0x10;
+pub const ID_MSIX: u8 = 0x11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of the capability header in configuration space.
+    pub offset: u16,
+}
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            ID_POWER_MANAGEMENT => "power management",
+            0x03 => "VPD",
+            ID_MSI => "MSI",
+            ID_VENDOR => "vendor specific",
+            0x0d => "bridge subsystem ID",
+            ID_PCI_EXPRESS => "PCI Express",
+            ID_MSIX => "MSI-X",
+            0x12 => "SATA",
+            0x13 => "advanced features",
+            _ => "?",
+        }
+    }
+}
+
+/// PCI Express extended capability, only reachable through ECAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+impl ExtendedCapability {
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            0x0001 => "advanced error reporting",
+            0x0002 => "virtual channel",
+            0x0003 => "device serial number",
+            0x000b => "vendor specific",
+            0x000d => "ACS",
+            0x000e => "ARI",
+            0x0010 => "SR-IOV",
+            0x0015 => "resizable BAR",
+            0x0018 => "LTR",
+            0x001e => "L1 PM substates",
+            _ => "?",
+        }
+    }
+}
+
+pub(super) fn read_capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if read_u16(address, STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+    let mut offset = (read_u8(address, CAPABILITIES_POINTER) & 0xfc) as u16;
+    // At most 48 capabilities fit; the bound guards against loops.
+    while offset >= 0x40 && capabilities.len() < 48 {
+        let header = read_u16(address, offset);
+        capabilities.push(Capability {
+            id: header as u8,
+            offset,
+        });
+        offset = (header >> 8) & 0xfc;
+    }
+    capabilities
+}
+
+pub(super) fn read_extended_capabilities(address: PciAddress) -> Vec<ExtendedCapability> {
+    let mut capabilities = Vec::new();
+    let mut offset = EXTENDED_START;
+    while offset >= EXTENDED_START && capabilities.len() < 960 {
+        let header = read_config(address, offset);
+        if header == 0 || header == u32::MAX {
+            break;
+        }
+        capabilities.push(ExtendedCapability {
+            id: header as u16,
+            version: (header >> 16) as u8 & 0xf,
+            offset,
+        });
+        offset = (header >> 20) as u16 & 0xffc;
+    }
+    capabilities
+}
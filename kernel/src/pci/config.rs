@@ -0,0 +1,120 @@
+//! Configuration space access, through ECAM where the MCFG table lists it
+//! and the legacy 0xCF8/0xCFC ports otherwise.
+
+use super::PciAddress;
+use crate::memory;
+use crate::sync::IrqMutex;
+use alloc::vec::Vec;
+use spin::Once;
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// An ECAM window covering `buses` of one segment group.
+#[derive(Debug, Clone)]
+pub struct EcamRegion {
+    pub segment: u16,
+    pub buses: core::ops::RangeInclusive<u8>,
+    pub base: PhysAddr,
+}
+
+static ECAM: Once<Vec<EcamRegion>> = Once::new();
+/// The address and data ports must be used as a pair.
+static LEGACY: IrqMutex<()> = IrqMutex::named("PCI legacy config", ());
+
+pub(super) fn init(regions: Vec<EcamRegion>) {
+    ECAM.call_once(|| regions);
+}
+
+/// ECAM regions in use; empty when only the legacy ports are.
+pub fn ecam_regions() -> &'static [EcamRegion] {
+    ECAM.get().map_or(&[], |regions| regions.as_slice())
+}
+
+/// Segment groups and bus ranges to scan.
+pub(super) fn bus_ranges() -> Vec<(u16, core::ops::RangeInclusive<u8>)> {
+    match ecam_regions() {
+        [] => alloc::vec![(0, 0..=255)],
+        regions => regions.iter().map(|region| (region.segment, region.buses.clone())).collect(),
+    }
+}
+
+fn ecam_address(address: PciAddress, offset: u16) -> Option<VirtAddr> {
+    let region = ecam_regions()
+        .iter()
+        .find(|region| region.segment == address.segment && region.buses.contains(&address.bus))?;
+    let offset = ((address.bus - region.buses.start()) as u64) << 20
+        | (address.device as u64) << 15
+        | (address.function as u64) << 12
+        | (offset & 0xffc) as u64;
+    Some(memory::phys_to_virt(region.base + offset))
+}
+
+fn legacy_address(address: PciAddress, offset: u16) -> Option<u32> {
+    if address.segment != 0 || offset >= 0x100 {
+        return None;
+    }
+    Some(
+        0x8000_0000
+            | (address.bus as u32) << 16
+            | (address.device as u32) << 11
+            | (address.function as u32) << 8
+            | (offset & 0xfc) as u32,
+    )
+}
+
+/// Reads the dword containing `offset`. Offsets the function cannot reach,
+/// such as extended space without ECAM, read as all ones.
+pub fn read_config(address: PciAddress, offset: u16) -> u32 {
+    if let Some(virt) = ecam_address(address, offset) {
+        return unsafe { virt.as_ptr::<u32>().read_volatile() };
+    }
+    let Some(value) = legacy_address(address, offset) else {
+        return u32::MAX;
+    };
+    let _ports = LEGACY.lock();
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(value);
+        Port::<u32>::new(CONFIG_DATA).read()
+    }
+}
+
+/// Writes the dword containing `offset`; ignored where unreachable.
+pub fn write_config(address: PciAddress, offset: u16, data: u32) {
+    if let Some(virt) = ecam_address(address, offset) {
+        unsafe { virt.as_mut_ptr::<u32>().write_volatile(data) };
+        return;
+    }
+    let Some(value) = legacy_address(address, offset) else {
+        return;
+    };
+    let _ports = LEGACY.lock();
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(value);
+        Port::<u32>::new(CONFIG_DATA).write(data);
+    }
+}
+
+pub fn read_u16(address: PciAddress, offset: u16) -> u16 {
+    (read_config(address, offset) >> ((offset & 2) * 8)) as u16
+}
+
+pub fn read_u8(address: PciAddress, offset: u16) -> u8 {
+    (read_config(address, offset) >> ((offset & 3) * 8)) as u8
+}
+
+/// Writes the 16 bits at `offset`, keeping the other half of the dword.
+pub fn write_u16(address: PciAddress, offset: u16, value: u16) {
+    let shift = (offset & 2) * 8;
+    let dword = match offset & !3 {
+        // The status register's bits are write-one-to-clear; writing back
+        // what was read would clear them. The command register is kept
+        // when the status is written.
+        0x04 if shift == 16 => read_config(address, offset) & 0xffff,
+        0x04 => 0,
+        _ => read_config(address, offset) & !(0xffff << shift),
+    };
+    write_config(address, offset, dword | (value as u32) << shift);
+}
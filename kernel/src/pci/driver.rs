@@ -0,0 +1,96 @@
+//! Binding drivers to PCI functions.
+//!
+//! A driver lists the functions it handles by vendor and device ID or by
+//! class. Registering it probes every unbound function in the registry that
+//! matches, and functions found by [`super::init`] later are offered to the
+//! drivers already registered. The first driver whose probe succeeds owns
+//! the function.
+
+use super::PciFunction;
+use crate::sync::SpinLock;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    Id { vendor: u16, device: u16 },
+    /// `None` matches any subclass or programming interface.
+    Class { class: u8, subclass: Option<u8>, prog_if: Option<u8> },
+}
+
+impl Match {
+    pub const fn id(vendor: u16, device: u16) -> Match {
+        Match::Id { vendor, device }
+    }
+
+    pub const fn class(class: u8, subclass: u8) -> Match {
+        Match::Class {
+            class,
+            subclass: Some(subclass),
+            prog_if: None,
+        }
+    }
+
+    pub const fn class_prog_if(class: u8, subclass: u8, prog_if: u8) -> Match {
+        Match::Class {
+            class,
+            subclass: Some(subclass),
+            prog_if: Some(prog_if),
+        }
+    }
+
+    pub fn matches(&self, function: &PciFunction) -> bool {
+        match *self {
+            Match::Id { vendor, device } => function.vendor_id == vendor && function.device_id == device,
+            Match::Class { class, subclass, prog_if } => {
+                function.class == class
+                    && subclass.is_none_or(|subclass| function.subclass == subclass)
+                    && prog_if.is_none_or(|prog_if| function.prog_if == prog_if)
+            }
+        }
+    }
+}
+
+pub type ProbeResult = Result<(), &'static str>;
+
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    /// Sets up the function; an error leaves it for other drivers.
+    pub probe: fn(&PciFunction) -> ProbeResult,
+}
+
+static DRIVERS: SpinLock<Vec<&'static Driver>> = SpinLock::named("PCI drivers", Vec::new());
+
+/// Registers `driver` and binds it to matching functions found so far.
+pub fn register(driver: &'static Driver) {
+    DRIVERS.lock().push(driver);
+    for function in super::unbound() {
+        try_bind(driver, &function);
+    }
+}
+
+/// Offers a newly found function to the registered drivers.
+pub(super) fn bind(function: &PciFunction) {
+    let drivers = DRIVERS.lock().clone();
+    for driver in drivers {
+        if try_bind(driver, function) {
+            break;
+        }
+    }
+}
+
+fn try_bind(driver: &'static Driver, function: &PciFunction) -> bool {
+    if !driver.matches.iter().any(|m| m.matches(function)) {
+        return false;
+    }
+    match (driver.probe)(function) {
+        Ok(()) => {
+            super::set_driver(function.address, driver.name);
+            true
+        }
+        Err(err) => {
+            crate::println!("pci {}: {} probe failed: {}", function.address, driver.name, err);
+            false
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Message signalled interrupts.
+//!
+//! Both MSI and MSI-X deliver an interrupt as a memory write of the vector
+//! to the address of a local APIC. Vectors come from
+//! [`interrupts::vector`]; [`Msi::route`] and [`MsiX::route`] allocate one,
+//! point an entry at it and unmask the entry.
+
+use super::bar::Bar;
+use super::capability::{ID_MSI, ID_MSIX};
+use super::config::{read_config, read_u16, write_config, write_u16};
+use super::{PciAddress, PciFunction, COMMAND_INTX_DISABLE};
+use crate::interrupts::vector::{self, Handler};
+use crate::{memory, smp};
+use x86_64::VirtAddr;
+
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_64BIT: u16 = 1 << 7;
+const MSI_PER_VECTOR_MASK: u16 = 1 << 8;
+
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_MASKED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    NoCapability,
+    /// The MSI-X table BAR is missing, not memory, or not mapped.
+    BadBar,
+    InvalidIndex,
+    NoVectors,
+}
+
+/// Address and data a device writes to raise `vector` on `cpu`.
+fn message(vector: u8, cpu: usize) -> (u64, u32) {
+    let address = 0xfee0_0000 | ((smp::apic_id(cpu) & 0xff) as u64) << 12;
+    // Fixed delivery, edge triggered.
+    (address, vector as u32)
+}
+
+/// The MSI capability of a function, used with a single message.
+#[derive(Debug, Clone, Copy)]
+pub struct Msi {
+    address: PciAddress,
+    offset: u16,
+    control: u16,
+}
+
+impl Msi {
+    pub fn new(function: &PciFunction) -> Result<Msi, MsiError> {
+        let capability = function.capability(ID_MSI).ok_or(MsiError::NoCapability)?;
+        Ok(Msi {
+            address: function.address,
+            offset: capability.offset,
+            control: read_u16(function.address, capability.offset + 2),
+        })
+    }
+
+    /// Messages the function can send, though only the first is used.
+    pub fn max_vectors(&self) -> usize {
+        1 << ((self.control >> 1) & 0x7)
+    }
+
+    fn data_offset(&self) -> u16 {
+        if self.control & MSI_64BIT != 0 { self.offset + 0x0c } else { self.offset + 0x08 }
+    }
+
+    fn mask_offset(&self) -> Option<u16> {
+        (self.control & MSI_PER_VECTOR_MASK != 0).then(|| self.data_offset() + 4)
+    }
+
+    /// Points the message at `vector` on `cpu` with a single message
+    /// enabled.
+    pub fn configure(&self, vector: u8, cpu: usize) {
+        let (address, data) = message(vector, cpu);
+        write_config(self.address, self.offset + 4, address as u32);
+        if self.control & MSI_64BIT != 0 {
+            write_config(self.address, self.offset + 8, (address >> 32) as u32);
+        }
+        write_u16(self.address, self.data_offset(), data as u16);
+        let control = read_u16(self.address, self.offset + 2) & !(0x7 << 4);
+        write_u16(self.address, self.offset + 2, control);
+    }
+
+    /// Turns MSI on and the legacy INTx line off.
+    pub fn enable(&self) {
+        let control = read_u16(self.address, self.offset + 2);
+        write_u16(self.address, self.offset + 2, control | MSI_ENABLE);
+        set_intx_disable(self.address, true);
+    }
+
+    pub fn disable(&self) {
+        let control = read_u16(self.address, self.offset + 2);
+        write_u16(self.address, self.offset + 2, control & !MSI_ENABLE);
+        set_intx_disable(self.address, false);
+    }
+
+    /// Masks the message; returns `false` if the function cannot mask.
+    pub fn mask(&self) -> bool {
+        self.set_masked(true)
+    }
+
+    pub fn unmask(&self) -> bool {
+        self.set_masked(false)
+    }
+
+    fn set_masked(&self, masked: bool) -> bool {
+        let Some(offset) = self.mask_offset() else {
+            return false;
+        };
+        let bits = read_config(self.address, offset);
+        write_config(self.address, offset, if masked { bits | 1 } else { bits & !1 });
+        true
+    }
+
+    /// Allocates a vector for `handler`, points the message at it on `cpu`
+    /// and enables MSI.
+    pub fn route(&self, name: &'static str, handler: Handler, data: usize, cpu: usize) -> Result<u8, MsiError> {
+        let vector = vector::allocate(name, handler, data).ok_or(MsiError::NoVectors)?;
+        self.configure(vector, cpu);
+        self.unmask();
+        self.enable();
+        Ok(vector)
+    }
+}
+
+/// The MSI-X capability of a function and its vector table.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiX {
+    address: PciAddress,
+    offset: u16,
+    table: VirtAddr,
+    pba: VirtAddr,
+    len: usize,
+}
+
+impl MsiX {
+    pub fn new(function: &PciFunction) -> Result<MsiX, MsiError> {
+        let capability = function.capability(ID_MSIX).ok_or(MsiError::NoCapability)?;
+        let address = function.address;
+        let control = read_u16(address, capability.offset + 2);
+        let len = (control & 0x7ff) as usize + 1;
+        let locate = |register: u16| -> Result<VirtAddr, MsiError> {
+            let value = read_config(address, capability.offset + register);
+            let Some(Bar::Memory { address: base, size, .. }) = function.bars.get((value & 0x7) as usize).copied().flatten()
+            else {
+                return Err(MsiError::BadBar);
+            };
+            let offset = (value & !0x7) as u64;
+            if offset >= size {
+                return Err(MsiError::BadBar);
+            }
+            let virt = memory::phys_to_virt(base + offset);
+            memory::is_mapped(virt).then_some(virt).ok_or(MsiError::BadBar)
+        };
+        Ok(MsiX {
+            address,
+            offset: capability.offset,
+            table: locate(4)?,
+            pba: locate(8)?,
+            len,
+        })
+    }
+
+    /// Number of table entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn entry(&self, index: usize) -> Result<*mut u32, MsiError> {
+        if index >= self.len {
+            return Err(MsiError::InvalidIndex);
+        }
+        Ok((self.table + index as u64 * MSIX_ENTRY_SIZE).as_mut_ptr())
+    }
+
+    /// Points entry `index` at `vector` on `cpu`, leaving it masked.
+    pub fn configure(&self, index: usize, vector: u8, cpu: usize) -> Result<(), MsiError> {
+        let entry = self.entry(index)?;
+        let (address, data) = message(vector, cpu);
+        unsafe {
+            let control = entry.add(3).read_volatile();
+            entry.add(3).write_volatile(control | MSIX_VECTOR_MASKED);
+            entry.write_volatile(address as u32);
+            entry.add(1).write_volatile((address >> 32) as u32);
+            entry.add(2).write_volatile(data);
+        }
+        Ok(())
+    }
+
+    pub fn mask(&self, index: usize) -> Result<(), MsiError> {
+        self.set_masked(index, true)
+    }
+
+    pub fn unmask(&self, index: usize) -> Result<(), MsiError> {
+        self.set_masked(index, false)
+    }
+
+    fn set_masked(&self, index: usize, masked: bool) -> Result<(), MsiError> {
+        let control = unsafe { self.entry(index)?.add(3) };
+        unsafe {
+            let value = control.read_volatile();
+            let value = if masked { value | MSIX_VECTOR_MASKED } else { value & !MSIX_VECTOR_MASKED };
+            control.write_volatile(value);
+        }
+        Ok(())
+    }
+
+    /// Whether entry `index` has an interrupt held back by its mask.
+    pub fn is_pending(&self, index: usize) -> Result<bool, MsiError> {
+        self.entry(index)?;
+        let qword = unsafe { (self.pba + (index / 64) as u64 * 8).as_ptr::<u64>().read_volatile() };
+        Ok(qword & (1 << (index % 64)) != 0)
+    }
+
+    /// Turns MSI-X on and the legacy INTx line off. Entries stay masked
+    /// until unmasked one by one.
+    pub fn enable(&self) {
+        let control = read_u16(self.address, self.offset + 2);
+        write_u16(self.address, self.offset + 2, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+        set_intx_disable(self.address, true);
+    }
+
+    pub fn disable(&self) {
+        let control = read_u16(self.address, self.offset + 2);
+        write_u16(self.address, self.offset + 2, control & !MSIX_ENABLE);
+        set_intx_disable(self.address, false);
+    }
+
+    /// Allocates a vector for `handler`, points entry `index` at it on
+    /// `cpu` and unmasks the entry. MSI-X must be enabled separately.
+    pub fn route(
+        &self,
+        index: usize,
+        name: &'static str,
+        handler: Handler,
+        data: usize,
+        cpu: usize,
+    ) -> Result<u8, MsiError> {
+        self.entry(index)?;
+        let vector = vector::allocate(name, handler, data).ok_or(MsiError::NoVectors)?;
+        self.configure(index, vector, cpu)?;
+        self.unmask(index)?;
+        Ok(vector)
+    }
+}
+
+fn set_intx_disable(address: PciAddress, disable: bool) {
+    let command = read_u16(address, super::COMMAND);
+    let command = if disable { command | COMMAND_INTX_DISABLE } else { command & !COMMAND_INTX_DISABLE };
+    write_u16(address, super::COMMAND, command);
+}
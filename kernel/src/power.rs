@@ -0,0 +1,37 @@
+//! Reboot and power off.
+
+use x86_64::instructions::port::Port;
+
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        // Pulse the CPU reset line through the 8042 keyboard controller.
+        let mut status = Port::<u8>::new(0x64);
+        while status.read() & 0x02 != 0 {}
+        status.write(0xfe);
+    }
+
+    // Fall back to a triple fault.
+    unsafe {
+        use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+        use x86_64::VirtAddr;
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        core::arch::asm!("int3");
+    }
+    crate::hlt_loop()
+}
+
+pub fn shutdown() -> ! {
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        // ACPI PM1a control ports used by QEMU (q35 and piix) and Bochs.
+        Port::<u16>::new(0x604).write(0x2000);
+        Port::<u16>::new(0xb004).write(0x2000);
+        // The isa-debug-exit device added by the runner.
+        Port::<u32>::new(0xf4).write(0x10);
+    }
+    crate::hlt_loop()
+}
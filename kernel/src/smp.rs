@@ -0,0 +1,261 @@
+//! Symmetric multiprocessing: per-CPU data and application processor
+//! start-up.
+//!
+//! APs start in [`trampoline`], which is reached through INIT-SIPI-SIPI and
+//! takes them to [`ap_main`] in long mode on the BSP's page tables. Each AP
+//! loads its own GDT and TSS, enables its local APIC and runs an executor.
+
+mod trampoline;
+
+use crate::apic::{self, lapic::LAPIC};
+use crate::memory::{BootInfoFrameAllocator, FRAME_ALLOCATOR};
+use crate::sync::SpinLock;
+use crate::task::executor::Executor;
+use crate::{gdt, interrupts, time};
+use acpi::platform::ProcessorState;
+use acpi::AcpiError;
+use core::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use x86_64::registers::control::{Cr3, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::GsBase;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+pub const MAX_CPUS: usize = 16;
+
+/// Virtual region for AP stacks; every stack sits above an unmapped guard
+/// page.
+const STACKS_START: u64 = 0x_5555_5555_0000;
+const STACK_PAGES: u64 = 16;
+const DOUBLE_FAULT_STACK_PAGES: u64 = 5;
+
+/// Data reached through the GS base of each CPU.
+#[repr(C)]
+struct PerCpu {
+    /// Must stay the first field; [`current_cpu`] reads it at `gs:0`.
+    index: usize,
+}
+
+static PER_CPU: [PerCpu; MAX_CPUS] = {
+    let mut cpus = [const { PerCpu { index: 0 } }; MAX_CPUS];
+    let mut i = 0;
+    while i < MAX_CPUS {
+        cpus[i].index = i;
+        i += 1;
+    }
+    cpus
+};
+
+static APIC_IDS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACKS_START);
+static DOUBLE_FAULT_STACKS: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Page below 1 MiB the trampoline runs from, if one was free.
+static TRAMPOLINE: SpinLock<Option<PhysFrame>> = SpinLock::named("TRAMPOLINE", None);
+/// Start-up progress of the AP being started, one of the `AP_*` states.
+static AP_STATE: AtomicU8 = AtomicU8::new(AP_WAITING);
+const AP_WAITING: u8 = 0;
+/// The AP reached [`ap_main`] and no longer needs the trampoline.
+const AP_CLAIMED: u8 = 1;
+const AP_READY: u8 = 2;
+/// The BSP gave up on the AP; if it shows up late it halts.
+const AP_ABANDONED: u8 = 3;
+
+#[derive(Debug)]
+pub enum SmpError {
+    Acpi(AcpiError),
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<AcpiError> for SmpError {
+    fn from(err: AcpiError) -> Self {
+        SmpError::Acpi(err)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        SmpError::Map(err)
+    }
+}
+
+/// Points the BSP's GS base at its per-CPU data; must run before anything
+/// calls [`current_cpu`].
+pub fn init_bsp() {
+    set_gs_base(0);
+}
+
+fn set_gs_base(cpu: usize) {
+    GsBase::write(VirtAddr::from_ptr(&PER_CPU[cpu]));
+}
+
+/// Index of the CPU running this code; the BSP is 0.
+pub fn current_cpu() -> usize {
+    let index: usize;
+    unsafe {
+        core::arch::asm!("mov {}, gs:[0]", out(reg) index, options(nostack, readonly, preserves_flags));
+    }
+    index
+}
+
+/// Number of CPUs running; indices are `0..cpu_count()`.
+pub fn cpu_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+pub fn apic_id(cpu: usize) -> u32 {
+    APIC_IDS[cpu].load(Ordering::Relaxed)
+}
+
+/// Sets aside a page for the trampoline. Must run before the heap takes
+/// the low frames.
+pub fn reserve_trampoline(frame_allocator: &mut BootInfoFrameAllocator) {
+    *TRAMPOLINE.lock() = frame_allocator.allocate_frame_below(PhysAddr::new(0x10_0000));
+}
+
+/// Starts every enabled AP listed in the MADT and returns the number of
+/// CPUs online.
+pub fn start_aps(mapper: &mut impl Mapper<Size4KiB>) -> Result<usize, SmpError> {
+    let tables = apic::acpi_tables()?;
+    let platform_info = tables.platform_info()?;
+    let Some(processors) = platform_info.processor_info else {
+        return Ok(cpu_count());
+    };
+    APIC_IDS[0].store(processors.boot_processor.local_apic_id, Ordering::Relaxed);
+
+    let (cr3_frame, _) = Cr3::read();
+    let cr3 = cr3_frame.start_address().as_u64();
+    let Some(trampoline) = *TRAMPOLINE.lock() else {
+        return Ok(cpu_count());
+    };
+    // The trampoline loads CR3 in 32-bit mode.
+    if cr3 >= 1 << 32 {
+        return Ok(cpu_count());
+    }
+    identity_map(mapper, trampoline)?;
+    trampoline::install(trampoline);
+    // PCIDs can only be enabled from long mode.
+    let cr4 = (Cr4::read() - Cr4Flags::PCID).bits();
+
+    // Stacks of an AP that failed to start, for the next one.
+    let mut spare_stacks = None;
+    for processor in processors.application_processors.iter() {
+        if processor.state == ProcessorState::Disabled {
+            continue;
+        }
+        let cpu = cpu_count();
+        if cpu == MAX_CPUS {
+            break;
+        }
+
+        let (stack, double_fault_stack) = match spare_stacks.take() {
+            Some(stacks) => stacks,
+            None => (map_stack(mapper, STACK_PAGES)?, map_stack(mapper, DOUBLE_FAULT_STACK_PAGES)?),
+        };
+        DOUBLE_FAULT_STACKS[cpu].store(double_fault_stack.as_u64(), Ordering::Relaxed);
+        APIC_IDS[cpu].store(processor.local_apic_id, Ordering::Relaxed);
+        trampoline::set_params(
+            trampoline,
+            trampoline::Params {
+                cr3,
+                cr4,
+                stack: stack.as_u64(),
+                entry: ap_main as *const () as u64,
+                cpu: cpu as u64,
+            },
+        );
+
+        AP_STATE.store(AP_WAITING, Ordering::Release);
+        if start_ap(processor.local_apic_id, trampoline) {
+            ONLINE.store(cpu + 1, Ordering::Release);
+        } else {
+            spare_stacks = Some((stack, double_fault_stack));
+        }
+    }
+
+    Ok(cpu_count())
+}
+
+/// Sends INIT-SIPI-SIPI, skipping the second SIPI if the AP already ran,
+/// and returns whether the AP came up. An AP that does not is reset, so it
+/// cannot reach the trampoline once it holds another AP's parameters.
+fn start_ap(apic_id: u32, trampoline: PhysFrame) -> bool {
+    let page = (trampoline.start_address().as_u64() >> 12) as u8;
+    let sipi = || LAPIC.lock().send_sipi(page, apic_id);
+
+    LAPIC.lock().send_init_ipi(apic_id);
+    time::delay(Duration::from_millis(10));
+    sipi();
+    if !wait_for_ap(Duration::from_millis(1)) {
+        sipi();
+        wait_for_ap(Duration::from_millis(100));
+    }
+
+    let abandoned = AP_STATE.compare_exchange(AP_WAITING, AP_ABANDONED, Ordering::AcqRel, Ordering::Acquire);
+    if abandoned.is_ok() {
+        LAPIC.lock().send_init_ipi(apic_id);
+        time::delay(Duration::from_millis(10));
+        return false;
+    }
+    while AP_STATE.load(Ordering::Acquire) != AP_READY {
+        core::hint::spin_loop();
+    }
+    true
+}
+
+fn wait_for_ap(timeout: Duration) -> bool {
+    let start = time::uptime();
+    while AP_STATE.load(Ordering::Acquire) == AP_WAITING {
+        if time::uptime() - start > timeout {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+fn identity_map(mapper: &mut impl Mapper<Size4KiB>, frame: PhysFrame) -> Result<(), MapToError<Size4KiB>> {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    match unsafe { mapper.identity_map(frame, flags, allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+        Err(err) => return Err(err),
+    }
+    Ok(())
+}
+
+/// Maps a stack of `pages` pages and returns its top.
+fn map_stack(mapper: &mut impl Mapper<Size4KiB>, pages: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let guard = NEXT_STACK.fetch_add((pages + 1) * 4096, Ordering::Relaxed);
+    let bottom = VirtAddr::new(guard + 4096);
+    let top = bottom + pages * 4096;
+
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for page in Page::range(Page::containing_address(bottom), Page::containing_address(top)) {
+        let frame = allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe { mapper.map_to(page, frame, flags, allocator)?.flush() };
+    }
+    Ok(top)
+}
+
+extern "C" fn ap_main(cpu: usize) -> ! {
+    let claimed = AP_STATE.compare_exchange(AP_WAITING, AP_CLAIMED, Ordering::AcqRel, Ordering::Relaxed);
+    if claimed.is_err() {
+        // Too late; the BSP is about to reset this CPU.
+        crate::hlt_loop();
+    }
+    set_gs_base(cpu);
+    gdt::init_ap(VirtAddr::new(DOUBLE_FAULT_STACKS[cpu].load(Ordering::Relaxed)));
+    interrupts::init();
+    LAPIC.lock().enable();
+    AP_STATE.store(AP_READY, Ordering::Release);
+
+    interrupts::enable();
+    Executor::new().run()
+}
@@ -0,0 +1,143 @@
+//! Real-mode entry code for application processors.
+//!
+//! The code is copied to a page below 1 MiB, which the start-up IPI points
+//! the AP at. It fills in its own GDT and far-jump addresses from the load
+//! address in `cs`. Then it enters protected mode and long mode using the
+//! BSP's CR3 and CR4, and calls `entry(cpu)` on `stack`. The page must be
+//! identity mapped, because paging turns on while it is still executing.
+
+use crate::memory;
+use x86_64::structures::paging::PhysFrame;
+
+core::arch::global_asm!(
+    r#"
+.section .text.ap_trampoline, "ax"
+.global ap_trampoline_start
+.global ap_trampoline_params
+.global ap_trampoline_end
+
+.code16
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+    xor %ebx, %ebx
+    mov %ax, %bx
+    shl $4, %ebx
+
+    mov %ebx, %eax
+    add $(gdt - ap_trampoline_start), %eax
+    mov %eax, (gdtr + 2 - ap_trampoline_start)
+    mov %ebx, %eax
+    add $(protected_mode - ap_trampoline_start), %eax
+    mov %eax, (protected_mode_ptr - ap_trampoline_start)
+    mov %ebx, %eax
+    add $(long_mode - ap_trampoline_start), %eax
+    mov %eax, (long_mode_ptr - ap_trampoline_start)
+
+    lgdtl (gdtr - ap_trampoline_start)
+    mov %cr0, %eax
+    or $1, %eax
+    mov %eax, %cr0
+    ljmpl *(protected_mode_ptr - ap_trampoline_start)
+
+.code32
+protected_mode:
+    mov $0x10, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+
+    mov (params_cr4 - ap_trampoline_start)(%ebx), %eax
+    mov %eax, %cr4
+    mov (params_cr3 - ap_trampoline_start)(%ebx), %eax
+    mov %eax, %cr3
+
+    # EFER: long mode and no-execute enable.
+    mov $0xc0000080, %ecx
+    rdmsr
+    or $0x900, %eax
+    wrmsr
+
+    mov %cr0, %eax
+    or $0x80000000, %eax
+    mov %eax, %cr0
+    ljmpl *(long_mode_ptr - ap_trampoline_start)(%ebx)
+
+.code64
+long_mode:
+    mov (params_stack - ap_trampoline_start)(%rbx), %rsp
+    mov (params_cpu - ap_trampoline_start)(%rbx), %rdi
+    mov (params_entry - ap_trampoline_start)(%rbx), %rax
+    call *%rax
+1:
+    hlt
+    jmp 1b
+
+.balign 8
+gdt:
+    .quad 0
+    .quad 0x00cf9a000000ffff
+    .quad 0x00cf92000000ffff
+    .quad 0x00af9a000000ffff
+gdt_end:
+gdtr:
+    .word gdt_end - gdt - 1
+    .long 0
+protected_mode_ptr:
+    .long 0
+    .word 0x08
+long_mode_ptr:
+    .long 0
+    .word 0x18
+
+.balign 8
+ap_trampoline_params:
+params_cr3:
+    .quad 0
+params_cr4:
+    .quad 0
+params_stack:
+    .quad 0
+params_entry:
+    .quad 0
+params_cpu:
+    .quad 0
+ap_trampoline_end:
+
+.text
+"#,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Matches the `params_*` block at the end of the trampoline.
+#[repr(C)]
+pub struct Params {
+    pub cr3: u64,
+    pub cr4: u64,
+    pub stack: u64,
+    pub entry: u64,
+    pub cpu: u64,
+}
+
+/// Copies the trampoline to the start of `frame`.
+pub fn install(frame: PhysFrame) {
+    let start = &raw const ap_trampoline_start;
+    let len = &raw const ap_trampoline_end as usize - start as usize;
+    let dest = memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::copy_nonoverlapping(start, dest, len) };
+}
+
+/// Sets the parameters for the next AP started from `frame`.
+pub fn set_params(frame: PhysFrame, params: Params) {
+    let offset = &raw const ap_trampoline_params as usize - &raw const ap_trampoline_start as usize;
+    let dest = memory::phys_to_virt(frame.start_address()) + offset as u64;
+    unsafe { core::ptr::write_volatile(dest.as_mut_ptr::<Params>(), params) };
+}
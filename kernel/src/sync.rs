@@ -0,0 +1,31 @@
+//! Synchronisation primitives for tasks and interrupt handlers.
+//!
+//! The async primitives park the waiting task on a [`WaitQueue`] instead of
+//! spinning, so they must only be used from tasks; their guards may be held
+//! across `.await`. [`SpinLock`] is checked for deadlocks by [`lockdep`] in
+//! debug builds, and [`IrqMutex`] is a spinlock that keeps interrupts
+//! disabled while held, for data shared with interrupt handlers.
+
+mod barrier;
+mod irq;
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+pub mod lockdep;
+mod mutex;
+mod notify;
+mod once_cell;
+mod rwlock;
+mod semaphore;
+mod spinlock;
+mod wait_queue;
+mod wake_list;
+
+pub use self::barrier::{Barrier, BarrierWaitResult};
+pub use self::irq::{IrqMutex, IrqMutexGuard};
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::notify::Notify;
+pub use self::once_cell::OnceCell;
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::{Semaphore, SemaphorePermit};
+pub use self::spinlock::{SpinLock, SpinLockGuard};
+pub use self::wait_queue::WaitQueue;
+pub use self::wake_list::WakeList;
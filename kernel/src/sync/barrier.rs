@@ -0,0 +1,54 @@
+use super::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Lets a fixed number of tasks wait until all of them have arrived.
+pub struct Barrier {
+    count: usize,
+    /// Tasks that arrived in the current generation.
+    arrived: spin::Mutex<usize>,
+    generation: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// True for exactly one task per generation, the last to arrive.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    #[track_caller]
+    pub const fn new(count: usize) -> Self {
+        Barrier {
+            count,
+            arrived: spin::Mutex::new(0),
+            generation: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Waits until `count` tasks have called this; the barrier can then be
+    /// used again.
+    pub async fn wait(&self) -> BarrierWaitResult {
+        let generation = {
+            let mut arrived = self.arrived.lock();
+            *arrived += 1;
+            if *arrived >= self.count {
+                *arrived = 0;
+                self.generation.fetch_add(1, Ordering::AcqRel);
+                drop(arrived);
+                self.waiters.wake_all();
+                return BarrierWaitResult(true);
+            }
+            self.generation.load(Ordering::Acquire)
+        };
+        self.waiters
+            .wait_until(|| (self.generation.load(Ordering::Acquire) != generation).then_some(()))
+            .await;
+        BarrierWaitResult(false)
+    }
+}
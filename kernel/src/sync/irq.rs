@@ -0,0 +1,97 @@
+use super::{SpinLock, SpinLockGuard};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+
+/// A spinlock that disables interrupts on the current CPU while held.
+///
+/// An interrupt handler that takes a plain spinlock deadlocks if it
+/// interrupted code on the same CPU holding that lock. With this lock that
+/// code cannot be interrupted, and the interrupt state it had is restored
+/// when the guard is dropped.
+pub struct IrqMutex<T: ?Sized> {
+    inner: SpinLock<T>,
+}
+
+pub struct IrqMutexGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<SpinLockGuard<'a, T>>,
+    were_enabled: bool,
+}
+
+impl<T> IrqMutex<T> {
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        IrqMutex {
+            inner: SpinLock::new(value),
+        }
+    }
+
+    #[track_caller]
+    pub const fn named(name: &'static str, value: T) -> Self {
+        IrqMutex {
+            inner: SpinLock::named(name, value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqMutex<T> {
+    #[track_caller]
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            were_enabled,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqMutexGuard {
+                guard: ManuallyDrop::new(guard),
+                were_enabled,
+            }),
+            None => {
+                if were_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+impl<T: ?Sized> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock before interrupts can come in again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.were_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,517 @@
+//! Runtime lock checking for [`SpinLock`](super::SpinLock), enabled in
+//! debug builds.
+//!
+//! Locks are grouped into classes by the place they are constructed, as in
+//! Linux's lockdep, so every lock built by the same `new` call is checked
+//! as one. On each acquisition the checker looks for:
+//!
+//! - recursion: the lock is already held by this CPU, possibly by code an
+//!   interrupt handler interrupted. This would spin forever, so it panics
+//!   with a report instead.
+//! - order inversions: the new lock's class, or one acquired after it, was
+//!   taken while the lock about to be held was held before.
+//! - IRQ-unsafe use: a class taken both in interrupt handlers and with
+//!   interrupts enabled.
+//! - long spins: waiting for a lock held by another CPU for seconds.
+//!
+//! Violations other than recursion are reported once and execution goes
+//! on. The checker runs inside lock acquisitions, interrupt handlers
+//! included, so it never allocates or prints: reports go to a fixed ring
+//! that the executor prints from between tasks and the `lockdep` shell
+//! command lists.
+
+use crate::backtrace::Backtrace;
+use crate::smp::{self, MAX_CPUS};
+use crate::task::shell::{Command, CommandResult};
+use crate::{interrupts, println, time};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+const MAX_CLASSES: usize = 64;
+const MAX_HELD: usize = 16;
+/// Spinning this long on a lock is reported as a possible deadlock.
+const LONG_SPIN: Duration = Duration::from_secs(2);
+/// Class of locks constructed after the class table filled up; only the
+/// recursion check applies to them.
+const UNTRACKED: usize = usize::MAX;
+/// Reports the ring keeps; newer ones overwrite the oldest.
+const MAX_REPORTS: usize = 8;
+/// Longest report kept; the rest of a longer one is cut off.
+const REPORT_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClassInfo {
+    pub name: &'static str,
+    pub site: &'static Location<'static>,
+    /// Taken inside interrupt handlers.
+    pub in_irq: bool,
+    /// Taken with interrupts enabled.
+    pub irqs_enabled: bool,
+}
+
+static CLASSES: spin::Mutex<Classes> = spin::Mutex::new(Classes {
+    info: [None; MAX_CLASSES],
+    len: 0,
+});
+/// Bit `b` of entry `a`: class `b` was acquired while `a` was held.
+static ORDER: [AtomicU64; MAX_CLASSES] = [const { AtomicU64::new(0) }; MAX_CLASSES];
+static ORDER_REPORTED: [AtomicU64; MAX_CLASSES] = [const { AtomicU64::new(0) }; MAX_CLASSES];
+static IN_IRQ: AtomicU64 = AtomicU64::new(0);
+static IRQS_ENABLED: AtomicU64 = AtomicU64::new(0);
+static IRQ_REPORTED: AtomicU64 = AtomicU64::new(0);
+static HELD: [spin::Mutex<Held>; MAX_CPUS] = [const { spin::Mutex::new(Held::new()) }; MAX_CPUS];
+static REPORTS: [ReportSlot; MAX_REPORTS] = [const { ReportSlot::new() }; MAX_REPORTS];
+/// Reports made so far; report `n` goes to slot `n % MAX_REPORTS`.
+static REPORTED: AtomicUsize = AtomicUsize::new(0);
+/// Reports the executor has printed.
+static PRINTED: AtomicUsize = AtomicUsize::new(0);
+
+struct Classes {
+    info: [Option<ClassInfo>; MAX_CLASSES],
+    len: usize,
+}
+
+/// Locks held by one CPU, innermost last.
+struct Held {
+    locks: [(usize, Option<&'static Location<'static>>); MAX_HELD],
+    len: usize,
+}
+
+impl Held {
+    const fn new() -> Self {
+        Held {
+            locks: [(UNTRACKED, None); MAX_HELD],
+            len: 0,
+        }
+    }
+}
+
+/// Per-lock state the checker keeps.
+pub(super) struct LockDebug {
+    name: Option<&'static str>,
+    construction: &'static Location<'static>,
+    /// Index into [`CLASSES`] plus one, zero until first locked, or
+    /// [`UNTRACKED`].
+    class: AtomicUsize,
+    /// Holding CPU plus one; zero while unlocked.
+    owner: AtomicUsize,
+    site: AtomicPtr<Location<'static>>,
+    irqs_enabled: AtomicBool,
+}
+
+impl LockDebug {
+    pub(super) const fn new(name: Option<&'static str>, construction: &'static Location<'static>) -> Self {
+        LockDebug {
+            name,
+            construction,
+            class: AtomicUsize::new(0),
+            owner: AtomicUsize::new(0),
+            site: AtomicPtr::new(ptr::null_mut()),
+            irqs_enabled: AtomicBool::new(false),
+        }
+    }
+
+    fn name(&self) -> Name {
+        Name(self.name.unwrap_or(""), self.construction)
+    }
+
+    fn class(&self) -> usize {
+        match self.class.load(Ordering::Relaxed) {
+            0 => {
+                let class = register(self);
+                let stored = if class == UNTRACKED { UNTRACKED } else { class + 1 };
+                self.class.store(stored, Ordering::Relaxed);
+                class
+            }
+            UNTRACKED => UNTRACKED,
+            class => class - 1,
+        }
+    }
+
+    pub(super) fn is_held_by_current_cpu(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == smp::current_cpu() + 1
+    }
+
+    /// Checks an acquisition at `site` before spinning. If this CPU
+    /// already holds the lock, calls `unlock` so the panic handler can
+    /// print even if this is the framebuffer lock, and panics.
+    pub(super) fn check(&self, site: &'static Location<'static>, unlock: impl FnOnce()) {
+        let cpu = smp::current_cpu();
+        if self.owner.load(Ordering::Relaxed) == cpu + 1 {
+            unlock();
+            self.recursion(cpu, site);
+        }
+
+        let class = self.class();
+        if class == UNTRACKED {
+            return;
+        }
+        let irqs_enabled = x86_64::instructions::interrupts::are_enabled();
+        self.check_irq_safety(class, site, irqs_enabled);
+        self.check_order(cpu, class, site);
+    }
+
+    #[cold]
+    fn recursion(&self, cpu: usize, site: &'static Location<'static>) -> ! {
+        let mut buf = [0; REPORT_LEN];
+        let mut text = Text { buf: &mut buf, len: 0 };
+        let irqs = if self.irqs_enabled.load(Ordering::Relaxed) { "enabled" } else { "disabled" };
+        let _ = write!(
+            text,
+            "lockdep: recursive locking of {} on CPU {}\n  held since {} with interrupts {}\n  acquired again at {}{}\n{}",
+            self.name(),
+            cpu,
+            Site(self.site()),
+            irqs,
+            site,
+            if interrupts::in_irq() { " in an interrupt handler" } else { "" },
+            Backtrace::capture()
+        );
+        panic!("{}", text.as_str());
+    }
+
+    fn check_irq_safety(&self, class: usize, site: &'static Location<'static>, irqs_enabled: bool) {
+        let bit = 1 << class;
+        if interrupts::in_irq() {
+            IN_IRQ.fetch_or(bit, Ordering::Relaxed);
+        }
+        if irqs_enabled {
+            IRQS_ENABLED.fetch_or(bit, Ordering::Relaxed);
+        }
+        let unsafe_use = IN_IRQ.load(Ordering::Relaxed) & IRQS_ENABLED.load(Ordering::Relaxed) & bit != 0;
+        if unsafe_use && IRQ_REPORTED.fetch_or(bit, Ordering::Relaxed) & bit == 0 {
+            report(format_args!(
+                "lockdep: {} is taken in interrupt handlers and with interrupts enabled (at {}); \
+                 an interrupt while it is held deadlocks\n{}",
+                self.name(),
+                site,
+                Backtrace::capture()
+            ));
+        }
+    }
+
+    fn check_order(&self, cpu: usize, class: usize, site: &'static Location<'static>) {
+        let held = interrupts_disabled(|| {
+            let held = HELD[cpu].lock();
+            let mut locks = [(UNTRACKED, None); MAX_HELD];
+            locks[..held.len].copy_from_slice(&held.locks[..held.len]);
+            (locks, held.len)
+        });
+        for &(outer, outer_site) in &held.0[..held.1] {
+            if outer == UNTRACKED || outer == class || ORDER[outer].load(Ordering::Relaxed) & (1 << class) != 0 {
+                continue;
+            }
+            if reaches(class, outer) && ORDER_REPORTED[outer].fetch_or(1 << class, Ordering::Relaxed) & (1 << class) == 0 {
+                report(format_args!(
+                    "lockdep: lock order inversion on CPU {}\n  {} acquired at {}\n  while holding {} acquired at {}\n  \
+                     but the reverse order was seen before\n{}",
+                    cpu,
+                    self.name(),
+                    site,
+                    class_name(outer),
+                    Site(outer_site),
+                    Backtrace::capture()
+                ));
+            }
+            ORDER[outer].fetch_or(1 << class, Ordering::Relaxed);
+        }
+    }
+
+    /// Called while spinning; reports once the wait gets suspiciously long.
+    pub(super) fn spinning(&self, site: &'static Location<'static>, start: u64, reported: &mut bool) {
+        if *reported || time::tsc_hz() == 0 || time::cycles_to_duration(time::tsc() - start) < LONG_SPIN {
+            return;
+        }
+        *reported = true;
+        let owner = self.owner.load(Ordering::Relaxed);
+        report(format_args!(
+            "lockdep: CPU {} spinning on {} at {} for over {}s\n  held by CPU {} since {}\n{}",
+            smp::current_cpu(),
+            self.name(),
+            site,
+            LONG_SPIN.as_secs(),
+            owner.wrapping_sub(1) as isize,
+            Site(self.site()),
+            Backtrace::capture()
+        ));
+    }
+
+    pub(super) fn acquired(&self, site: &'static Location<'static>) {
+        let cpu = smp::current_cpu();
+        self.owner.store(cpu + 1, Ordering::Relaxed);
+        self.site.store(site as *const _ as *mut _, Ordering::Relaxed);
+        self.irqs_enabled
+            .store(x86_64::instructions::interrupts::are_enabled(), Ordering::Relaxed);
+
+        let class = self.class();
+        interrupts_disabled(|| {
+            let mut held = HELD[cpu].lock();
+            if held.len < MAX_HELD {
+                let len = held.len;
+                held.locks[len] = (class, Some(site));
+                held.len += 1;
+            }
+        });
+    }
+
+    pub(super) fn released(&self) {
+        let cpu = smp::current_cpu();
+        self.owner.store(0, Ordering::Relaxed);
+        let class = self.class();
+        interrupts_disabled(|| {
+            let mut held = HELD[cpu].lock();
+            let len = held.len;
+            // Guards are usually, but not always, dropped in reverse order.
+            if let Some(index) = held.locks[..len].iter().rposition(|&(other, _)| other == class) {
+                held.locks.copy_within(index + 1..len, index);
+                held.len -= 1;
+            }
+        });
+    }
+
+    fn site(&self) -> Option<&'static Location<'static>> {
+        unsafe { self.site.load(Ordering::Relaxed).as_ref() }
+    }
+}
+
+fn interrupts_disabled<T>(f: impl FnOnce() -> T) -> T {
+    x86_64::instructions::interrupts::without_interrupts(f)
+}
+
+/// Finds or adds the class of locks constructed where `lock` was.
+fn register(lock: &LockDebug) -> usize {
+    interrupts_disabled(|| register_locked(&mut CLASSES.lock(), lock))
+}
+
+fn register_locked(classes: &mut Classes, lock: &LockDebug) -> usize {
+    let same_site = |class: &ClassInfo| {
+        let (a, b) = (class.site, lock.construction);
+        a.file() == b.file() && a.line() == b.line() && a.column() == b.column()
+    };
+    if let Some(class) = classes.info[..classes.len].iter().flatten().position(same_site) {
+        return class;
+    }
+    if classes.len == MAX_CLASSES {
+        return UNTRACKED;
+    }
+    classes.info[classes.len] = Some(ClassInfo {
+        name: lock.name.unwrap_or(""),
+        site: lock.construction,
+        in_irq: false,
+        irqs_enabled: false,
+    });
+    classes.len += 1;
+    classes.len - 1
+}
+
+fn class_name(class: usize) -> Name {
+    let info = interrupts_disabled(|| CLASSES.lock().info.get(class).copied().flatten());
+    match info {
+        Some(info) => Name(info.name, info.site),
+        None => Name("?", Location::caller()),
+    }
+}
+
+/// A lock class's name, or where its locks are constructed if unnamed.
+struct Name(&'static str, &'static Location<'static>);
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            "" => write!(f, "lock from {}", self.1),
+            name => f.write_str(name),
+        }
+    }
+}
+
+/// Where a lock was acquired, if known.
+struct Site(Option<&'static Location<'static>>);
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(site) => write!(f, "{}", site),
+            None => f.write_str("?"),
+        }
+    }
+}
+
+/// Whether `to` was ever acquired, directly or through other classes,
+/// while `from` was held.
+fn reaches(from: usize, to: usize) -> bool {
+    let mut visited = 1u64 << from;
+    let mut frontier = 1u64 << from;
+    while frontier != 0 {
+        let class = frontier.trailing_zeros() as usize;
+        frontier &= frontier - 1;
+        let next = ORDER[class].load(Ordering::Relaxed) & !visited;
+        if next & (1 << to) != 0 {
+            return true;
+        }
+        visited |= next;
+        frontier |= next;
+    }
+    false
+}
+
+/// Formats into a fixed buffer, cutting off what does not fit.
+struct Text<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Text<'_> {
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever written.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Text<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(self.buf.len() - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// A report in the ring. `seq` is odd while report `seq / 2` is written
+/// into it and `2 * n + 2` once report `n` is complete, so readers can
+/// tell whether the text they copied was overwritten meanwhile.
+struct ReportSlot {
+    seq: AtomicUsize,
+    len: AtomicUsize,
+    text: UnsafeCell<[u8; REPORT_LEN]>,
+}
+
+// Writers own a slot between the two stores to `seq`, and readers check
+// `seq` around their copy.
+unsafe impl Sync for ReportSlot {}
+
+impl ReportSlot {
+    const fn new() -> Self {
+        ReportSlot {
+            seq: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            text: UnsafeCell::new([0; REPORT_LEN]),
+        }
+    }
+
+    /// Copies report `n` into `buf` and returns its length, or `None` if the
+    /// slot holds another report or is being written.
+    fn read(&self, n: usize, buf: &mut [u8; REPORT_LEN]) -> Option<usize> {
+        if self.seq.load(Ordering::Acquire) != 2 * n + 2 {
+            return None;
+        }
+        let len = self.len.load(Ordering::Relaxed);
+        unsafe { ptr::copy_nonoverlapping(self.text.get().cast::<u8>(), buf.as_mut_ptr(), len) };
+        fence(Ordering::Acquire);
+        (self.seq.load(Ordering::Relaxed) == 2 * n + 2).then_some(len)
+    }
+}
+
+/// Records a report in the ring. More than [`MAX_REPORTS`] reports made
+/// at once would share slots, which only garbles their text.
+fn report(args: fmt::Arguments) {
+    let n = REPORTED.fetch_add(1, Ordering::Relaxed);
+    let slot = &REPORTS[n % MAX_REPORTS];
+    slot.seq.store(2 * n + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    let mut text = Text { buf: unsafe { &mut *slot.text.get() }, len: 0 };
+    let _ = text.write_fmt(args);
+    slot.len.store(text.len, Ordering::Relaxed);
+    slot.seq.store(2 * n + 2, Ordering::Release);
+}
+
+/// Reads report `n` as a string, if the ring still holds it.
+fn read_report(n: usize) -> Option<String> {
+    let mut buf = [0; REPORT_LEN];
+    let len = REPORTS[n % MAX_REPORTS].read(n, &mut buf)?;
+    Some(String::from(Text { buf: &mut buf, len }.as_str()))
+}
+
+/// Prints the reports made since the last call. The executor calls this
+/// between tasks, where printing cannot deadlock on a lock being checked.
+pub fn print_reports() {
+    loop {
+        let printed = PRINTED.load(Ordering::Relaxed);
+        if printed == REPORTED.load(Ordering::Relaxed) {
+            return;
+        }
+        // Wait for a report still being written, unless it was overwritten.
+        let slot = &REPORTS[printed % MAX_REPORTS];
+        if slot.seq.load(Ordering::Acquire) == 2 * printed + 1 {
+            return;
+        }
+        if PRINTED.compare_exchange(printed, printed + 1, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            continue;
+        }
+        match read_report(printed) {
+            Some(text) => println!("{}", text),
+            None => println!("lockdep: report {} was overwritten before it was printed", printed),
+        }
+    }
+}
+
+/// Every lock class seen so far.
+pub fn classes() -> Vec<ClassInfo> {
+    let in_irq = IN_IRQ.load(Ordering::Relaxed);
+    let irqs_enabled = IRQS_ENABLED.load(Ordering::Relaxed);
+    let mut classes: Vec<ClassInfo> = interrupts_disabled(|| CLASSES.lock().info.iter().flatten().copied().collect());
+    for (i, class) in classes.iter_mut().enumerate() {
+        class.in_irq = in_irq & (1 << i) != 0;
+        class.irqs_enabled = irqs_enabled & (1 << i) != 0;
+    }
+    classes
+}
+
+/// Violations reported so far that the ring still holds, oldest first.
+pub fn reports() -> Vec<String> {
+    let reported = REPORTED.load(Ordering::Relaxed);
+    (reported.saturating_sub(MAX_REPORTS)..reported).filter_map(read_report).collect()
+}
+
+/// Whether acquisitions are checked in this build.
+pub const fn enabled() -> bool {
+    cfg!(debug_assertions)
+}
+
+/// Adds the `lockdep` shell command.
+pub fn register_command() {
+    crate::task::shell::register(Command {
+        name: "lockdep",
+        help: "lock classes and lock violations",
+        run: lockdep_command,
+    });
+}
+
+fn lockdep_command(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    if !enabled() {
+        writeln!(out, "lock checking is only enabled in debug builds")?;
+        return Ok(());
+    }
+    writeln!(out, "IRQ  IRQS-ON  CLASS")?;
+    for class in classes() {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let name = match class.name {
+            "" => format!("{}", class.site),
+            name => format!("{} ({})", name, class.site),
+        };
+        writeln!(out, "{:<3}  {:<7}  {}", yes_no(class.in_irq), yes_no(class.irqs_enabled), name)?;
+    }
+    for report in reports() {
+        writeln!(out, "{}", report)?;
+    }
+    Ok(())
+}
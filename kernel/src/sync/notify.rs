@@ -0,0 +1,55 @@
+use super::WaitQueue;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Wakes tasks waiting for an event that carries no data.
+///
+/// [`notify_one`](Notify::notify_one) stores a permit when nobody waits, so
+/// the next [`notified`](Notify::notified) completes at once;
+/// [`notify_waiters`](Notify::notify_waiters) only reaches current waiters.
+/// Both may be called from interrupt handlers.
+pub struct Notify {
+    permit: AtomicBool,
+    /// Bumped by `notify_waiters`.
+    generation: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Notify {
+    #[track_caller]
+    pub const fn new() -> Self {
+        Notify {
+            permit: AtomicBool::new(false),
+            generation: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Completes on the next notification. The future counts as waiting
+    /// from when it is created, not first polled.
+    pub fn notified(&self) -> impl Future<Output = ()> + '_ {
+        let generation = self.generation.load(Ordering::Acquire);
+        self.waiters.wait_until(move || {
+            let notified = self.generation.load(Ordering::Acquire) != generation
+                || self.permit.swap(false, Ordering::AcqRel);
+            notified.then_some(())
+        })
+    }
+
+    pub fn notify_one(&self) {
+        self.permit.store(true, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_waiters(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Notify {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
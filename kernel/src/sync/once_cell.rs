@@ -0,0 +1,117 @@
+use super::WaitQueue;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const EMPTY: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+
+/// A cell written once, possibly by an async initialiser; tasks that need
+/// the value while another task initialises it wait for it.
+pub struct OnceCell<T> {
+    state: AtomicU8,
+    waiters: WaitQueue,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send> Send for OnceCell<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    #[track_caller]
+    pub const fn new() -> Self {
+        OnceCell {
+            state: AtomicU8::new(EMPTY),
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        match self.state.load(Ordering::Acquire) {
+            READY => Some(unsafe { (*self.value.get()).assume_init_ref() }),
+            _ => None,
+        }
+    }
+
+    /// Stores `value` unless the cell is set or being initialised, in which
+    /// case `value` is handed back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if !self.begin() {
+            return Err(value);
+        }
+        self.finish(value);
+        Ok(())
+    }
+
+    /// Waits until the cell has a value.
+    pub async fn wait(&self) -> &T {
+        self.waiters.wait_until(|| self.get()).await
+    }
+
+    /// Returns the value, running `init` first if the cell is empty. If
+    /// another task is already initialising, waits for it instead. When an
+    /// initialiser is dropped before completing, a waiting task runs its
+    /// own `init`.
+    pub async fn get_or_init<F, Fut>(&self, init: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut init = Some(init);
+        loop {
+            if let Some(value) = self.get() {
+                return value;
+            }
+            if self.begin() {
+                let reset = ResetOnDrop(self);
+                let value = (init.take().unwrap())().await;
+                core::mem::forget(reset);
+                self.finish(value);
+                return self.get().unwrap();
+            }
+            self.waiters
+                .wait_until(|| (self.state.load(Ordering::Acquire) != INITIALIZING).then_some(()))
+                .await;
+        }
+    }
+
+    fn begin(&self) -> bool {
+        self.state
+            .compare_exchange(EMPTY, INITIALIZING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+    }
+
+    fn finish(&self, value: T) {
+        unsafe { (*self.value.get()).write(value) };
+        self.state.store(READY, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// Empties the cell again if an initialiser is cancelled.
+struct ResetOnDrop<'a, T>(&'a OnceCell<T>);
+
+impl<T> Drop for ResetOnDrop<'_, T> {
+    fn drop(&mut self) {
+        self.0.state.store(EMPTY, Ordering::Release);
+        self.0.waiters.wake_all();
+    }
+}
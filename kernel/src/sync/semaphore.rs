@@ -0,0 +1,68 @@
+use super::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A counting semaphore whose waiters park until enough permits are free.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+/// Permits taken from a [`Semaphore`]; returned when dropped.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    count: usize,
+}
+
+impl Semaphore {
+    #[track_caller]
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1).await
+    }
+
+    pub async fn acquire_many(&self, count: usize) -> SemaphorePermit<'_> {
+        self.waiters.wait_until(|| self.try_acquire_many(count)).await
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, count: usize) -> Option<SemaphorePermit<'_>> {
+        self.permits
+            .try_update(Ordering::Acquire, Ordering::Relaxed, |permits| permits.checked_sub(count))
+            .ok()
+            .map(|_| SemaphorePermit { semaphore: self, count })
+    }
+
+    pub fn add_permits(&self, count: usize) {
+        self.permits.fetch_add(count, Ordering::Release);
+        // Waiters may want different counts, so let them all check.
+        self.waiters.wake_all();
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permits taken instead of returning them on drop.
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.semaphore.add_permits(self.count);
+        }
+    }
+}
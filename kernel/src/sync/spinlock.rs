@@ -0,0 +1,126 @@
+#[cfg(debug_assertions)]
+use super::lockdep::LockDebug;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+
+/// A spinlock that is checked by [`lockdep`](super::lockdep) in debug
+/// builds and is a plain `spin::Mutex` otherwise.
+pub struct SpinLock<T: ?Sized> {
+    #[cfg(debug_assertions)]
+    debug: LockDebug,
+    inner: spin::Mutex<T>,
+}
+
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    #[cfg(debug_assertions)]
+    debug: &'a LockDebug,
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+}
+
+impl<T> SpinLock<T> {
+    /// Creates a lock whose class is named after where it is constructed.
+    #[track_caller]
+    pub const fn new(value: T) -> Self {
+        Self::with_name(None, value, Location::caller())
+    }
+
+    /// Creates a lock that reports refer to as `name`.
+    #[track_caller]
+    pub const fn named(name: &'static str, value: T) -> Self {
+        Self::with_name(Some(name), value, Location::caller())
+    }
+
+    #[allow(unused_variables)]
+    const fn with_name(name: Option<&'static str>, value: T, site: &'static Location<'static>) -> Self {
+        SpinLock {
+            #[cfg(debug_assertions)]
+            debug: LockDebug::new(name, site),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        #[cfg(debug_assertions)]
+        {
+            let site = Location::caller();
+            self.debug.check(site, || unsafe { self.inner.force_unlock() });
+            let start = crate::time::tsc();
+            let mut reported = false;
+            let guard = loop {
+                if let Some(guard) = self.inner.try_lock() {
+                    break guard;
+                }
+                self.debug.spinning(site, start, &mut reported);
+                core::hint::spin_loop();
+            };
+            self.debug.acquired(site);
+            SpinLockGuard {
+                debug: &self.debug,
+                guard: ManuallyDrop::new(guard),
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        SpinLockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        #[cfg(debug_assertions)]
+        self.debug.acquired(Location::caller());
+        Some(SpinLockGuard {
+            #[cfg(debug_assertions)]
+            debug: &self.debug,
+            guard: ManuallyDrop::new(guard),
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Whether the current CPU holds the lock. Without lock checking this
+    /// only tells whether any CPU does.
+    pub fn is_held_by_current_cpu(&self) -> bool {
+        #[cfg(debug_assertions)]
+        return self.debug.is_held_by_current_cpu();
+        #[cfg(not(debug_assertions))]
+        return self.inner.is_locked();
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        self.debug.released();
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+    }
+}
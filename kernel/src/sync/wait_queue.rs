@@ -0,0 +1,194 @@
+use super::{IrqMutex, WakeList};
+use alloc::collections::BTreeMap;
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// A FIFO of parked tasks, the building block of the other primitives.
+///
+/// A waiter that is woken but dropped before it runs passes the wakeup on
+/// to the next waiter, so [`WaitQueue::wake_one`] is never lost. Interrupt
+/// handlers may wake waiters.
+pub struct WaitQueue {
+    waiters: IrqMutex<Waiters>,
+}
+
+struct Waiters {
+    next_key: u64,
+    /// Keys grow with every registration, so this iterates in FIFO order.
+    list: BTreeMap<u64, Waiter>,
+}
+
+struct Waiter {
+    waker: Waker,
+    notified: bool,
+}
+
+impl WaitQueue {
+    #[track_caller]
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqMutex::new(Waiters {
+                next_key: 0,
+                list: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Waits for the next [`wake_one`](Self::wake_one) or
+    /// [`wake_all`](Self::wake_all) after the first poll.
+    pub fn wait(&self) -> Wait<'_> {
+        Wait { queue: self, key: None }
+    }
+
+    /// Waits until `condition` returns `Some`, checking it first and again
+    /// after every wakeup.
+    pub async fn wait_until<T>(&self, mut condition: impl FnMut() -> Option<T>) -> T {
+        let mut wait = self.wait();
+        poll_fn(|cx| {
+            loop {
+                if let Some(value) = condition() {
+                    wait.leave();
+                    return Poll::Ready(value);
+                }
+                if !wait.register(cx.waker()) {
+                    // Registered; check again in case the wakeup we need
+                    // came just before.
+                    return match condition() {
+                        Some(value) => {
+                            wait.leave();
+                            Poll::Ready(value)
+                        }
+                        None => Poll::Pending,
+                    };
+                }
+            }
+        })
+        .await
+    }
+
+    /// Wakes the longest waiting task; returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        let waker = {
+            let mut waiters = self.waiters.lock();
+            let waiter = waiters.list.values_mut().find(|waiter| !waiter.notified);
+            waiter.map(|waiter| {
+                waiter.notified = true;
+                waiter.waker.clone()
+            })
+        };
+        waker.map(Waker::wake).is_some()
+    }
+
+    /// Wakes every waiting task and returns how many there were.
+    ///
+    /// Wakers are taken in batches and woken with the lock released, without
+    /// allocating, since interrupt handlers call this. Tasks that start
+    /// waiting meanwhile are left alone.
+    pub fn wake_all(&self) -> usize {
+        let end = self.waiters.lock().next_key;
+        let mut count = 0;
+        loop {
+            let mut list = WakeList::new();
+            let mut waiters = self.waiters.lock();
+            let pending = waiters.list.range_mut(..end).map(|(_, waiter)| waiter).filter(|waiter| !waiter.notified);
+            for waiter in pending.take(WakeList::CAPACITY) {
+                waiter.notified = true;
+                list.push(waiter.waker.clone());
+                count += 1;
+            }
+            drop(waiters);
+            let full = list.is_full();
+            list.wake_all();
+            if !full {
+                return count;
+            }
+        }
+    }
+
+    /// Number of tasks waiting and not yet woken.
+    pub fn len(&self) -> usize {
+        self.waiters.lock().list.values().filter(|waiter| !waiter.notified).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for WaitQueue {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`WaitQueue::wait`].
+pub struct Wait<'a> {
+    queue: &'a WaitQueue,
+    key: Option<u64>,
+}
+
+impl Wait<'_> {
+    /// Queues the waiter or updates its waker. Returns `true`, leaving the
+    /// queue, if it has been woken since the last call.
+    fn register(&mut self, waker: &Waker) -> bool {
+        let mut waiters = self.queue.waiters.lock();
+        if let Some(key) = self.key {
+            let waiter = waiters.list.get_mut(&key).expect("registered waiter missing");
+            if waiter.notified {
+                waiters.list.remove(&key);
+                self.key = None;
+                return true;
+            }
+            waiter.waker.clone_from(waker);
+            return false;
+        }
+
+        let key = waiters.next_key;
+        waiters.next_key += 1;
+        waiters.list.insert(
+            key,
+            Waiter {
+                waker: waker.clone(),
+                notified: false,
+            },
+        );
+        self.key = Some(key);
+        false
+    }
+
+    /// Leaves the queue, returning whether a wakeup was pending.
+    fn leave(&mut self) -> bool {
+        let Some(key) = self.key.take() else {
+            return false;
+        };
+        let waiter = self.queue.waiters.lock().list.remove(&key);
+        waiter.is_some_and(|waiter| waiter.notified)
+    }
+
+    /// Leaves the queue, handing a wakeup that was not acted on to the next
+    /// waiter.
+    fn cancel(&mut self) {
+        if self.leave() {
+            self.queue.wake_one();
+        }
+    }
+}
+
+impl Future for Wait<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match self.register(cx.waker()) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
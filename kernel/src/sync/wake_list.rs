@@ -0,0 +1,42 @@
+use core::task::Waker;
+
+/// Wakers collected under a lock, to be woken once it is released.
+///
+/// The capacity is fixed so interrupt handlers can use it without
+/// allocating; a handler with more to wake drains its work in batches.
+pub struct WakeList {
+    wakers: [Option<Waker>; WakeList::CAPACITY],
+    len: usize,
+}
+
+impl WakeList {
+    pub const CAPACITY: usize = 16;
+
+    pub const fn new() -> Self {
+        WakeList { wakers: [const { None }; WakeList::CAPACITY], len: 0 }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == Self::CAPACITY
+    }
+
+    /// Adds `waker`; the list must not be full.
+    pub fn push(&mut self, waker: Waker) {
+        self.wakers[self.len] = Some(waker);
+        self.len += 1;
+    }
+
+    /// Wakes and removes every waker in the list.
+    pub fn wake_all(&mut self) {
+        for waker in &mut self.wakers[..self.len] {
+            waker.take().unwrap().wake();
+        }
+        self.len = 0;
+    }
+}
+
+impl Default for WakeList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,31 +1,180 @@
+pub mod channel;
+pub mod coop;
 pub mod executor;
+pub mod foreground;
+mod join;
 pub mod keyboard;
 pub mod shell;
 
+pub use self::executor::block_on;
+pub use self::join::{JoinError, JoinHandle};
+
+use self::join::Join;
+use crate::sync::SpinLock;
+use crate::time;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::task::{Context, Poll};
+use core::time::Duration;
 use core::{future::Future, pin::Pin};
 
+/// Tasks spawned on an executor that have not completed yet.
+static TASKS: SpinLock<BTreeMap<TaskId, TaskInfo>> = SpinLock::named("TASKS", BTreeMap::new());
+
+/// Scheduling class of a task. Higher classes are polled first, but each
+/// gets a share of polls so lower ones are never starved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Work deferred from interrupt handlers, such as input decoding.
+    BottomHalf,
+    /// Tasks the user is waiting on, such as the shell.
+    Interactive,
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::BottomHalf, Priority::Interactive, Priority::Background];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::BottomHalf => "bottom-half",
+            Priority::Interactive => "interactive",
+            Priority::Background => "background",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub priority: Priority,
+    /// CPU the task last ran on.
+    pub cpu: usize,
+    pub polls: u64,
+    /// Polls that used up the [`coop`] budget.
+    pub budget_exhausted: u64,
+    /// TSC cycles spent polling the task.
+    poll_cycles: u64,
+    max_poll_cycles: u64,
+}
+
+impl TaskInfo {
+    pub fn poll_time(&self) -> Duration {
+        time::cycles_to_duration(self.poll_cycles)
+    }
+
+    pub fn max_poll_time(&self) -> Duration {
+        time::cycles_to_duration(self.max_poll_cycles)
+    }
+}
+
+/// Snapshot of every live task.
+pub fn tasks() -> Vec<TaskInfo> {
+    TASKS.lock().values().cloned().collect()
+}
+
+/// Spawns `future` on the current CPU and returns a handle to await its
+/// output. Idle CPUs may steal the task and run it from then on.
+pub fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_with_priority(name, Priority::Interactive, future)
+}
+
+pub fn spawn_with_priority<F>(name: &str, priority: Priority, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (join, handle) = Join::new(future);
+    executor::spawn(Task::named(name, join).with_priority(priority));
+    handle
+}
+
+/// Spawns a future that does not need to be `Send`. The task stays on the
+/// current CPU, which suits CPU-bound work with core-local state.
+pub fn spawn_local<F>(name: &str, priority: Priority, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (join, handle) = Join::new(future);
+    let mut task = Task::named(name, LocalFuture(join)).with_priority(priority);
+    task.pinned = true;
+    executor::spawn(task);
+    handle
+}
+
+/// Lets a pinned task's future live in a [`Task`].
+struct LocalFuture<F>(F);
+
+// SAFETY: pinned tasks are only polled and dropped on the CPU that spawned
+// them, so the future never actually changes threads.
+unsafe impl<F> Send for LocalFuture<F> {}
+
+impl<F: Future> Future for LocalFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        unsafe { self.map_unchecked_mut(|local| &mut local.0) }.poll(cx)
+    }
+}
+
+/// Lets the other ready tasks run before continuing.
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
 pub struct Task {
     id: TaskId,
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    name: String,
+    priority: Priority,
+    /// Never stolen by another CPU.
+    pinned: bool,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
 }
 
 impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Task {
+        Task::named("", future)
+    }
+
+    pub fn named(name: &str, future: impl Future<Output = ()> + Send + 'static) -> Task {
         Task {
             id: TaskId::new(),
+            name: String::from(name),
+            priority: Priority::Interactive,
+            pinned: false,
             future: Box::pin(future),
         }
     }
 
-    fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+    pub fn with_priority(mut self, priority: Priority) -> Task {
+        self.priority = priority;
+        self
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 use core::sync::atomic::{AtomicU64, Ordering};
 
@@ -34,4 +183,8 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
 }
@@ -0,0 +1,325 @@
+//! Async channels for wiring drivers and tasks together.
+//!
+//! Both ends can be cloned. Every value goes to exactly one receiver, and
+//! each sender and receiver registers its own waker. The channel closes when
+//! either side calls `close` or when all senders or all receivers have been
+//! dropped. After that, sends fail, and receivers drain what is left and
+//! then get `None`.
+//!
+//! [`Sender::try_send`] on a [`bounded`] channel never allocates or blocks,
+//! so interrupt handlers can call it. [`unbounded`] channels allocate when
+//! they grow, so only tasks should send on them.
+
+use super::coop;
+use crate::sync::{IrqMutex, WakeList};
+use alloc::sync::Arc;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use futures_util::stream::Stream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+/// The channel was closed; holds the value that was not sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+enum Queue<T> {
+    Bounded(ArrayQueue<T>),
+    Unbounded(SegQueue<T>),
+}
+
+impl<T> Queue<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        match self {
+            Queue::Bounded(queue) => queue.push(value),
+            Queue::Unbounded(queue) => {
+                queue.push(value);
+                Ok(())
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        match self {
+            Queue::Bounded(queue) => queue.pop(),
+            Queue::Unbounded(queue) => queue.pop(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Queue::Bounded(queue) => queue.len(),
+            Queue::Unbounded(queue) => queue.len(),
+        }
+    }
+}
+
+/// Tasks that can be parked on one side of a channel at once. A task
+/// that finds every slot taken is polled again instead of parked.
+const MAX_WAITERS: usize = WakeList::CAPACITY;
+
+/// Wakers of the tasks waiting on one side, keyed by sender or receiver id.
+///
+/// Interrupt handlers wake through this list, so it has a fixed capacity
+/// and never allocates, and it sits behind an [`IrqMutex`].
+struct Wakers(IrqMutex<[Option<(usize, Waker)>; MAX_WAITERS]>);
+
+impl Wakers {
+    const fn new() -> Self {
+        Wakers(IrqMutex::new([const { None }; MAX_WAITERS]))
+    }
+
+    /// Parks the waiter `id` until the next [`wake_all`](Self::wake_all);
+    /// if the list is full, wakes it right away so it polls again.
+    fn register(&self, id: usize, waker: &Waker) {
+        let mut wakers = self.0.lock();
+        if let Some((_, registered)) = wakers.iter_mut().flatten().find(|(other, _)| *other == id) {
+            registered.clone_from(waker);
+            return;
+        }
+        match wakers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some((id, waker.clone())),
+            None => waker.wake_by_ref(),
+        }
+    }
+
+    fn remove(&self, id: usize) {
+        let mut wakers = self.0.lock();
+        for slot in wakers.iter_mut() {
+            if slot.as_ref().is_some_and(|(other, _)| *other == id) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn wake_all(&self) {
+        let mut list = WakeList::new();
+        for slot in self.0.lock().iter_mut() {
+            if let Some((_, waker)) = slot.take() {
+                list.push(waker);
+            }
+        }
+        list.wake_all();
+    }
+}
+
+struct Shared<T> {
+    queue: Queue<T>,
+    closed: AtomicBool,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    next_id: AtomicUsize,
+    send_wakers: Wakers,
+    recv_wakers: Wakers,
+}
+
+impl<T> Shared<T> {
+    fn id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn close(&self) {
+        if !self.closed.swap(true, Ordering::AcqRel) {
+            self.send_wakers.wake_all();
+            self.recv_wakers.wake_all();
+        }
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+}
+
+/// Creates a channel that holds at most `capacity` values.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    channel(Queue::Bounded(ArrayQueue::new(capacity)))
+}
+
+/// Creates a channel with no limit on queued values.
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    channel(Queue::Unbounded(SegQueue::new()))
+}
+
+fn channel<T>(queue: Queue<T>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue,
+        closed: AtomicBool::new(false),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        next_id: AtomicUsize::new(2),
+        send_wakers: Wakers::new(),
+        recv_wakers: Wakers::new(),
+    });
+    let sender = Sender {
+        shared: shared.clone(),
+        id: 0,
+    };
+    (sender, Receiver { shared, id: 1 })
+}
+
+impl<T> Sender<T> {
+    /// Queues `value` without waiting.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(value));
+        }
+        self.shared.queue.push(value).map_err(TrySendError::Full)?;
+        self.shared.recv_wakers.wake_all();
+        Ok(())
+    }
+
+    /// Queues `value`, waiting for space while the channel is full.
+    pub async fn send(&self, value: T) -> Result<(), Closed<T>> {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let result = self.try_send(value.take().unwrap());
+            match result {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(v)) => Poll::Ready(Err(Closed(v))),
+                Err(TrySendError::Full(v)) => {
+                    self.shared.send_wakers.register(self.id, cx.waker());
+                    // A receiver may have made room before we registered.
+                    match self.try_send(v) {
+                        Ok(()) => Poll::Ready(Ok(())),
+                        Err(TrySendError::Closed(v)) => Poll::Ready(Err(Closed(v))),
+                        Err(TrySendError::Full(v)) => {
+                            value = Some(v);
+                            Poll::Pending
+                        }
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// Closes the channel for every sender and receiver.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+            id: self.shared.id(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.send_wakers.remove(self.id);
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.shared.queue.pop() {
+            Some(value) => {
+                self.shared.send_wakers.wake_all();
+                Ok(value)
+            }
+            None if self.is_closed() => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Waits for the next value; `None` once the channel is closed and empty.
+    pub async fn recv(&self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&self, cx: &mut Context) -> Poll<Option<T>> {
+        if coop::poll_proceed(cx).is_pending() {
+            return Poll::Pending;
+        }
+        match self.try_recv() {
+            Ok(value) => return Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        self.shared.recv_wakers.register(self.id, cx.waker());
+        // A sender may have queued a value before we registered.
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// Number of values waiting to be received.
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// Closes the channel for every sender and receiver; values already
+    /// queued can still be received.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            shared: self.shared.clone(),
+            id: self.shared.id(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.recv_wakers.remove(self.id);
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
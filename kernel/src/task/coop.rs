@@ -0,0 +1,44 @@
+//! Cooperative poll budget.
+//!
+//! The executor grants every poll a budget of [`BUDGET`] units. Futures that
+//! can make progress in a loop, such as [`super::channel::Receiver`], spend
+//! a unit each time they are ready, and once the budget is gone they return
+//! `Pending` after waking themselves. A task that always has input waiting
+//! therefore still yields to the executor regularly. Each CPU has its own
+//! budget for the task it is polling.
+
+use crate::smp::{self, MAX_CPUS};
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
+
+pub const BUDGET: u32 = 128;
+
+static REMAINING: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(BUDGET) }; MAX_CPUS];
+
+fn remaining() -> &'static AtomicU32 {
+    &REMAINING[smp::current_cpu()]
+}
+
+/// Refills the budget before the executor polls a task, or
+/// [`super::block_on`] its future.
+pub(super) fn reset() {
+    remaining().store(BUDGET, Ordering::Relaxed);
+}
+
+/// Whether the last poll ran out of budget.
+pub(super) fn exhausted() -> bool {
+    remaining().load(Ordering::Relaxed) == 0
+}
+
+/// Spends one unit, or wakes the task and returns `Pending` if the budget
+/// is used up.
+pub fn poll_proceed(cx: &mut Context) -> Poll<()> {
+    let budget = remaining();
+    let remaining = budget.load(Ordering::Relaxed);
+    if remaining == 0 {
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+    }
+    budget.store(remaining - 1, Ordering::Relaxed);
+    Poll::Ready(())
+}
@@ -1,103 +1,377 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc};
-use core::task::Waker;
-use crossbeam_queue::ArrayQueue;
+//! Per-CPU executors.
+//!
+//! Every CPU runs an [`Executor`] with its own ready queues. A woken task
+//! goes back to the queue of the CPU it last ran on, and a CPU that runs
+//! out of work steals ready tasks from the others; tasks spawned with
+//! [`super::spawn_local`] are never stolen. Idle CPUs halt, and whoever
+//! queues work for one sends it a wakeup IPI.
+
+mod ready;
+
+use self::ready::{Node, ReadyQueue};
+use super::{coop, Task, TaskId, TaskInfo, TASKS};
+use crate::apic::lapic::LAPIC;
+use crate::interrupts::InterruptIndex;
+use crate::smp::{self, MAX_CPUS};
+use crate::time;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+
+/// Polls each priority gets per round while lower priorities have work.
+const WEIGHTS: [u32; 3] = [32, 8, 1];
+
+static POLLS: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+static BUDGET_EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+static IDLE: AtomicU64 = AtomicU64::new(0);
+static STOLEN: AtomicU64 = AtomicU64::new(0);
+static WAKEUPS: AtomicU64 = AtomicU64::new(0);
+
+static LOCALS: [Once<Local>; MAX_CPUS] = [const { Once::new() }; MAX_CPUS];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// Polls per [`Priority`], indexed in [`Priority::ALL`] order.
+    ///
+    /// [`Priority`]: super::Priority
+    /// [`Priority::ALL`]: super::Priority::ALL
+    pub polls: [u64; 3],
+    /// Polls that used up the cooperative budget.
+    pub budget_exhausted: u64,
+    /// Times an executor halted with nothing to run.
+    pub idle: u64,
+    /// Tasks taken from another CPU's queues.
+    pub stolen: u64,
+    /// Wakeup IPIs sent to idle CPUs.
+    pub wakeups: u64,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        polls: POLLS.each_ref().map(|polls| polls.load(Ordering::Relaxed)),
+        budget_exhausted: BUDGET_EXHAUSTED.load(Ordering::Relaxed),
+        idle: IDLE.load(Ordering::Relaxed),
+        stolen: STOLEN.load(Ordering::Relaxed),
+        wakeups: WAKEUPS.load(Ordering::Relaxed),
+    }
+}
+
+/// Run queues of one CPU.
+struct Local {
+    /// One ready queue per [`super::Priority`].
+    ready: [Arc<ReadyQueue>; 3],
+    /// Held while popping, so thieves can share the single-consumer queues.
+    consumer: Mutex<()>,
+    /// Set while the CPU halts waiting for work.
+    idle: AtomicBool,
+}
+
+impl Local {
+    fn new() -> Self {
+        Local {
+            ready: [ReadyQueue::new(), ReadyQueue::new(), ReadyQueue::new()],
+            consumer: Mutex::new(()),
+            idle: AtomicBool::new(false),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ready.iter().all(|ready| ready.is_empty())
+    }
+}
+
+fn local(cpu: usize) -> &'static Local {
+    LOCALS[cpu].call_once(Local::new)
+}
+
+/// Queues `task` on the current CPU.
+pub fn spawn(task: Task) {
+    let cpu = smp::current_cpu();
+    TASKS.lock().insert(
+        task.id,
+        TaskInfo {
+            id: task.id,
+            name: task.name,
+            priority: task.priority,
+            cpu,
+            polls: 0,
+            budget_exhausted: 0,
+            poll_cycles: 0,
+            max_poll_cycles: 0,
+        },
+    );
+    let cell = Arc::new(TaskCell {
+        node: Node::new(),
+        id: task.id,
+        priority: task.priority.index(),
+        home: AtomicUsize::new(cpu),
+        pinned: task.pinned,
+        scheduled: AtomicBool::new(false),
+        future: Mutex::new(Some(task.future)),
+    });
+    cell.schedule();
+}
 
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    cpu: usize,
+    /// Polls left for each priority in the current round.
+    credits: [u32; 3],
 }
 
 impl Executor {
+    /// Creates the executor for the current CPU.
     pub fn new() -> Self {
-        Executor {
-            tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
-        }
+        let cpu = smp::current_cpu();
+        local(cpu);
+        Executor { cpu, credits: WEIGHTS }
     }
 
     pub fn run(&mut self) -> ! {
         loop {
             self.run_ready_tasks();
-            self.sleep_if_idle();
+            crate::sync::lockdep::print_reports();
+            if !self.steal() {
+                self.sleep_if_idle();
+            }
         }
     }
 
     pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
-        }
-        self.task_queue.push(task_id).expect("queue full");
+        spawn(task);
+    }
+
+    /// Picks the priority to poll next: the highest one with work and
+    /// credits left, or, once those run out, the highest one with work
+    /// after starting a new round.
+    fn next_priority(&mut self) -> Option<usize> {
+        let local = local(self.cpu);
+        let ready: [bool; 3] = core::array::from_fn(|i| !local.ready[i].is_empty());
+        let pick = (0..3).find(|&i| ready[i] && self.credits[i] > 0).or_else(|| {
+            self.credits = WEIGHTS;
+            (0..3).find(|&i| ready[i])
+        })?;
+        self.credits[pick] -= 1;
+        Some(pick)
     }
 
     fn run_ready_tasks(&mut self) {
-        use core::task::{Context, Poll};
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-        } = self;
-
-        while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue,
+        let local = local(self.cpu);
+        while let Some(priority) = self.next_priority() {
+            let task = {
+                let _consumer = local.consumer.lock();
+                unsafe { local.ready[priority].pop() }
+            };
+            // `None` means a waker is still linking itself in; come back to it.
+            if let Some(task) = task {
+                self.poll(task);
+            }
+        }
+    }
+
+    /// Takes one ready task from another CPU and polls it here.
+    fn steal(&mut self) -> bool {
+        let count = smp::cpu_count();
+        for offset in 1..count {
+            let victim = (self.cpu + offset) % count;
+            let Some(local) = LOCALS[victim].get() else {
+                continue;
+            };
+            let Some(task) = Self::steal_from(local) else {
+                continue;
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+            STOLEN.fetch_add(1, Ordering::Relaxed);
+            task.home.store(self.cpu, Ordering::Release);
+            self.poll(task);
+            return true;
+        }
+        false
+    }
+
+    fn steal_from(local: &Local) -> Option<Arc<TaskCell>> {
+        let _consumer = local.consumer.try_lock()?;
+        for ready in &local.ready {
+            let Some(task) = (unsafe { ready.pop() }) else {
+                continue;
+            };
+            if !task.pinned {
+                return Some(task);
+            }
+            // Still scheduled, so it can go straight back.
+            ready.push(task);
+        }
+        None
+    }
+
+    fn poll(&mut self, task: Arc<TaskCell>) {
+        let Some(mut future) = task.future.try_lock() else {
+            // Another CPU is still polling it after an earlier wake; retry
+            // once that poll is over.
+            let home = task.home.load(Ordering::Acquire);
+            local(home).ready[task.priority].push(task);
+            return;
+        };
+        // Clear before polling so a wake during the poll requeues it.
+        task.scheduled.store(false, Ordering::Release);
+        let Some(inner) = future.as_mut() else {
+            // Woken after it completed.
+            return;
+        };
+
+        let waker = Waker::from(task.clone());
+        let mut context = Context::from_waker(&waker);
+        coop::reset();
+        let start = time::tsc();
+        let result = inner.as_mut().poll(&mut context);
+        let cycles = time::tsc() - start;
+        let exhausted = coop::exhausted();
+
+        POLLS[task.priority].fetch_add(1, Ordering::Relaxed);
+        if exhausted {
+            BUDGET_EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+        }
+        match result {
+            Poll::Ready(()) => {
+                *future = None;
+                TASKS.lock().remove(&task.id);
+            }
+            Poll::Pending => {
+                if let Some(info) = TASKS.lock().get_mut(&task.id) {
+                    info.cpu = self.cpu;
+                    info.polls += 1;
+                    info.budget_exhausted += exhausted as u64;
+                    info.poll_cycles += cycles;
+                    info.max_poll_cycles = info.max_poll_cycles.max(cycles);
                 }
-                Poll::Pending => {}
             }
         }
     }
 
     fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts::{self, enable_and_hlt};
-
+        let local = local(self.cpu);
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
+        local.idle.store(true, Ordering::SeqCst);
+        // Pairs with the fence in `notify`: either a waker sees `idle` and
+        // sends an IPI, which stays pending until `hlt`, or we see its task.
+        fence(Ordering::SeqCst);
+        if local.is_empty() {
+            IDLE.fetch_add(1, Ordering::Relaxed);
+            interrupts::enable_and_hlt();
         } else {
             interrupts::enable();
         }
+        local.idle.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Wakes `cpu` after work was queued for it, or, if it is busy, one idle
+/// CPU that can steal the work.
+fn notify(cpu: usize, stealable: bool) {
+    fence(Ordering::SeqCst);
+    let current = smp::current_cpu();
+    let is_idle = |cpu: usize| cpu != current && LOCALS[cpu].get().is_some_and(|local| local.idle.load(Ordering::SeqCst));
+
+    let target = if is_idle(cpu) {
+        Some(cpu)
+    } else if stealable {
+        (0..smp::cpu_count()).find(|&other| is_idle(other))
+    } else {
+        None
+    };
+    if let Some(target) = target {
+        WAKEUPS.fetch_add(1, Ordering::Relaxed);
+        let apic_id = smp::apic_id(target);
+        LAPIC.lock().send_ipi(InterruptIndex::Wakeup.as_u8(), apic_id);
+    }
+}
+
+/// Runs `future` to completion outside the executor, halting the CPU while
+/// it is pending. This is for synchronous callers such as shell commands,
+/// so the future must not depend on tasks queued on the current CPU.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let signal = Arc::new(Signal {
+        cpu: smp::current_cpu(),
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(signal.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = core::pin::pin!(future);
+    loop {
+        // Each poll gets a full budget, even when the task calling us has
+        // spent its own.
+        coop::reset();
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        interrupts::disable();
+        if signal.woken.swap(false, Ordering::SeqCst) {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
     }
 }
 
-struct TaskWaker {
-    task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+/// Waker for [`block_on`].
+struct Signal {
+    cpu: usize,
+    woken: AtomicBool,
 }
 
-impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
     }
 
-    fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        if self.cpu != smp::current_cpu() {
+            WAKEUPS.fetch_add(1, Ordering::Relaxed);
+            LAPIC.lock().send_ipi(InterruptIndex::Wakeup.as_u8(), smp::apic_id(self.cpu));
+        }
     }
 }
 
-use alloc::task::Wake;
+/// A spawned task, which is also its own waker: waking links it into the
+/// ready queue of its home CPU.
+#[repr(C)]
+struct TaskCell {
+    /// Link in a ready queue; must stay the first field.
+    node: Node,
+    id: TaskId,
+    priority: usize,
+    /// CPU that last ran the task; it is queued there when woken.
+    home: AtomicUsize,
+    pinned: bool,
+    /// Set while the task is in a ready queue.
+    scheduled: AtomicBool,
+    /// `None` once the task has completed.
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+}
+
+impl TaskCell {
+    fn schedule(self: Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let home = self.home.load(Ordering::Acquire);
+        let stealable = !self.pinned;
+        local(home).ready[self.priority].push(self);
+        notify(home, stealable);
+    }
+}
 
-impl Wake for TaskWaker {
+impl Wake for TaskCell {
     fn wake(self: Arc<Self>) {
-        self.wake_task();
+        self.schedule();
     }
 
     fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task();
+        self.clone().schedule();
     }
 }
@@ -0,0 +1,124 @@
+//! Intrusive MPSC queue of tasks ready to run.
+//!
+//! Wakers link themselves into the queue through a node embedded in
+//! [`TaskCell`], so waking never allocates and never fails; interrupt
+//! handlers can wake tasks at any rate. Each task's `scheduled` flag keeps
+//! it in the queue at most once, which both coalesces duplicate wakes and
+//! makes the intrusive link safe to reuse. This is Dmitry Vyukov's
+//! non-blocking MPSC queue: producers only swap the head, and the single
+//! consumer (the owning executor or a CPU stealing from it) walks from the
+//! tail.
+
+use super::TaskCell;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+#[repr(C)]
+pub(super) struct Node {
+    next: AtomicPtr<Node>,
+}
+
+impl Node {
+    pub(super) const fn new() -> Self {
+        Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+pub(super) struct ReadyQueue {
+    /// Most recently pushed node.
+    head: AtomicPtr<Node>,
+    /// Next node to pop; only touched by the consumer.
+    tail: UnsafeCell<*mut Node>,
+    stub: Node,
+    len: AtomicUsize,
+}
+
+unsafe impl Send for ReadyQueue {}
+unsafe impl Sync for ReadyQueue {}
+
+impl ReadyQueue {
+    pub(super) fn new() -> Arc<Self> {
+        let queue = Arc::new(ReadyQueue {
+            head: AtomicPtr::new(ptr::null_mut()),
+            tail: UnsafeCell::new(ptr::null_mut()),
+            stub: Node::new(),
+            len: AtomicUsize::new(0),
+        });
+        let stub = &queue.stub as *const Node as *mut Node;
+        queue.head.store(stub, Ordering::Relaxed);
+        unsafe { *queue.tail.get() = stub };
+        queue
+    }
+
+    /// Number of queued tasks; may briefly include one still being linked.
+    pub(super) fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queues `waker`, which must not already be queued. The queue keeps a
+    /// strong reference until it is popped.
+    pub(super) fn push(&self, waker: Arc<TaskCell>) {
+        self.len.fetch_add(1, Ordering::AcqRel);
+        let node = Arc::into_raw(waker) as *mut Node;
+        self.push_node(node);
+    }
+
+    fn push_node(&self, node: *mut Node) {
+        unsafe { (*node).next.store(ptr::null_mut(), Ordering::Relaxed) };
+        let prev = self.head.swap(node, Ordering::AcqRel);
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+    }
+
+    /// Takes the oldest queued waker. Returns `None` when empty or when a
+    /// producer is midway through a push; the caller retries later.
+    ///
+    /// # Safety
+    ///
+    /// Only one consumer may pop at a time.
+    pub(super) unsafe fn pop(&self) -> Option<Arc<TaskCell>> {
+        let stub = &self.stub as *const Node as *mut Node;
+        unsafe {
+            let tail_slot = &mut *self.tail.get();
+            let mut tail = *tail_slot;
+            let mut next = (*tail).next.load(Ordering::Acquire);
+
+            if tail == stub {
+                if next.is_null() {
+                    return None;
+                }
+                *tail_slot = next;
+                tail = next;
+                next = (*next).next.load(Ordering::Acquire);
+            }
+            if !next.is_null() {
+                *tail_slot = next;
+                return Some(self.take(tail));
+            }
+            if tail != self.head.load(Ordering::Acquire) {
+                return None;
+            }
+            // `tail` is the last node; queue the stub behind it so it can
+            // be unlinked.
+            self.push_node(stub);
+            next = (*tail).next.load(Ordering::Acquire);
+            if !next.is_null() {
+                *tail_slot = next;
+                return Some(self.take(tail));
+            }
+            None
+        }
+    }
+
+    unsafe fn take(&self, node: *mut Node) -> Arc<TaskCell> {
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        unsafe { Arc::from_raw(node as *const TaskCell) }
+    }
+}
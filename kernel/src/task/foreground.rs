@@ -0,0 +1,41 @@
+//! Ctrl-C handling for the foreground task.
+
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use futures_util::task::AtomicWaker;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Signals the foreground task to stop, e.g. on Ctrl-C.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Release);
+    WAKER.wake();
+}
+
+/// Checks and clears a pending interrupt, for code that polls instead of
+/// awaiting [`interruptible`].
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::AcqRel)
+}
+
+/// Runs `future` as the foreground task, returning `None` if it was
+/// interrupted before completing. An interrupt still pending from before
+/// the call counts, so whoever starts foreground work should
+/// [`take_interrupt`] first to drop stale ones.
+pub async fn interruptible<F: Future>(future: F) -> Option<F::Output> {
+    let mut future = pin!(future);
+    core::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        WAKER.register(cx.waker());
+        if take_interrupt() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
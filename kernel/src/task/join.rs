@@ -0,0 +1,106 @@
+//! Task outputs and cancellation.
+
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted before it completed.
+    Aborted,
+}
+
+struct State<T> {
+    output: SpinLock<Option<Result<T, JoinError>>>,
+    aborted: AtomicBool,
+    /// Wakes the task so it notices an abort.
+    task_waker: AtomicWaker,
+    /// Wakes whoever awaits the [`JoinHandle`].
+    join_waker: AtomicWaker,
+}
+
+/// Awaits the output of a spawned task. Dropping the handle detaches the
+/// task, which keeps running.
+pub struct JoinHandle<T> {
+    state: Arc<State<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Stops the task before its next poll; the handle then resolves to
+    /// [`JoinError::Aborted`] unless the task already completed.
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::Release);
+        self.state.task_waker.wake();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.output.lock().is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(output) = self.state.output.lock().take() {
+            return Poll::Ready(output);
+        }
+        self.state.join_waker.register(cx.waker());
+        match self.state.output.lock().take() {
+            Some(output) => Poll::Ready(output),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// The future the executor runs for a spawned task: polls the user future
+/// and hands its output to the [`JoinHandle`].
+pub(super) struct Join<F: Future> {
+    future: Pin<Box<F>>,
+    state: Arc<State<F::Output>>,
+}
+
+impl<F: Future> Join<F> {
+    pub(super) fn new(future: F) -> (Self, JoinHandle<F::Output>) {
+        let state = Arc::new(State {
+            output: SpinLock::new(None),
+            aborted: AtomicBool::new(false),
+            task_waker: AtomicWaker::new(),
+            join_waker: AtomicWaker::new(),
+        });
+        let join = Join {
+            future: Box::pin(future),
+            state: state.clone(),
+        };
+        (join, JoinHandle { state })
+    }
+
+    fn finish(&self, output: Result<F::Output, JoinError>) {
+        *self.state.output.lock() = Some(output);
+        self.state.join_waker.wake();
+    }
+}
+
+impl<F: Future> Future for Join<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.state.aborted.load(Ordering::Acquire) {
+            self.finish(Err(JoinError::Aborted));
+            return Poll::Ready(());
+        }
+        self.state.task_waker.register(cx.waker());
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(output) => {
+                self.finish(Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -1,95 +1,261 @@
-use crate::print;
-use crate::println;
+mod layout;
+pub mod led;
+pub mod scancode;
+
+pub use self::layout::Layout;
+pub use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+use self::led::Leds;
+use self::scancode::{Decoder, Set, Stats};
+use super::shell::{Command, CommandError, CommandResult};
+use super::channel::{self, Receiver, Sender, TrySendError};
+use crate::drivers::i8042;
+use crate::sync::{IrqMutex, SpinLock};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
 use conquer_once::spin::OnceCell;
-use core::{
-    pin::Pin,
-    task::{Context, Poll},
-};
-use crossbeam_queue::ArrayQueue;
-use futures_util::stream::Stream;
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
 use futures_util::stream::StreamExt;
-use futures_util::task::AtomicWaker;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::layouts::AnyLayout;
+use pc_keyboard::{EventDecoder, HandleControl};
+
+/// Scancodes from the IRQ handler, set up by [`process_scancodes`].
+static SCANCODES: OnceCell<Sender<u8>> = OnceCell::uninit();
+/// Scancodes lost because the queue was full or not set up yet.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+/// Decoder counters, updated by [`process_scancodes`].
+static STATS: SpinLock<Stats> = SpinLock::named("keyboard::STATS", Stats {
+    overruns: 0,
+    invalid: 0,
+    unknown: 0,
+});
 
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Spots Ctrl-C in the IRQ handler, so it interrupts the foreground task
+/// even while that task holds the CPU the keyboard task would run on.
+struct CtrlCWatch {
+    scancodes: Decoder,
+    events: EventDecoder<AnyLayout>,
+    layout: Layout,
+}
+
+static CTRL_C_WATCH: IrqMutex<Option<CtrlCWatch>> = IrqMutex::new(None);
 
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake();
-        }
-    } else {
-        println!("WARNING: scancode queue uninitialized");
+    watch_ctrl_c(scancode);
+    let sent = SCANCODES
+        .try_get()
+        .is_ok_and(|sender| sender.try_send(scancode).is_ok());
+    if !sent {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
     }
 }
 
-pub struct ScancodeStream {
-    _private: (),
+fn watch_ctrl_c(scancode: u8) {
+    let mut watch = CTRL_C_WATCH.lock();
+    let Some(watch) = watch.as_mut() else {
+        return;
+    };
+    if layout() != watch.layout {
+        watch.layout = layout();
+        watch.events.change_layout(watch.layout.to_any());
+    }
+    let Some(event) = watch.scancodes.push(scancode) else {
+        return;
+    };
+    if watch.events.process_keyevent(event) == Some(DecodedKey::Unicode(CTRL_C)) {
+        super::foreground::interrupt();
+    }
 }
 
-impl ScancodeStream {
-    pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
-        ScancodeStream { _private: () }
-    }
+const SCANCODE_CAPACITY: usize = 100;
+
+const CTRL_C: char = '\u{3}';
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
 }
 
-impl Stream for ScancodeStream {
-    type Item = u8;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// Modifier state after this event.
+    pub modifiers: Modifiers,
+    /// What the key produces under the current layout; only set on press.
+    pub key: Option<DecodedKey>,
+}
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
-        let queue = SCANCODE_QUEUE
-            .try_get()
-            .expect("scancode queue not initialized");
+static SUBSCRIBERS: SpinLock<Vec<Sender<KeyEvent>>> = SpinLock::named("keyboard::SUBSCRIBERS", Vec::new());
 
-        if let Some(scancode) = queue.pop() {
-            return Poll::Ready(Some(scancode));
-        }
+/// Subscribes to every key event; events are dropped for a subscriber
+/// whose queue of `capacity` events is full.
+pub fn subscribe(capacity: usize) -> Receiver<KeyEvent> {
+    let (sender, receiver) = channel::bounded(capacity);
+    SUBSCRIBERS.lock().push(sender);
+    receiver
+}
 
-        WAKER.register(&cx.waker());
-        match queue.pop() {
-            Some(scancode) => {
-                WAKER.take();
-                Poll::Ready(Some(scancode))
-            }
-            None => Poll::Pending,
-        }
-    }
+fn publish(event: KeyEvent) {
+    SUBSCRIBERS
+        .lock()
+        .retain(|subscriber| !matches!(subscriber.try_send(event), Err(TrySendError::Closed(_))));
 }
 
-static WAKER: AtomicWaker = AtomicWaker::new();
+static LAYOUT: AtomicU8 = AtomicU8::new(0);
 
-pub async fn print_keypresses() {
-    let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(
-        ScancodeSet1::new(),
-        layouts::Us104Key,
-        HandleControl::Ignore,
-    );
+/// Switches the keyboard layout; takes effect with the next scancode.
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout.as_u8(), Ordering::Relaxed);
+}
 
-    use crate::framebuffer::writer::FRAMEBUFFER;
+pub fn layout() -> Layout {
+    Layout::from_u8(LAYOUT.load(Ordering::Relaxed))
+}
+
+/// Number of scancodes dropped before reaching the decoder.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+pub fn decoder_stats() -> Stats {
+    *STATS.lock()
+}
+
+/// Decodes scancodes into key events and tracks modifiers and lock LEDs.
+/// From then on the IRQ handler turns Ctrl-C into an interrupt of the
+/// foreground task.
+pub async fn process_scancodes() {
+    if let Some(layout) = crate::cmdline::get("keyboard.layout").and_then(Layout::from_name) {
+        set_layout(layout);
+    }
+
+    super::shell::register(Command {
+        name: "layout",
+        help: "show or switch the keyboard layout",
+        run: layout_command,
+    });
+    super::shell::register(Command {
+        name: "kbdstat",
+        help: "show keyboard decoder counters",
+        run: kbdstat_command,
+    });
+
+    let (sender, mut scancodes) = channel::bounded(SCANCODE_CAPACITY);
+    SCANCODES
+        .try_init_once(|| sender)
+        .expect("process_scancodes should only be run once");
+    let mut current_layout = layout();
+    let set = match i8042::scancode_set() {
+        2 => Set::Set2,
+        _ => Set::Set1,
+    };
+    *CTRL_C_WATCH.lock() = Some(CtrlCWatch {
+        scancodes: Decoder::new(set),
+        events: EventDecoder::new(current_layout.to_any(), HandleControl::MapLettersToUnicode),
+        layout: current_layout,
+    });
+    let mut scancode_decoder = Decoder::new(set);
+    let mut decoder = EventDecoder::new(current_layout.to_any(), HandleControl::MapLettersToUnicode);
+    let mut modifiers = Modifiers {
+        num_lock: true,
+        ..Modifiers::default()
+    };
+    // Left and right shift and control.
+    let mut pressed = [false; 4];
+    led::set(leds(&modifiers));
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(c) => {
-                        if c == '\u{8}' {
-                            FRAMEBUFFER.lock().back();
-                            print!(" ");
-                            FRAMEBUFFER.lock().back();
-                        }
-                        super::shell::add_char(c)
-                    }
-                    DecodedKey::RawKey(_key) => {
-                        print!("{:?}", key)
-                    }
-                }
+        if layout() != current_layout {
+            current_layout = layout();
+            decoder.change_layout(current_layout.to_any());
+        }
+
+        let event = scancode_decoder.push(scancode);
+        if scancode_decoder.stats() != *STATS.lock() {
+            *STATS.lock() = scancode_decoder.stats();
+        }
+        let Some(event) = event else {
+            continue;
+        };
+        let (code, state) = (event.code, event.state);
+        let key = decoder.process_keyevent(event);
+
+        let down = state != KeyState::Up;
+        let locks = leds(&modifiers);
+        match code {
+            KeyCode::LShift => pressed[0] = down,
+            KeyCode::RShift => pressed[1] = down,
+            KeyCode::LControl => pressed[2] = down,
+            KeyCode::RControl => pressed[3] = down,
+            KeyCode::LAlt => modifiers.alt = down,
+            KeyCode::RAltGr => modifiers.alt_gr = down,
+            KeyCode::CapsLock if down => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NumpadLock if down && key == Some(DecodedKey::RawKey(KeyCode::NumpadLock)) => {
+                modifiers.num_lock = !modifiers.num_lock
+            }
+            KeyCode::ScrollLock if down => modifiers.scroll_lock = !modifiers.scroll_lock,
+            _ => {}
+        }
+        modifiers.shift = pressed[0] || pressed[1];
+        modifiers.ctrl = pressed[2] || pressed[3];
+        if leds(&modifiers) != locks {
+            led::set(leds(&modifiers));
+        }
+
+        publish(KeyEvent {
+            code,
+            state,
+            modifiers,
+            key: key.filter(|_| down),
+        });
+    }
+}
+
+fn layout_command(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    match args {
+        [] => {
+            for layout in Layout::ALL {
+                let marker = if layout == self::layout() { '*' } else { ' ' };
+                writeln!(out, "{} {}", marker, layout.name())?;
             }
+            Ok(())
+        }
+        [name] => {
+            let layout = Layout::from_name(name)
+                .ok_or_else(|| CommandError::Failed(format!("unknown layout `{}`", name)))?;
+            set_layout(layout);
+            Ok(())
         }
+        _ => Err(CommandError::Usage("layout [name]")),
+    }
+}
+
+fn kbdstat_command(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    if !args.is_empty() {
+        return Err(CommandError::Usage("kbdstat"));
+    }
+    let stats = decoder_stats();
+    writeln!(out, "scancode set: {}", i8042::scancode_set())?;
+    writeln!(out, "dropped:      {}", dropped())?;
+    writeln!(out, "overruns:     {}", stats.overruns)?;
+    writeln!(out, "invalid:      {}", stats.invalid)?;
+    writeln!(out, "unknown:      {}", stats.unknown)?;
+    Ok(())
+}
+
+fn leds(modifiers: &Modifiers) -> Leds {
+    Leds {
+        scroll_lock: modifiers.scroll_lock,
+        num_lock: modifiers.num_lock,
+        caps_lock: modifiers.caps_lock,
     }
 }
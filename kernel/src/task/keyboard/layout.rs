@@ -0,0 +1,56 @@
+use pc_keyboard::layouts::{self, AnyLayout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Uk,
+    De,
+    Fr,
+    Dvorak,
+    Colemak,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 6] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::De,
+        Layout::Fr,
+        Layout::Dvorak,
+        Layout::Colemak,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::De => "de",
+            Layout::Fr => "fr",
+            Layout::Dvorak => "dvorak",
+            Layout::Colemak => "colemak",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+
+    pub(super) fn to_any(self) -> AnyLayout {
+        match self {
+            Layout::Us => AnyLayout::Us104Key(layouts::Us104Key),
+            Layout::Uk => AnyLayout::Uk105Key(layouts::Uk105Key),
+            Layout::De => AnyLayout::De105Key(layouts::De105Key),
+            Layout::Fr => AnyLayout::Azerty(layouts::Azerty),
+            Layout::Dvorak => AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+            Layout::Colemak => AnyLayout::Colemak(layouts::Colemak),
+        }
+    }
+
+    pub(super) fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub(super) fn from_u8(value: u8) -> Layout {
+        Layout::ALL[value as usize]
+    }
+}
@@ -0,0 +1,22 @@
+//! Keyboard lock LEDs.
+
+use crate::drivers::i8042;
+
+const SET_LEDS: u8 = 0xed;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+/// Sends the LED state to the keyboard. Its ACK bytes arrive as scancodes
+/// and are dropped by the decoder.
+pub fn set(leds: Leds) {
+    if !i8042::keyboard_present() {
+        return;
+    }
+    let mask = leds.scroll_lock as u8 | (leds.num_lock as u8) << 1 | (leds.caps_lock as u8) << 2;
+    let _ = i8042::write_port_1(SET_LEDS).and_then(|()| i8042::write_port_1(mask));
+}
@@ -0,0 +1,289 @@
+//! Scancode framing for sets 1 and 2.
+//!
+//! Bytes are grouped into complete sequences here and only then handed to
+//! pc-keyboard for mapping, so a corrupted or truncated sequence is
+//! detected and dropped without leaving the mapper in a half-read state.
+//! Pause (which has no break code) becomes a single
+//! [`KeyState::SingleShot`] event and the fake shifts that some keyboards
+//! wrap around extended keys are discarded. The decoder does no I/O.
+
+use pc_keyboard::{KeyCode, KeyEvent, KeyState, ScancodeSet, ScancodeSet1, ScancodeSet2};
+
+const EXTENDED: u8 = 0xe0;
+const PAUSE: u8 = 0xe1;
+/// Set 2 break prefix.
+const RELEASE: u8 = 0xf0;
+
+/// Keyboard replies to commands such as LED updates.
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const ECHO: u8 = 0xee;
+/// Key detection error or internal buffer overrun.
+const OVERRUN: [u8; 2] = [0x00, 0xff];
+
+const SET1_PAUSE: [u8; 6] = [0xe1, 0x1d, 0x45, 0xe1, 0x9d, 0xc5];
+const SET2_PAUSE: [u8; 8] = [0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77];
+
+/// Extended codes of the fake shifts around PrintScreen and the
+/// navigation cluster, make and break.
+const SET1_FAKE_SHIFTS: [u8; 4] = [0x2a, 0xaa, 0x36, 0xb6];
+const SET2_FAKE_SHIFTS: [u8; 2] = [0x12, 0x59];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Set {
+    Set1,
+    Set2,
+}
+
+/// Counters of bytes the decoder could not turn into key events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Overrun markers reported by the keyboard.
+    pub overruns: u64,
+    /// Bytes discarded while resynchronising after a bad sequence.
+    pub invalid: u64,
+    /// Well-formed sequences for keys pc-keyboard does not know.
+    pub unknown: u64,
+}
+
+pub struct Decoder {
+    set: Set,
+    /// The sequence read so far.
+    pending: [u8; 8],
+    len: usize,
+    stats: Stats,
+}
+
+impl Decoder {
+    pub const fn new(set: Set) -> Self {
+        Decoder {
+            set,
+            pending: [0; 8],
+            len: 0,
+            stats: Stats {
+                overruns: 0,
+                invalid: 0,
+                unknown: 0,
+            },
+        }
+    }
+
+    pub fn set(&self) -> Set {
+        self.set
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Feeds one byte from the keyboard, returning an event once it
+    /// completes a sequence.
+    pub fn push(&mut self, byte: u8) -> Option<KeyEvent> {
+        if matches!(byte, ACK | RESEND | ECHO) {
+            return None;
+        }
+        if OVERRUN.contains(&byte) {
+            self.stats.overruns += 1;
+            self.reset();
+            return None;
+        }
+
+        if self.len > 0 && !self.continues(byte) {
+            // The pending sequence was cut short; drop it and start over.
+            self.stats.invalid += self.len as u64;
+            self.reset();
+        }
+        self.pending[self.len] = byte;
+        self.len += 1;
+
+        if !self.is_complete() {
+            return None;
+        }
+        let event = self.finish();
+        self.reset();
+        event
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    fn sequence(&self) -> &[u8] {
+        &self.pending[..self.len]
+    }
+
+    /// Whether `byte` can extend the pending sequence.
+    fn continues(&self, byte: u8) -> bool {
+        if self.pending[0] == PAUSE {
+            let pause: &[u8] = match self.set {
+                Set::Set1 => &SET1_PAUSE,
+                Set::Set2 => &SET2_PAUSE,
+            };
+            return pause.get(self.len) == Some(&byte);
+        }
+        match (self.set, byte) {
+            (_, EXTENDED | PAUSE) => false,
+            // Only `E0` may be followed by `F0`.
+            (Set::Set2, RELEASE) => self.sequence() == [EXTENDED],
+            _ => true,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        let last = self.pending[self.len - 1];
+        match (self.set, self.pending[0]) {
+            (Set::Set1, PAUSE) => self.len == SET1_PAUSE.len(),
+            (Set::Set2, PAUSE) => self.len == SET2_PAUSE.len(),
+            (Set::Set1, _) => last != EXTENDED,
+            (Set::Set2, _) => last != EXTENDED && last != RELEASE,
+        }
+    }
+
+    fn finish(&mut self) -> Option<KeyEvent> {
+        let sequence = self.sequence();
+        if sequence[0] == PAUSE {
+            return Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::SingleShot));
+        }
+        let fake_shift = match (self.set, sequence) {
+            (Set::Set1, [EXTENDED, code]) => SET1_FAKE_SHIFTS.contains(code),
+            (Set::Set2, [EXTENDED, code] | [EXTENDED, RELEASE, code]) => {
+                SET2_FAKE_SHIFTS.contains(code)
+            }
+            _ => false,
+        };
+        if fake_shift {
+            return None;
+        }
+
+        // A complete sequence always leaves pc-keyboard's state machine back
+        // at its start, so a fresh one per sequence is equivalent.
+        let result = match self.set {
+            Set::Set1 => map(ScancodeSet1::new(), sequence),
+            Set::Set2 => map(ScancodeSet2::new(), sequence),
+        };
+        if result.is_none() {
+            self.stats.unknown += 1;
+        }
+        result
+    }
+}
+
+fn map(mut set: impl ScancodeSet, sequence: &[u8]) -> Option<KeyEvent> {
+    let mut event = None;
+    for &byte in sequence {
+        event = set.advance_state(byte).ok()?;
+    }
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<KeyEvent> {
+        bytes.iter().filter_map(|&byte| decoder.push(byte)).collect()
+    }
+
+    fn down(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyState::Down)
+    }
+
+    fn up(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyState::Up)
+    }
+
+    #[test]
+    fn set1_make_and_break() {
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(decode(&mut decoder, &[0x1e, 0x9e]), [down(KeyCode::A), up(KeyCode::A)]);
+        assert_eq!(decoder.stats(), Stats::default());
+    }
+
+    #[test]
+    fn set2_make_and_break() {
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(decode(&mut decoder, &[0x1c, 0xf0, 0x1c]), [down(KeyCode::A), up(KeyCode::A)]);
+        assert_eq!(decoder.stats(), Stats::default());
+    }
+
+    #[test]
+    fn extended_keys() {
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(decode(&mut decoder, &[0xe0, 0x48, 0xe0, 0xc8]), [down(KeyCode::ArrowUp), up(KeyCode::ArrowUp)]);
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(
+            decode(&mut decoder, &[0xe0, 0x75, 0xe0, 0xf0, 0x75]),
+            [down(KeyCode::ArrowUp), up(KeyCode::ArrowUp)]
+        );
+    }
+
+    #[test]
+    fn media_keys() {
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(decode(&mut decoder, &[0xe0, 0x22, 0xe0, 0xa2]), [down(KeyCode::Play), up(KeyCode::Play)]);
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(decode(&mut decoder, &[0xe0, 0x34, 0xe0, 0xf0, 0x34]), [down(KeyCode::Play), up(KeyCode::Play)]);
+    }
+
+    #[test]
+    fn pause_is_a_single_event() {
+        let single_shot = KeyEvent::new(KeyCode::PauseBreak, KeyState::SingleShot);
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(decode(&mut decoder, &SET1_PAUSE), [single_shot.clone()]);
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(decode(&mut decoder, &SET2_PAUSE), [single_shot]);
+        assert_eq!(decoder.stats(), Stats::default());
+    }
+
+    #[test]
+    fn print_screen_drops_fake_shifts() {
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(
+            decode(&mut decoder, &[0xe0, 0x2a, 0xe0, 0x37, 0xe0, 0xb7, 0xe0, 0xaa]),
+            [down(KeyCode::PrintScreen), up(KeyCode::PrintScreen)]
+        );
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(
+            decode(&mut decoder, &[0xe0, 0x12, 0xe0, 0x7c, 0xe0, 0xf0, 0x7c, 0xe0, 0xf0, 0x12]),
+            [down(KeyCode::PrintScreen), up(KeyCode::PrintScreen)]
+        );
+        assert_eq!(decoder.stats(), Stats::default());
+    }
+
+    #[test]
+    fn replies_are_ignored() {
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(decode(&mut decoder, &[ACK, 0x1c, RESEND, ECHO]), [down(KeyCode::A)]);
+        assert_eq!(decoder.stats(), Stats::default());
+    }
+
+    #[test]
+    fn overrun_drops_the_pending_sequence() {
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(decode(&mut decoder, &[0xe0, 0xff, 0xf0, 0x1c, 0x00]), [up(KeyCode::A)]);
+        assert_eq!(decoder.stats().overruns, 2);
+        assert_eq!(decoder.stats().invalid, 0);
+    }
+
+    #[test]
+    fn resynchronises_after_a_cut_sequence() {
+        let mut decoder = Decoder::new(Set::Set2);
+        assert_eq!(decode(&mut decoder, &[0xe0, 0xe0, 0x75]), [down(KeyCode::ArrowUp)]);
+        assert_eq!(decoder.stats().invalid, 1);
+
+        // A break prefix that is not after E0 cannot follow another one.
+        assert_eq!(decode(&mut decoder, &[0xf0, 0xf0, 0x1c]), [up(KeyCode::A)]);
+        assert_eq!(decoder.stats().invalid, 2);
+
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(decode(&mut decoder, &[0xe1, 0x1d, 0x1e, 0x9e]), [down(KeyCode::A), up(KeyCode::A)]);
+        assert_eq!(decoder.stats().invalid, 2);
+    }
+
+    #[test]
+    fn counts_unknown_keys() {
+        let mut decoder = Decoder::new(Set::Set1);
+        assert_eq!(decode(&mut decoder, &[0xe0, 0x11, 0x1e]), [down(KeyCode::A)]);
+        assert_eq!(decoder.stats().unknown, 1);
+    }
+}
@@ -0,0 +1,386 @@
+use super::command::{self, Command, CommandError, CommandResult};
+use super::{env, exec};
+use crate::framebuffer::font::{FontHandle, Psf};
+use crate::framebuffer::writer::FRAMEBUFFER;
+use crate::interrupts::InterruptIndex;
+use crate::task::{executor, Priority};
+use crate::fs::{self, FsError};
+use crate::{allocator, apic, cpu, interrupts, memory, pci, power, println, smp, task, time};
+use alloc::sync::Arc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+
+pub fn register() {
+    for command in BUILTINS {
+        command::register(*command);
+    }
+}
+
+const BUILTINS: &[Command] = &[
+    Command { name: "help", help: "list commands", run: help },
+    Command { name: "clear", help: "clear the screen", run: clear },
+    Command { name: "setfont", help: "load a PSF console font", run: setfont },
+    Command { name: "echo", help: "print the arguments", run: echo },
+    Command { name: "env", help: "list shell variables", run: env },
+    Command { name: "unset", help: "remove shell variables", run: unset },
+    Command { name: "true", help: "succeed", run: true_ },
+    Command { name: "false", help: "fail", run: false_ },
+    Command { name: "grep", help: "print input lines containing a pattern", run: grep },
+    Command { name: "wc", help: "count input lines, words and bytes", run: wc },
+    Command { name: "head", help: "print the first input lines", run: head },
+    Command { name: "source", help: "run a script file", run: source },
+    Command { name: "meminfo", help: "frame and heap usage", run: meminfo },
+    Command { name: "uptime", help: "time since boot", run: uptime },
+    Command { name: "tasks", help: "list executor tasks", run: tasks },
+    Command { name: "taskstress", help: "spawn many short-lived tasks", run: taskstress },
+    Command { name: "sched", help: "executor scheduling statistics", run: sched },
+    Command { name: "irqs", help: "interrupt counts per vector", run: irqs },
+    Command { name: "cpuinfo", help: "decode CPUID", run: cpuinfo },
+    Command { name: "acpi", help: "list ACPI tables", run: acpi },
+    Command { name: "lspci", help: "list PCI functions; -v for BARs and capabilities", run: lspci },
+    Command { name: "reboot", help: "reset the machine", run: reboot },
+    Command { name: "shutdown", help: "power off the machine", run: shutdown },
+];
+
+fn help(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let commands = command::all();
+    let width = commands.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for command in commands {
+        writeln!(out, "{:width$}  {}", command.name, command.help, width = width)?;
+    }
+    Ok(())
+}
+
+fn clear(_args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    FRAMEBUFFER.lock().clear();
+    Ok(())
+}
+
+fn setfont(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    let [path] = args else {
+        return Err(CommandError::Usage("setfont <file>"));
+    };
+    let data = command::block_on(async {
+        let mut data = Vec::new();
+        fs::File::open(path).await?.read_to_end(&mut data).await?;
+        Ok(data)
+    })?
+    .map_err(|err: FsError| CommandError::Failed(format!("{}: {}", path, err)))?;
+    let psf = Psf::parse(data).map_err(|err| CommandError::Failed(format!("{}: {}", path, err)))?;
+    let mut framebuffer = FRAMEBUFFER.lock();
+    framebuffer.set_font(FontHandle::Loaded(Arc::new(psf)));
+    framebuffer.clear();
+    Ok(())
+}
+
+fn echo(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let mut words = args.iter();
+    if let Some(first) = words.next() {
+        write!(out, "{}", first)?;
+    }
+    for word in words {
+        write!(out, " {}", word)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn env(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    for (name, value) in env::vars() {
+        writeln!(out, "{}={}", name, value)?;
+    }
+    Ok(())
+}
+
+fn unset(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    for name in args {
+        env::unset(name);
+    }
+    Ok(())
+}
+
+fn true_(_args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    Ok(())
+}
+
+fn false_(_args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    Err(CommandError::Status(1))
+}
+
+fn grep(args: &[String], input: &str, out: &mut dyn Write) -> CommandResult {
+    let [pattern] = args else {
+        return Err(CommandError::Usage("grep <pattern>"));
+    };
+    let mut found = false;
+    for line in input.lines().filter(|line| line.contains(pattern.as_str())) {
+        command::check_interrupt()?;
+        writeln!(out, "{}", line)?;
+        found = true;
+    }
+    match found {
+        true => Ok(()),
+        false => Err(CommandError::Status(1)),
+    }
+}
+
+fn wc(_args: &[String], input: &str, out: &mut dyn Write) -> CommandResult {
+    let lines = input.lines().count();
+    let words = input.split_whitespace().count();
+    writeln!(out, "{} {} {}", lines, words, input.len())?;
+    Ok(())
+}
+
+fn head(args: &[String], input: &str, out: &mut dyn Write) -> CommandResult {
+    let count = match args {
+        [] => 10,
+        [count] => count.parse().map_err(|_| CommandError::Usage("head [lines]"))?,
+        _ => return Err(CommandError::Usage("head [lines]")),
+    };
+    for line in input.lines().take(count) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn source(args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    let [path] = args else {
+        return Err(CommandError::Usage("source <file>"));
+    };
+    let script = exec::read_file(path)?;
+    match exec::run_script(&script) {
+        0 => Ok(()),
+        status => Err(CommandError::Status(status)),
+    }
+}
+
+fn meminfo(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    const KIB: usize = 1024;
+    const FRAME_SIZE: usize = 4096;
+
+    if let Some(frames) = memory::FRAME_ALLOCATOR.lock().as_ref() {
+        let used = frames.allocated_frames();
+        let total = frames.usable_frame_count();
+        writeln!(
+            out,
+            "frames: {} / {} used ({} KiB / {} KiB)",
+            used,
+            total,
+            used * FRAME_SIZE / KIB,
+            total * FRAME_SIZE / KIB
+        )?;
+    }
+    let (used, size) = allocator::heap_stats();
+    writeln!(out, "heap:   {} / {} bytes used", used, size)?;
+    Ok(())
+}
+
+fn uptime(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let uptime = time::uptime();
+    let secs = uptime.as_secs();
+    writeln!(
+        out,
+        "up {}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        uptime.subsec_millis()
+    )?;
+    Ok(())
+}
+
+fn tasks(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    writeln!(out, "ID    PRIORITY     CPU  POLLS       YIELDS  TIME(ms)    MAX(ms)   NAME")?;
+    for info in task::tasks() {
+        writeln!(
+            out,
+            "{:<4}  {:<11}  {:<3}  {:<10}  {:<6}  {:<10}  {:<8}  {}",
+            info.id.as_u64(),
+            info.priority.name(),
+            info.cpu,
+            info.polls,
+            info.budget_exhausted,
+            millis(info.poll_time()),
+            millis(info.max_poll_time()),
+            info.name
+        )?;
+    }
+    Ok(())
+}
+
+fn sched(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let stats = executor::stats();
+    for (priority, polls) in Priority::ALL.iter().zip(stats.polls) {
+        writeln!(out, "{:<11}  {} polls", priority.name(), polls)?;
+    }
+    writeln!(out, "budget exhausted: {}", stats.budget_exhausted)?;
+    writeln!(out, "idle halts:       {}", stats.idle)?;
+    writeln!(out, "stolen:           {}", stats.stolen)?;
+    writeln!(out, "wakeup IPIs:      {}", stats.wakeups)?;
+    writeln!(out, "CPUs:             {}", smp::cpu_count())?;
+    Ok(())
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{}.{:03}", duration.as_millis(), duration.as_micros() % 1000)
+}
+
+/// Times each stress task yields before finishing.
+const STRESS_YIELDS: usize = 10;
+/// Tasks `taskstress` spawns by default and at most. Every task lives on
+/// the heap until it is joined, and the heap is only 100 KiB.
+const STRESS_DEFAULT: usize = 100;
+const STRESS_MAX: usize = 200;
+
+/// Spawns `count` tasks that each wake themselves repeatedly, plus one that
+/// joins them all and reports when they are done.
+fn taskstress(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "taskstress [count]";
+    let count: usize = match args {
+        [] => STRESS_DEFAULT,
+        [count] => count.parse().map_err(|_| CommandError::Usage(USAGE))?,
+        _ => return Err(CommandError::Usage(USAGE)),
+    };
+    if count > STRESS_MAX {
+        return Err(CommandError::Failed(format!("at most {} tasks at a time", STRESS_MAX)));
+    }
+
+    let start = time::tsc();
+    let handles: Vec<_> = (0..count)
+        .map(|_| {
+            task::spawn_with_priority("stress", Priority::Background, async {
+                for _ in 0..STRESS_YIELDS {
+                    task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+    task::spawn_with_priority("stress-join", Priority::Background, async move {
+        let mut finished = 0;
+        for handle in handles {
+            if handle.await.is_ok() {
+                finished += 1;
+            }
+        }
+        let elapsed = time::cycles_to_duration(time::tsc() - start);
+        println!("taskstress: {}/{} tasks finished in {} ms", finished, count, elapsed.as_millis());
+    });
+    writeln!(out, "spawned {} tasks", count)?;
+    Ok(())
+}
+
+fn irqs(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    writeln!(out, "VECTOR  COUNT       NAME")?;
+    for (vector, count) in interrupts::counts() {
+        write!(out, "{:<6}  {:<10}  ", vector, count)?;
+        match (InterruptIndex::from_u8(vector), interrupts::vector::name(vector)) {
+            (Some(index), _) => writeln!(out, "{:?}", index)?,
+            (None, Some(name)) => writeln!(out, "{}", name)?,
+            (None, None) => writeln!(out, "-")?,
+        }
+    }
+    Ok(())
+}
+
+fn cpuinfo(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let info = cpu::info();
+    writeln!(out, "vendor:   {}", info.vendor)?;
+    if let Some(brand) = &info.brand {
+        writeln!(out, "brand:    {}", brand)?;
+    }
+    writeln!(
+        out,
+        "family:   {:#x}  model: {:#x}  stepping: {}",
+        info.family, info.model, info.stepping
+    )?;
+    writeln!(out, "logical:  {}", info.logical_cpus)?;
+    write!(out, "features:")?;
+    for feature in info.features {
+        write!(out, " {}", feature)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn acpi(_args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let tables = apic::acpi_tables()
+        .map_err(|err| CommandError::Failed(alloc::format!("{:?}", err)))?;
+    writeln!(out, "SIG   LENGTH  REV  OEM")?;
+    for header in tables.headers() {
+        let (signature, length, revision) = (header.signature, header.length, header.revision);
+        let oem = core::str::from_utf8(&header.oem_id).unwrap_or("?");
+        writeln!(out, "{}  {:<6}  {:<3}  {}", signature, length, revision, oem)?;
+    }
+    Ok(())
+}
+
+fn lspci(args: &[String], _input: &str, out: &mut dyn Write) -> CommandResult {
+    let verbose = match args {
+        [] => false,
+        [flag] if flag == "-v" => true,
+        _ => return Err(CommandError::Usage("lspci [-v]")),
+    };
+    for function in pci::functions() {
+        write!(
+            out,
+            "{}  {:04x}:{:04x}  class {:02x}{:02x}{:02x}  rev {:02x}",
+            function.address,
+            function.vendor_id,
+            function.device_id,
+            function.class,
+            function.subclass,
+            function.prog_if,
+            function.revision
+        )?;
+        match pci::driver_of(function.address) {
+            Some(driver) => writeln!(out, "  [{}]", driver)?,
+            None => writeln!(out)?,
+        }
+        if !verbose {
+            continue;
+        }
+        for (index, bar) in function.bars.iter().enumerate() {
+            match bar {
+                Some(pci::Bar::Memory { address, size, prefetchable, is_64 }) => writeln!(
+                    out,
+                    "    BAR{} memory at {:#x} ({} KiB{}{})",
+                    index,
+                    address.as_u64(),
+                    size / 1024,
+                    if *is_64 { ", 64-bit" } else { "" },
+                    if *prefetchable { ", prefetchable" } else { "" }
+                )?,
+                Some(pci::Bar::Io { port, size }) => writeln!(out, "    BAR{} I/O at {:#x} ({} bytes)", index, port, size)?,
+                None => {}
+            }
+        }
+        if function.interrupt_pin != 0 {
+            let pin = (b'A' + function.interrupt_pin - 1) as char;
+            writeln!(out, "    INT{}# line {}", pin, function.interrupt_line)?;
+        }
+        for capability in &function.capabilities {
+            writeln!(out, "    capability {:#04x} at {:#x}: {}", capability.id, capability.offset, capability.name())?;
+        }
+        for capability in &function.extended_capabilities {
+            writeln!(
+                out,
+                "    extended capability {:#06x} v{} at {:#x}: {}",
+                capability.id,
+                capability.version,
+                capability.offset,
+                capability.name()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn reboot(_args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    power::reboot()
+}
+
+fn shutdown(_args: &[String], _input: &str, _out: &mut dyn Write) -> CommandResult {
+    power::shutdown()
+}
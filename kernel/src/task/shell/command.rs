@@ -0,0 +1,108 @@
+use crate::sync::SpinLock;
+use crate::task::{self, foreground};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::future::Future;
+
+/// Exit status of a command; zero means success.
+pub type ExitStatus = i32;
+
+/// Status of a command stopped by Ctrl-C, as in other shells.
+pub const INTERRUPTED: ExitStatus = 130;
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// Bad arguments; carries the usage line.
+    Usage(&'static str),
+    Failed(String),
+    /// Failed silently with a specific status, e.g. that of a script.
+    Status(ExitStatus),
+    Output,
+    /// Stopped by Ctrl-C.
+    Interrupted,
+}
+
+impl CommandError {
+    pub fn exit_status(&self) -> ExitStatus {
+        match self {
+            CommandError::Usage(_) => 2,
+            CommandError::Failed(_) | CommandError::Output => 1,
+            CommandError::Status(status) => *status,
+            CommandError::Interrupted => INTERRUPTED,
+        }
+    }
+}
+
+impl From<fmt::Error> for CommandError {
+    fn from(_: fmt::Error) -> Self {
+        CommandError::Output
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Usage(usage) => write!(f, "usage: {}", usage),
+            CommandError::Failed(message) => write!(f, "{}", message),
+            CommandError::Status(status) => write!(f, "exit status {}", status),
+            CommandError::Output => write!(f, "failed to write output"),
+            CommandError::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
+
+/// Runs `future` to completion as the foreground task; Ctrl-C abandons it.
+pub fn block_on<F: Future>(future: F) -> Result<F::Output, CommandError> {
+    task::block_on(foreground::interruptible(future)).ok_or(CommandError::Interrupted)
+}
+
+/// Fails if Ctrl-C was pressed, for commands that loop without awaiting.
+pub fn check_interrupt() -> CommandResult {
+    match foreground::take_interrupt() {
+        true => Err(CommandError::Interrupted),
+        false => Ok(()),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// Gets the arguments after the command name and the output of the
+    /// previous command in a pipeline, empty if there is none.
+    pub run: fn(args: &[String], input: &str, out: &mut dyn Write) -> CommandResult,
+}
+
+static COMMANDS: SpinLock<BTreeMap<&'static str, Command>> = SpinLock::named("COMMANDS", BTreeMap::new());
+
+/// Adds a shell command, replacing any command with the same name.
+pub fn register(command: Command) {
+    COMMANDS.lock().insert(command.name, command);
+}
+
+pub fn get(name: &str) -> Option<Command> {
+    COMMANDS.lock().get(name).copied()
+}
+
+/// All registered commands, sorted by name.
+pub fn all() -> Vec<Command> {
+    COMMANDS.lock().values().copied().collect()
+}
+
+pub fn names() -> Vec<&'static str> {
+    COMMANDS.lock().keys().copied().collect()
+}
+
+/// Command output that goes straight to the framebuffer console.
+pub struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::print!("{}", s);
+        Ok(())
+    }
+}
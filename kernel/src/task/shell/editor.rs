@@ -0,0 +1,309 @@
+//! Readline-style line editor for the shell.
+
+use crate::framebuffer::writer::FRAMEBUFFER;
+use crate::{print, println};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+const BACKSPACE: char = '\u{8}';
+const DELETE: char = '\u{7f}';
+const CTRL_A: char = '\u{1}';
+const CTRL_C: char = '\u{3}';
+const CTRL_E: char = '\u{5}';
+const CTRL_K: char = '\u{b}';
+const CTRL_U: char = '\u{15}';
+const CTRL_W: char = '\u{17}';
+
+pub struct LineEditor {
+    prompt: &'static str,
+    buffer: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    history_capacity: usize,
+    /// Position while browsing history; `history.len()` is the line being edited.
+    history_index: usize,
+    /// The line being edited, stashed while browsing history.
+    stash: Vec<char>,
+}
+
+impl LineEditor {
+    pub fn new(prompt: &'static str, history_capacity: usize) -> Self {
+        LineEditor {
+            prompt,
+            buffer: Vec::new(),
+            cursor: 0,
+            history: VecDeque::with_capacity(history_capacity),
+            history_capacity,
+            history_index: 0,
+            stash: Vec::new(),
+        }
+    }
+
+    /// Prints the prompt for a fresh line.
+    pub fn start(&mut self) {
+        print!("{}", self.prompt);
+        show_cursor(true);
+    }
+
+    /// Feeds a key to the editor, returning the line once Enter is pressed.
+    ///
+    /// `commands` are the candidates for Tab completion of the first word.
+    pub fn handle_key(&mut self, key: DecodedKey, commands: &[&str]) -> Option<String> {
+        show_cursor(false);
+        let line = match key {
+            DecodedKey::Unicode('\n') => Some(self.submit()),
+            DecodedKey::Unicode(BACKSPACE) => {
+                if self.cursor > 0 {
+                    self.move_left(1);
+                    self.delete(1);
+                }
+                None
+            }
+            DecodedKey::Unicode(DELETE) => {
+                self.delete(1);
+                None
+            }
+            DecodedKey::Unicode('\t') => {
+                self.complete(commands);
+                None
+            }
+            DecodedKey::Unicode(CTRL_C) => {
+                self.cancel();
+                None
+            }
+            DecodedKey::Unicode(CTRL_A) | DecodedKey::RawKey(KeyCode::Home) => {
+                self.move_left(self.cursor);
+                None
+            }
+            DecodedKey::Unicode(CTRL_E) | DecodedKey::RawKey(KeyCode::End) => {
+                self.move_right(self.buffer.len() - self.cursor);
+                None
+            }
+            DecodedKey::Unicode(CTRL_K) => {
+                self.delete(self.buffer.len() - self.cursor);
+                None
+            }
+            DecodedKey::Unicode(CTRL_U) => {
+                let count = self.cursor;
+                self.move_left(count);
+                self.delete(count);
+                None
+            }
+            DecodedKey::Unicode(CTRL_W) => {
+                let count = self.cursor - self.previous_word_start();
+                self.move_left(count);
+                self.delete(count);
+                None
+            }
+            DecodedKey::Unicode(c) if !c.is_control() => {
+                self.insert(&[c]);
+                None
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => {
+                self.move_left(1);
+                None
+            }
+            DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                self.move_right(1);
+                None
+            }
+            DecodedKey::RawKey(KeyCode::ArrowUp) => {
+                self.history_step(-1);
+                None
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                self.history_step(1);
+                None
+            }
+            _ => None,
+        };
+        if line.is_none() {
+            show_cursor(true);
+        }
+        line
+    }
+
+    fn submit(&mut self) -> String {
+        println!();
+        let line: String = self.buffer.drain(..).collect();
+        self.cursor = 0;
+        self.stash.clear();
+
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && self.history.back().map(String::as_str) != Some(trimmed) {
+            if self.history.len() == self.history_capacity {
+                self.history.pop_front();
+            }
+            if self.history_capacity > 0 {
+                self.history.push_back(String::from(trimmed));
+            }
+        }
+        self.history_index = self.history.len();
+
+        line
+    }
+
+    /// Abandons the current line and starts a fresh one.
+    fn cancel(&mut self) {
+        self.move_right(self.buffer.len() - self.cursor);
+        println!("^C");
+        self.buffer.clear();
+        self.cursor = 0;
+        self.history_index = self.history.len();
+        print!("{}", self.prompt);
+    }
+
+    fn insert(&mut self, chars: &[char]) {
+        let tail: Vec<char> = self.buffer.drain(self.cursor..).collect();
+        self.buffer.extend_from_slice(chars);
+        self.buffer.extend_from_slice(&tail);
+
+        print_chars(&self.buffer[self.cursor..]);
+        self.cursor += chars.len();
+        move_back(self.buffer.len() - self.cursor);
+    }
+
+    /// Removes up to `count` chars at the cursor and redraws the tail.
+    fn delete(&mut self, count: usize) {
+        let end = (self.cursor + count).min(self.buffer.len());
+        let removed = end - self.cursor;
+        if removed == 0 {
+            return;
+        }
+        self.buffer.drain(self.cursor..end);
+
+        print_chars(&self.buffer[self.cursor..]);
+        print_blanks(removed);
+        move_back(self.buffer.len() - self.cursor + removed);
+    }
+
+    fn move_left(&mut self, count: usize) {
+        let count = count.min(self.cursor);
+        move_back(count);
+        self.cursor -= count;
+    }
+
+    fn move_right(&mut self, count: usize) {
+        let end = (self.cursor + count).min(self.buffer.len());
+        print_chars(&self.buffer[self.cursor..end]);
+        self.cursor = end;
+    }
+
+    /// Replaces the whole line, leaving the cursor at its end.
+    fn replace(&mut self, line: Vec<char>) {
+        self.move_left(self.cursor);
+        let old_len = self.buffer.len();
+        self.buffer = line;
+
+        print_chars(&self.buffer);
+        let excess = old_len.saturating_sub(self.buffer.len());
+        print_blanks(excess);
+        move_back(excess);
+        self.cursor = self.buffer.len();
+    }
+
+    fn history_step(&mut self, step: isize) {
+        let Some(index) = self.history_index.checked_add_signed(step) else {
+            return;
+        };
+        if index > self.history.len() {
+            return;
+        }
+
+        if self.history_index == self.history.len() {
+            self.stash = self.buffer.clone();
+        }
+        self.history_index = index;
+
+        let line = match self.history.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => core::mem::take(&mut self.stash),
+        };
+        self.replace(line);
+    }
+
+    fn previous_word_start(&self) -> usize {
+        let before = &self.buffer[..self.cursor];
+        let end = before.iter().rposition(|c| !c.is_whitespace()).map_or(0, |i| i + 1);
+        before[..end].iter().rposition(|c| c.is_whitespace()).map_or(0, |i| i + 1)
+    }
+
+    /// Completes the command name under the cursor, listing the candidates
+    /// if there is no unique longer prefix.
+    fn complete(&mut self, commands: &[&str]) {
+        let before = &self.buffer[..self.cursor];
+        if before.iter().any(|c| c.is_whitespace()) {
+            return;
+        }
+        let prefix: String = before.iter().collect();
+        let matches: Vec<&str> = commands
+            .iter()
+            .copied()
+            .filter(|command| command.starts_with(prefix.as_str()))
+            .collect();
+
+        match matches.as_slice() {
+            [] => {}
+            [command] => {
+                let mut rest: Vec<char> = command[prefix.len()..].chars().collect();
+                if self.buffer.get(self.cursor) != Some(&' ') {
+                    rest.push(' ');
+                }
+                self.insert(&rest);
+            }
+            [first, others @ ..] => {
+                let common = others.iter().fold(first.len(), |len, other| {
+                    first
+                        .bytes()
+                        .zip(other.bytes())
+                        .take(len)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+                if common > prefix.len() {
+                    let rest: Vec<char> = first[prefix.len()..common].chars().collect();
+                    self.insert(&rest);
+                } else {
+                    self.list(&matches);
+                }
+            }
+        }
+    }
+
+    /// Prints completion candidates and redraws the prompt and line.
+    fn list(&mut self, matches: &[&str]) {
+        self.move_right(self.buffer.len() - self.cursor);
+        println!();
+        for command in matches {
+            print!("{}  ", command);
+        }
+        println!();
+        print!("{}", self.prompt);
+        print_chars(&self.buffer);
+    }
+}
+
+fn print_chars(chars: &[char]) {
+    for c in chars {
+        print!("{}", c);
+    }
+}
+
+fn print_blanks(count: usize) {
+    for _ in 0..count {
+        print!(" ");
+    }
+}
+
+fn move_back(count: usize) {
+    let mut framebuffer = FRAMEBUFFER.lock();
+    for _ in 0..count {
+        framebuffer.back();
+    }
+}
+
+fn show_cursor(visible: bool) {
+    FRAMEBUFFER.lock().draw_cursor(visible);
+}
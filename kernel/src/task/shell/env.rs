@@ -0,0 +1,41 @@
+//! Shell variables, shared by every command.
+
+use super::command::ExitStatus;
+use crate::sync::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicI32, Ordering};
+
+static VARS: SpinLock<BTreeMap<String, String>> = SpinLock::named("VARS", BTreeMap::new());
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// Looks up a variable; `?` is the exit status of the last pipeline.
+pub fn get(name: &str) -> Option<String> {
+    match name {
+        "?" => Some(LAST_STATUS.load(Ordering::Relaxed).to_string()),
+        _ => VARS.lock().get(name).cloned(),
+    }
+}
+
+pub fn set(name: &str, value: &str) {
+    VARS.lock().insert(String::from(name), String::from(value));
+}
+
+pub fn unset(name: &str) {
+    VARS.lock().remove(name);
+}
+
+pub fn vars() -> Vec<(String, String)> {
+    VARS.lock().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+pub(super) fn set_status(status: ExitStatus) {
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
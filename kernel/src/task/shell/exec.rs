@@ -0,0 +1,152 @@
+//! Runs parsed command lines: variable expansion, `;`/`&&`/`||` lists,
+//! pipelines and output redirection.
+
+use super::command::{self, CommandError, Console, ExitStatus, INTERRUPTED};
+use super::env;
+use super::parse::{self, Connector, Pipeline, SimpleCommand, Word, WordPart};
+use crate::fs::{File, FsError, OpenOptions};
+use crate::println;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Status of a command that could not be found.
+const NOT_FOUND: ExitStatus = 127;
+/// Status of a line that failed to parse or expand.
+const SYNTAX_ERROR: ExitStatus = 2;
+
+/// Runs one command line and returns the status of its last pipeline.
+pub fn run_line(line: &str) -> ExitStatus {
+    let list = match parse::parse(line) {
+        Ok(list) => list,
+        Err(err) => {
+            println!("parse error: {:?}", err);
+            env::set_status(SYNTAX_ERROR);
+            return SYNTAX_ERROR;
+        }
+    };
+
+    let mut status = 0;
+    for (connector, pipeline) in &list {
+        let run = match connector {
+            Connector::Always => true,
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if run {
+            status = run_pipeline(pipeline);
+            env::set_status(status);
+        }
+        // Ctrl-C stops the whole line, not just the pipeline it hit.
+        if status == INTERRUPTED {
+            break;
+        }
+    }
+    status
+}
+
+/// Runs a script line by line, returning the status of the last command.
+pub fn run_script(script: &str) -> ExitStatus {
+    let mut status = 0;
+    for line in script.lines() {
+        status = run_line(line);
+        if status == INTERRUPTED {
+            break;
+        }
+    }
+    status
+}
+
+/// Each command's output becomes the next one's input; the last command
+/// writes to the console unless redirected.
+fn run_pipeline(pipeline: &Pipeline) -> ExitStatus {
+    let mut input = String::new();
+    let mut status = 0;
+
+    for (i, command) in pipeline.iter().enumerate() {
+        let is_last = i + 1 == pipeline.len();
+        let mut output = String::new();
+
+        status = if is_last && command.redirect.is_none() {
+            run_command(command, &input, &mut Console)
+        } else {
+            run_command(command, &input, &mut output)
+        };
+
+        if let Some(redirect) = &command.redirect {
+            let path = expand(&redirect.path);
+            if let Err(err) = write_file(&path, &output, redirect.append) {
+                println!("{}: {}", path, err);
+                status = err.exit_status();
+            }
+            output.clear();
+        }
+        input = output;
+        if status == INTERRUPTED {
+            break;
+        }
+    }
+
+    status
+}
+
+fn run_command(command: &SimpleCommand, input: &str, out: &mut dyn Write) -> ExitStatus {
+    let words: Vec<String> = command.words.iter().map(expand).collect();
+
+    // Leading `NAME=value` words set shell variables.
+    let assignments = words
+        .iter()
+        .take_while(|word| word.split_once('=').is_some_and(|(name, _)| env::is_valid_name(name)))
+        .count();
+    for assignment in &words[..assignments] {
+        let (name, value) = assignment.split_once('=').unwrap();
+        env::set(name, value);
+    }
+
+    let Some((name, args)) = words[assignments..].split_first() else {
+        return 0;
+    };
+    let Some(command) = command::get(name) else {
+        println!("{}: command not found", name);
+        return NOT_FOUND;
+    };
+    match (command.run)(args, input, out) {
+        Ok(()) => 0,
+        Err(CommandError::Status(status)) => status,
+        Err(err) => {
+            println!("{}: {}", name, err);
+            err.exit_status()
+        }
+    }
+}
+
+fn expand(word: &Word) -> String {
+    let mut expanded = String::new();
+    for part in word {
+        match part {
+            WordPart::Literal(literal) => expanded.push_str(literal),
+            WordPart::Var(name) => expanded.push_str(&env::get(name).unwrap_or_default()),
+        }
+    }
+    expanded
+}
+
+pub fn read_file(path: &str) -> Result<String, CommandError> {
+    let failed = |err: FsError| CommandError::Failed(format!("{}: {}", path, err));
+    let contents = command::block_on(async {
+        let mut contents = Vec::new();
+        File::open(path).await?.read_to_end(&mut contents).await?;
+        Ok(contents)
+    })?
+    .map_err(failed)?;
+    String::from_utf8(contents).map_err(|_| CommandError::Failed(format!("{}: not UTF-8 text", path)))
+}
+
+fn write_file(path: &str, contents: &str, append: bool) -> Result<(), CommandError> {
+    command::block_on(async {
+        let file = OpenOptions::new().write(true).create(true).truncate(!append).append(append).open(path).await?;
+        file.write_all(contents.as_bytes()).await
+    })?
+    .map_err(|err| CommandError::Failed(format!("{}", err)))
+}
@@ -0,0 +1,262 @@
+//! Command line grammar:
+//!
+//! ```text
+//! list     := pipeline (( ";" | "&&" | "||" | newline ) pipeline)*
+//! pipeline := command ("|" command)*
+//! command  := word+ ((">" | ">>") word)?
+//! ```
+//!
+//! Single quotes keep their contents literally; inside double quotes and
+//! unquoted text a backslash escapes the next character and `$NAME`,
+//! `${NAME}` and `$?` refer to variables. `#` at the start of a word starts
+//! a comment. Variables are expanded when a command runs, not when parsing.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::iter::Peekable;
+use core::str::Chars;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedQuote,
+    TrailingBackslash,
+    UnexpectedToken(&'static str),
+    MissingRedirectTarget,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    Literal(String),
+    Var(String),
+}
+
+pub type Word = Vec<WordPart>;
+
+#[derive(Debug)]
+pub struct Redirect {
+    pub path: Word,
+    pub append: bool,
+}
+
+#[derive(Debug)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub redirect: Option<Redirect>,
+}
+
+pub type Pipeline = Vec<SimpleCommand>;
+
+/// Decides whether a pipeline runs, based on the previous exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    Always,
+    And,
+    Or,
+}
+
+pub type List = Vec<(Connector, Pipeline)>;
+
+#[derive(Debug)]
+enum Token {
+    Word(Word),
+    Separator,
+    And,
+    Or,
+    Pipe,
+    Redirect { append: bool },
+}
+
+impl Token {
+    fn describe(&self) -> &'static str {
+        match self {
+            Token::Word(_) => "word",
+            Token::Separator => ";",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Pipe => "|",
+            Token::Redirect { append: false } => ">",
+            Token::Redirect { append: true } => ">>",
+        }
+    }
+}
+
+pub fn parse(line: &str) -> Result<List, ParseError> {
+    let mut list = List::new();
+    let mut connector = Connector::Always;
+    let mut pipeline = Pipeline::new();
+    let mut command = SimpleCommand {
+        words: Vec::new(),
+        redirect: None,
+    };
+
+    let mut tokens = tokenize(line)?.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => {
+                if command.redirect.is_some() {
+                    return Err(ParseError::UnexpectedToken("word"));
+                }
+                command.words.push(word);
+            }
+            Token::Redirect { append } => {
+                if command.words.is_empty() || command.redirect.is_some() {
+                    return Err(ParseError::UnexpectedToken(token.describe()));
+                }
+                let Some(Token::Word(path)) = tokens.next() else {
+                    return Err(ParseError::MissingRedirectTarget);
+                };
+                command.redirect = Some(Redirect { path, append });
+            }
+            Token::Pipe => {
+                if command.words.is_empty() {
+                    return Err(ParseError::UnexpectedToken("|"));
+                }
+                pipeline.push(core::mem::replace(
+                    &mut command,
+                    SimpleCommand {
+                        words: Vec::new(),
+                        redirect: None,
+                    },
+                ));
+            }
+            Token::Separator | Token::And | Token::Or => {
+                if command.words.is_empty() {
+                    // Blank commands are fine between `;`, but not around `&&`,
+                    // `||` or after `|`.
+                    if !pipeline.is_empty() || !matches!(token, Token::Separator) {
+                        return Err(ParseError::UnexpectedToken(token.describe()));
+                    }
+                } else {
+                    pipeline.push(core::mem::replace(
+                        &mut command,
+                        SimpleCommand {
+                            words: Vec::new(),
+                            redirect: None,
+                        },
+                    ));
+                    list.push((connector, core::mem::take(&mut pipeline)));
+                }
+                connector = match token {
+                    Token::And => Connector::And,
+                    Token::Or => Connector::Or,
+                    _ => Connector::Always,
+                };
+            }
+        }
+    }
+
+    if command.words.is_empty() {
+        if !pipeline.is_empty() || connector != Connector::Always {
+            return Err(ParseError::UnexpectedToken("end of line"));
+        }
+    } else {
+        pipeline.push(command);
+        list.push((connector, pipeline));
+    }
+
+    Ok(list)
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut word = Word::new();
+    let mut literal = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+
+    fn flush(literal: &mut String, word: &mut Word) {
+        if !literal.is_empty() {
+            word.push(WordPart::Literal(core::mem::take(literal)));
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        let operator = match c {
+            ';' | '\n' => Some(Token::Separator),
+            '&' if chars.next_if_eq(&'&').is_some() => Some(Token::And),
+            '&' => return Err(ParseError::UnexpectedToken("&")),
+            '|' if chars.next_if_eq(&'|').is_some() => Some(Token::Or),
+            '|' => Some(Token::Pipe),
+            '>' if chars.next_if_eq(&'>').is_some() => Some(Token::Redirect { append: true }),
+            '>' => Some(Token::Redirect { append: false }),
+            _ => None,
+        };
+        if operator.is_some() || c.is_whitespace() {
+            if in_word {
+                flush(&mut literal, &mut word);
+                tokens.push(Token::Word(core::mem::take(&mut word)));
+                in_word = false;
+            }
+            tokens.extend(operator);
+            continue;
+        }
+
+        match c {
+            '#' if !in_word => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            '\'' => loop {
+                match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                    '\'' => break,
+                    c => literal.push(c),
+                }
+            },
+            '"' => loop {
+                match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                    '"' => break,
+                    '\\' => literal.push(chars.next().ok_or(ParseError::UnterminatedQuote)?),
+                    '$' => variable(&mut chars, &mut literal, &mut word)?,
+                    c => literal.push(c),
+                }
+            },
+            '\\' => literal.push(chars.next().ok_or(ParseError::TrailingBackslash)?),
+            '$' => variable(&mut chars, &mut literal, &mut word)?,
+            c => literal.push(c),
+        }
+        in_word = true;
+    }
+    if in_word {
+        flush(&mut literal, &mut word);
+        tokens.push(Token::Word(word));
+    }
+
+    Ok(tokens)
+}
+
+/// Parses the variable reference after a `$`, which stays a literal `$` if
+/// no name follows.
+fn variable(
+    chars: &mut Peekable<Chars>,
+    literal: &mut String,
+    word: &mut Word,
+) -> Result<(), ParseError> {
+    let name = if chars.next_if_eq(&'?').is_some() {
+        String::from("?")
+    } else if chars.next_if_eq(&'{').is_some() {
+        let mut name = String::new();
+        loop {
+            match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                '}' => break,
+                c => name.push(c),
+            }
+        }
+        name
+    } else {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+            name.push(c);
+        }
+        name
+    };
+
+    if name.is_empty() {
+        literal.push('$');
+    } else {
+        if !literal.is_empty() {
+            word.push(WordPart::Literal(core::mem::take(literal)));
+        }
+        word.push(WordPart::Var(name));
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Time keeping based on the TSC, calibrated against the PIT at boot, or
+//! taken from CPUID if the PIT does not count.
+
+use crate::sync::WaitQueue;
+use core::arch::x86_64::__cpuid;
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use futures_util::future::{select, Either};
+use x86_64::instructions::port::Port;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const CALIBRATION_HZ: u64 = 100;
+/// TSC cycles after which calibration gives up on the PIT, over a second
+/// even for a TSC ten times faster than any today.
+const CALIBRATION_CUTOFF: u64 = 1 << 36;
+
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Woken on every local APIC timer tick, so waits can check a deadline.
+pub static TICK: WaitQueue = WaitQueue::new();
+
+pub fn init() -> Result<(), &'static str> {
+    BOOT_TSC.store(tsc(), Ordering::Relaxed);
+    let hz = calibrate().or_else(cpuid_tsc_hz).ok_or("PIT channel 2 does not count and CPUID has no TSC frequency")?;
+    TSC_HZ.store(hz, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Counts TSC cycles during a 10ms one-shot of PIT channel 2; `None` if it
+/// never finishes.
+fn calibrate() -> Option<u64> {
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel_2 = Port::<u8>::new(0x42);
+    let count = PIT_FREQUENCY / CALIBRATION_HZ;
+
+    unsafe {
+        // Gate channel 2 on, speaker off.
+        let value = gate.read();
+        gate.write((value & !0x02) | 0x01);
+
+        // Channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count).
+        command.write(0b1011_0000);
+        channel_2.write(count as u8);
+        channel_2.write((count >> 8) as u8);
+
+        // Pulse the gate. In mode 0 a low gate only pauses the count, which
+        // started when the count was loaded; it does not restart it.
+        let value = gate.read();
+        gate.write(value & !0x01);
+        gate.write(value | 0x01);
+
+        // Channel 2's output, bit 5, goes high at terminal count.
+        let start = tsc();
+        while gate.read() & 0x20 == 0 {
+            if tsc() - start > CALIBRATION_CUTOFF {
+                return None;
+            }
+        }
+        Some((tsc() - start) * CALIBRATION_HZ)
+    }
+}
+
+/// The TSC frequency from CPUID leaf 0x15, or the base frequency from leaf
+/// 0x16, which matches it on CPUs with an invariant TSC.
+fn cpuid_tsc_hz() -> Option<u64> {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf >= 0x15 {
+        let leaf = __cpuid(0x15);
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+        }
+    }
+    if max_leaf >= 0x16 {
+        let mhz = __cpuid(0x16).eax & 0xffff;
+        if mhz != 0 {
+            return Some(mhz as u64 * 1_000_000);
+        }
+    }
+    None
+}
+
+pub fn tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+pub fn tsc_hz() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// Converts a TSC cycle count into a [`Duration`]; zero before calibration.
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    match tsc_hz() {
+        0 => Duration::ZERO,
+        hz => Duration::from_nanos((cycles as u128 * 1_000_000_000 / hz as u128) as u64),
+    }
+}
+
+pub fn uptime() -> Duration {
+    cycles_to_duration(tsc() - BOOT_TSC.load(Ordering::Relaxed))
+}
+
+/// Runs `future` until it completes or `duration` passes; `None` if time
+/// ran out. The deadline is checked on timer ticks.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let start = tsc();
+    let expired = TICK.wait_until(|| (cycles_to_duration(tsc() - start) >= duration).then_some(()));
+    match select(pin!(future), pin!(expired)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Spins for at least `duration`.
+pub fn delay(duration: Duration) {
+    let cycles = (duration.as_nanos() * tsc_hz() as u128 / 1_000_000_000) as u64;
+    let start = tsc();
+    while tsc() - start < cycles {
+        core::hint::spin_loop();
+    }
+}
@@ -4,29 +4,92 @@ use std::process::Command;
 
 fn main() {
     let uefi_path = env!("UEFI_PATH");
+    let bios_path = env!("BIOS_PATH");
 
     let mut cmd = Command::new("qemu-system-x86_64");
     cmd.arg("-serial").arg("mon:stdio");
     cmd.arg("-device")
         .arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
 
-    let prebuilt =
-        Prebuilt::fetch(Source::LATEST, "target/ovmf").expect("failed to update prebuilt");
-
-    let code = prebuilt.get_file(Arch::X64, FileType::Code);
-    let vars = prebuilt.get_file(Arch::X64, FileType::Vars);
-
-    cmd.arg("-drive")
-        .arg(format!("format=raw,file={uefi_path}"));
-    cmd.arg("-drive").arg(format!(
-        "if=pflash,format=raw,unit=0,file={},readonly=on",
-        code.display()
-    ));
-    
-    cmd.arg("-drive").arg(format!(
-        "if=pflash,format=raw,unit=1,file={},snapshot=on",
-        vars.display()
-    ));
+    // `--bios` boots the BIOS image instead of the UEFI one. `--disk
+    // <image>` attaches a raw image as a virtio block device, `--sata
+    // <image>` as a SATA disk on an AHCI controller, `--nvme <image>` as
+    // the namespace of an NVMe controller and `--ide <image>` as the
+    // secondary master on the legacy IDE controller. `--smp <n>` sets the
+    // number of CPUs, four by default.
+    let mut args = env::args().skip(1);
+    let mut bios = false;
+    let mut cpus: u32 = 4;
+    let mut disks = 0;
+    let mut sata_ports = 0;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bios" => bios = true,
+            "--smp" => {
+                let count = args.next().expect("--smp needs a CPU count");
+                cpus = count.parse().expect("--smp needs a CPU count");
+            }
+            "--disk" => {
+                let path = args.next().expect("--disk needs an image path");
+                cmd.arg("-drive")
+                    .arg(format!("if=none,format=raw,id=disk{disks},file={path}"));
+                cmd.arg("-device")
+                    .arg(format!("virtio-blk-pci,drive=disk{disks}"));
+                disks += 1;
+            }
+            "--sata" => {
+                let path = args.next().expect("--sata needs an image path");
+                if sata_ports == 0 {
+                    cmd.arg("-device").arg("ahci,id=ahci");
+                }
+                cmd.arg("-drive")
+                    .arg(format!("if=none,format=raw,id=disk{disks},file={path}"));
+                cmd.arg("-device")
+                    .arg(format!("ide-hd,drive=disk{disks},bus=ahci.{sata_ports}"));
+                disks += 1;
+                sata_ports += 1;
+            }
+            "--nvme" => {
+                let path = args.next().expect("--nvme needs an image path");
+                cmd.arg("-drive")
+                    .arg(format!("if=none,format=raw,id=disk{disks},file={path}"));
+                cmd.arg("-device")
+                    .arg(format!("nvme,drive=disk{disks},serial=nvme{disks}"));
+                disks += 1;
+            }
+            "--ide" => {
+                let path = args.next().expect("--ide needs an image path");
+                cmd.arg("-drive")
+                    .arg(format!("if=ide,index=2,format=raw,file={path}"));
+            }
+            _ => panic!("unknown argument `{arg}`"),
+        }
+    }
+
+    cmd.arg("-smp").arg(cpus.to_string());
+
+    if bios {
+        cmd.arg("-drive")
+            .arg(format!("format=raw,file={bios_path}"));
+    } else {
+        let prebuilt =
+            Prebuilt::fetch(Source::LATEST, "target/ovmf").expect("failed to update prebuilt");
+
+        let code = prebuilt.get_file(Arch::X64, FileType::Code);
+        let vars = prebuilt.get_file(Arch::X64, FileType::Vars);
+
+        cmd.arg("-drive")
+            .arg(format!("format=raw,file={uefi_path}"));
+        cmd.arg("-drive").arg(format!(
+            "if=pflash,format=raw,unit=0,file={},readonly=on",
+            code.display()
+        ));
+
+        cmd.arg("-drive").arg(format!(
+            "if=pflash,format=raw,unit=1,file={},snapshot=on",
+            vars.display()
+        ));
+    }
 
     let mut child = cmd.spawn().expect("failed to start qemu-system-x86_64");
     let status = child.wait().expect("failed to wait on qemu");